@@ -0,0 +1,73 @@
+use crate::vector::Vector3;
+use crate::ray::Ray;
+
+/// axis aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AABB {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl AABB {
+    pub fn new(min: Vector3, max: Vector3) -> AABB {
+        AABB{min, max}
+    }
+
+    pub fn union(&self, other: &AABB) -> AABB {
+        AABB::new(Vector3::new(self.min[0].min(other.min[0]),
+                               self.min[1].min(other.min[1]),
+                               self.min[2].min(other.min[2])),
+                  Vector3::new(self.max[0].max(other.max[0]),
+                               self.max[1].max(other.max[1]),
+                               self.max[2].max(other.max[2])))
+    }
+
+    pub fn contains(&self, p: Vector3) -> bool {
+        (0..3).all(|i| self.min[i] <= p[i] && p[i] <= self.max[i])
+    }
+
+    /// returns the range of t in which the ray is inside of the box (slab method).
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for i in 0..3 {
+            if ray.direction[i] == 0.0 {
+                // parallel to the slab, possibly right on its plane
+                if ray.origin[i] < self.min[i] || self.max[i] < ray.origin[i] {
+                    return None;
+                }
+                continue;
+            }
+            let rd = 1.0 / ray.direction[i];
+            let ta = (self.min[i] - ray.origin[i]) * rd;
+            let tb = (self.max[i] - ray.origin[i]) * rd;
+            t0 = t0.max(ta.min(tb));
+            t1 = t1.min(ta.max(tb));
+            if t1 < t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aabb::*;
+
+    #[test]
+    fn hit_and_miss() {
+        let bb  = AABB::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let (t0, t1) = bb.hit(&ray, 0.0, f32::INFINITY).unwrap();
+        assert!((t0 - 4.0).abs() < 1e-6);
+        assert!((t1 - 6.0).abs() < 1e-6);
+
+        let ray = Ray::new(Vector3::new(0.0, 2.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(bb.hit(&ray, 0.0, f32::INFINITY).is_none());
+
+        // along the faces of the box
+        let ray = Ray::new(Vector3::new(1.0, -1.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(bb.hit(&ray, 0.0, f32::INFINITY).is_some());
+    }
+}
@@ -0,0 +1,106 @@
+//! normal and bump maps that perturb the shading normal.
+//!
+//! Both work in the tangent space of the collision: x along the tangent
+//! (increasing u), y along `normal x tangent` and z along the normal.
+
+use crate::vector::Vector3;
+use crate::color::Color;
+use crate::texture::Texture;
+use crate::collide::Collision;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NormalMap {
+    /// tangent-space normals encoded as `(n + 1) / 2` in RGB. `strength`
+    /// scales the tilt.
+    Normal{texture: Texture, strength: f32},
+    /// scalar heights. `scale` is the height per unit of uv.
+    Bump{height: Texture, scale: f32},
+}
+
+impl NormalMap {
+    pub fn make_normal(texture: Texture, strength: f32) -> Self {
+        NormalMap::Normal{texture, strength}
+    }
+    pub fn make_bump(height: Texture, scale: f32) -> Self {
+        NormalMap::Bump{height, scale}
+    }
+
+    /// shading normal at the collision point `p`.
+    pub fn perturb(&self, cr: &Collision, p: Vector3) -> Vector3 {
+        let n = cr.normal;
+        let t = cr.tangent;
+        let b = Vector3::cross(n, t);
+        match self {
+            NormalMap::Normal{texture, strength} => {
+                let c = texture.value(cr.uv, p);
+                let x = (2.0 * c.r() - 1.0) * strength;
+                let y = (2.0 * c.g() - 1.0) * strength;
+                let z = (2.0 * c.b() - 1.0).max(1e-3);
+                Vector3::unit(t * x + b * y + n * z)
+            }
+            NormalMap::Bump{height, scale} => {
+                const DELTA: f32 = 1e-3;
+                let (u, v) = cr.uv;
+                let h = |du: f32, dv: f32| {
+                    height.scalar((u + du, v + dv), p + t * du + b * dv)
+                };
+                let dhdu = (h(DELTA, 0.0) - h(-DELTA, 0.0)) / (2.0 * DELTA);
+                let dhdv = (h(0.0, DELTA) - h(0.0, -DELTA)) / (2.0 * DELTA);
+                Vector3::unit(n - (t * dhdu + b * dhdv) * *scale)
+            }
+        }
+    }
+
+    /// replaces the shading normal of the collision.
+    pub fn apply(&self, cr: Collision, p: Vector3) -> Collision {
+        let normal = self.perturb(&cr, p);
+        cr.with_shading(normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bump::*;
+    use crate::color::RGB;
+    use crate::ray::Ray;
+
+    fn flat() -> Collision {
+        Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.5, 0.5))
+            .with_tangent(Vector3::new(1.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn normal_map_in_tangent_space() {
+        let cr  = flat();
+        let up  = NormalMap::make_normal(Texture::from(RGB::new(0.5, 0.5, 1.0)), 1.0);
+        assert!((up.perturb(&cr, Vector3::zero()) - cr.normal).len() < 1e-5);
+
+        // tilted toward the tangent
+        let tilted = NormalMap::make_normal(Texture::from(RGB::new(1.0, 0.5, 1.0)), 1.0);
+        let n = tilted.perturb(&cr, Vector3::zero());
+        assert!((n - Vector3::new(1.0, 1.0, 0.0).unit()).len() < 1e-5);
+    }
+
+    #[test]
+    fn bump_map_slope() {
+        use crate::texture::ImageTexture;
+        // height increases with u: h = u
+        let img = ImageTexture::new(2, 1, vec![RGB::new(0.25, 0.0, 0.0), RGB::new(0.75, 0.0, 0.0)]);
+        let bump = NormalMap::make_bump(Texture::make_image(img), 0.5);
+        let n = bump.perturb(&flat(), Vector3::zero());
+        // slope 1 * 0.5 leans the normal against the tangent
+        assert!((n - Vector3::new(-0.5, 1.0, 0.0).unit()).len() < 1e-3, "{:?}", n);
+    }
+
+    #[test]
+    fn shading_normal_faces_the_ray() {
+        // grazing ray that would be below the tilted shading normal
+        let cr  = flat().with_shading(Vector3::new(1.0, 0.2, 0.0).unit());
+        let ray = Ray::new(Vector3::new(-1.0, 0.1, 0.0), Vector3::new(1.0, -0.1, 0.0));
+        let cr  = cr.facing(&ray);
+        assert!(Vector3::dot(-ray.direction, cr.normal) > 0.0);
+        assert!(Vector3::dot(cr.normal, cr.geometric) > 0.0);
+        assert!(cr.agrees(-ray.direction, Vector3::new(0.0, 1.0, 0.0)));
+        assert!(!cr.agrees(-ray.direction, Vector3::new(1.0, -0.02, 0.0)));
+    }
+}
@@ -1,11 +1,17 @@
-use crate::vector::{Vector3, pick_in_circle};
+use crate::vector::Vector3;
 use crate::world::World;
 use crate::image::Image;
+use crate::film::{Film, Tile};
+use crate::filter::Filter;
 use crate::color::RGB;
 use crate::ray::Ray;
 use crate::background::Background;
-use rand_core::SeedableRng;
-use rand::Rng;
+use crate::spectrum::Wavelengths;
+use crate::sampler::{Sampler, Pattern};
+use crate::progress::{Progress, CancelToken};
+use rand_core::RngCore;
+
+const TILE_SIZE: usize = 16;
 
 pub struct Camera {
     location:    Vector3,
@@ -19,6 +25,54 @@ pub struct Camera {
     rwidth:      f32,
     rheight:     f32,
     lens_radius: f32,
+    shutter:     (f32, f32), // open and close
+    spectral:    bool,
+    pattern:     Pattern,
+    adaptive:    Adaptive,
+    filter:      Filter,
+    seed:        u64, // of the sampler
+}
+
+/// per-pixel sample counts. Every pixel gets `min_spp` samples, and then
+/// batches of `min_spp` more until the relative error of the pixel is
+/// below `threshold` or it has `max_spp` samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adaptive {
+    pub min_spp:   usize,
+    pub max_spp:   usize,
+    pub threshold: f32,
+}
+
+impl Adaptive {
+    /// the same number of samples for all the pixels.
+    pub fn fixed(spp: usize) -> Self {
+        Adaptive{min_spp: spp, max_spp: spp, threshold: 0.0}
+    }
+
+    /// the number of samples to add to the pixel in the next batch.
+    pub fn next_batch(&self, film: &Film, x: usize, y: usize) -> usize {
+        let n = film.count(x, y);
+        if n < self.min_spp {
+            self.min_spp - n
+        } else if n < self.max_spp && film.relative_error(x, y) > self.threshold {
+            self.min_spp.max(1).min(self.max_spp - n)
+        } else {
+            0
+        }
+    }
+
+    /// the number of samples to add to the pixel in a progressive pass of
+    /// `spp` samples. The pixel gets at least `min_spp` samples, and stops
+    /// when it converges or has `max_spp` samples, which may exceed the
+    /// `max_spp` of the adaptive sampling.
+    pub fn next_pass(&self, film: &Film, x: usize, y: usize, spp: usize, max_spp: usize) -> usize {
+        let n = film.count(x, y);
+        if n >= max_spp || (n >= self.min_spp && film.relative_error(x, y) <= self.threshold) {
+            0
+        } else {
+            spp.max(1).min(max_spp - n)
+        }
+    }
 }
 
 impl Camera {
@@ -59,7 +113,13 @@ impl Camera {
                height,
                rwidth:  1.0 / width as f32,
                rheight: 1.0 / height as f32,
-               lens_radius}
+               lens_radius,
+               shutter: (0.0, 0.0),
+               spectral: false,
+               pattern:  Pattern::Sobol,
+               adaptive: Adaptive::fixed(100),
+               filter:   Filter::make_box(0.5),
+               seed:     0}
     }
 
 
@@ -94,40 +154,195 @@ impl Camera {
 //         }).collect()
 //     }
 
-    fn ray_through_lens<R: Rng>(&self, w: usize, h: usize, n: usize, rng: &mut R)
-        -> std::vec::Vec<Ray>
+    /// the dimensions are drawn in the order: pixel, lens, time and
+    /// wavelength. The path continues with the following dimensions.
+    /// returns the ray and the position in the pixel.
+    fn ray_through_lens(&self, w: usize, h: usize, sampler: &mut Sampler) -> (Ray, (f32, f32)) {
+        let (px, py) = sampler.get_2d();
+        let (lu, lv) = sampler.get_2d();
+        let (r, a)   = (lu.sqrt(), lv * 2.0 * std::f32::consts::PI);
+        let lens_offset = self.lens_radius * r * a.cos() * self.u +
+                          self.lens_radius * r * a.sin() * self.v;
+
+        let src = self.location + lens_offset;
+        let dst = self.point_at_ratio((w as f32 + px) * self.rwidth,
+                                      (h as f32 + py) * self.rheight);
+
+        let (open, close) = self.shutter;
+        let time = open + (close - open) * sampler.get_1d();
+        let lambda = sampler.get_1d();
+
+        let ray = Ray::with_time(src, dst - src, time);
+        if self.spectral {
+            (ray.with_wavelengths(Wavelengths::sample(lambda)), (px, py))
+        } else {
+            (ray, (px, py))
+        }
+    }
+
+    /// the color of the `index`-th sample of the pixel and the position
+    /// in the pixel.
+    fn sample_pixel<Bg: Background>(&self, world: &World<Bg>, sampler: &mut Sampler,
+                                    w: usize, h: usize, index: usize) -> (RGB, (f32, f32)) {
+        sampler.start_pixel(w, h, index);
+        let (ray, offset) = self.ray_through_lens(w, h, sampler);
+        let wavelengths = ray.wavelengths;
+        let color = world.color(ray, sampler, 0).0;
+        match wavelengths {
+            None    => {(color, offset)}
+            Some(w) => {(w.to_rgb(color), offset)}
+        }
+    }
+
+    /// adds a batch of samples to each pixel in the tile that has not
+    /// converged yet, unless cancelled. returns false if there was no such
+    /// pixel.
+    fn sample_tile<Bg, F>(&self, world: &World<Bg>, film: &mut Film, tile: Tile,
+                          sampler: &mut Sampler, cancel: &CancelToken, batch: F) -> bool
+    where
+        Bg: Background,
+        F:  Fn(&Film, usize, usize) -> usize
     {
-        (0..n).map(|_| {
-            let (cx, cy) = pick_in_circle(rng);
-            let lens_offset = self.lens_radius * cx * self.u +
-                              self.lens_radius * cy * self.v;
+        let mut active = false;
+        for (w, h) in tile.pixels() {
+            if cancel.is_cancelled() {
+                return false;
+            }
+            let n = film.count(w, h);
+            for index in n..n + batch(film, w, h) {
+                let (color, offset) = self.sample_pixel(world, sampler, w, h, index);
+                film.add_sample(w, h, offset, color);
+                active = true;
+            }
+        }
+        active
+    }
 
-            let src = self.location + lens_offset;
-            let dst = self.point_at_ratio(
-                (w as f32 + rng.gen_range(0.0f32, 1.0f32)) * self.rwidth,
-                (h as f32 + rng.gen_range(0.0f32, 1.0f32)) * self.rheight);
+    fn fill_tile<Bg: Background>(&self, world: &World<Bg>, film: &Film, tile: Tile,
+                                 cancel: &CancelToken) -> Film {
+        let mut sampler = Sampler::new(self.pattern, self.seed);
+        let mut film = film.tile(tile);
+        let batch = |film: &Film, w, h| self.adaptive.next_batch(film, w, h);
+        while self.sample_tile(world, &mut film, tile, &mut sampler, cancel, batch) {}
+        film
+    }
 
-            Ray::new(src, dst - src)
+    /// renders the tile into a film cut out of `film` until the pixels
+    /// converge.
+    pub fn render_tile<Bg: Background>(&self, world: &World<Bg>, film: &Film, tile: Tile) -> Film {
+        self.fill_tile(world, film, tile, &CancelToken::new())
+    }
 
-        }).collect()
+    /// adds `spp` samples to every pixel of the image that has not converged
+    /// yet nor has `max_spp` samples, and calls `report` each time a tile of
+    /// the pass is done. returns false if there was no such pixel, or if
+    /// `cancel` is cancelled, in which case the pass is left unfinished.
+    pub fn render_pass<Bg, F>(&self, world: &World<Bg>, film: &mut Film, spp: usize, max_spp: usize,
+                              cancel: &CancelToken, mut report: F) -> bool
+    where
+        Bg: Background,
+        F:  FnMut(&Progress)
+    {
+        let start = std::time::Instant::now();
+        let tiles = self.tiles();
+        let mut sampler = Sampler::new(self.pattern, self.seed);
+        let mut active  = false;
+        let batch = |film: &Film, w, h| self.adaptive.next_pass(film, w, h, spp, max_spp);
+        for (i, tile) in tiles.iter().enumerate() {
+            let mut rendered = film.tile(*tile);
+            active |= self.sample_tile(world, &mut rendered, *tile, &mut sampler, cancel, batch);
+            film.merge(&rendered, *tile);
+            if cancel.is_cancelled() {
+                return false;
+            }
+            report(&Progress{tile: *tile, tiles_done: i + 1, tiles_total: tiles.len(),
+                             elapsed: start.elapsed()});
+        }
+        active
     }
 
-    pub fn render<Bg: Background>(&self, world: World<Bg>) -> Image {
-        const N:usize = 100;
-        let mut img = Image::new(self.width, self.height);
-        let mut rng = rand_xorshift::XorShiftRng::from_rng(
-            rand_os::OsRng::new().unwrap()).unwrap();
-
-        for w in 0..self.width {
-            for h in 0..self.height {
-                let color = self.ray_through_lens(w, h, N, &mut rng).into_iter()
-                    .map(|ray| world.color(ray, &mut rng, 0).0)
-                    .fold(RGB::new(0.0, 0.0, 0.0), |l, r| l + r) / (N as f32);
-
-                *img.at_mut(w, h) = std::convert::From::from(color.sqrt());
+    pub fn adaptive(&self) -> Adaptive {
+        self.adaptive
+    }
+
+    /// the tiles that the image is rendered by.
+    pub fn tiles(&self) -> std::vec::Vec<Tile> {
+        Tile::split(self.width, self.height, TILE_SIZE)
+    }
+
+    /// seed of the sampler.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// the same camera with another seed.
+    pub fn with_seed(mut self, seed: u64) -> Camera {
+        self.seed = seed;
+        self
+    }
+
+    /// an empty film of the image with the filter of the camera.
+    pub fn film(&self) -> Film {
+        Film::new(self.width, self.height).with_filter(self.filter)
+    }
+
+    /// renders the image tile by tile.
+    #[cfg(test)]
+    pub fn render_film<Bg: Background>(&self, world: &World<Bg>) -> Film {
+        self.render_with(world, &CancelToken::new(), |_| {})
+    }
+
+    /// renders the image tile by tile, and calls `report` each time a tile
+    /// is done. To receive the reports on another thread, send them to a
+    /// channel from `report`. When `cancel` is cancelled, it stops within
+    /// a batch of samples of a pixel and returns the image rendered so far.
+    pub fn render_with<Bg, F>(&self, world: &World<Bg>, cancel: &CancelToken, mut report: F) -> Film
+    where
+        Bg: Background,
+        F:  FnMut(&Progress)
+    {
+        let start = std::time::Instant::now();
+        let tiles = self.tiles();
+        let mut film = self.film();
+        for (i, tile) in tiles.iter().enumerate() {
+            if cancel.is_cancelled() {
+                break;
             }
+            let rendered = self.fill_tile(world, &film, *tile, cancel);
+            film.merge(&rendered, *tile);
+            if cancel.is_cancelled() {
+                break;
+            }
+            report(&Progress{tile: *tile, tiles_done: i + 1, tiles_total: tiles.len(),
+                             elapsed: start.elapsed()});
+        }
+        film
+    }
+
+    pub fn render<Bg: Background>(&self, world: World<Bg>) -> Image {
+        self.render_image_with(&world, &CancelToken::new(), |_| {})
+    }
+
+    /// `render_with`, into an image. A cancelled render gives the image
+    /// rendered so far, black where no tile was done.
+    pub fn render_image_with<Bg, F>(&self, world: &World<Bg>, cancel: &CancelToken, report: F) -> Image
+    where
+        Bg: Background,
+        F:  FnMut(&Progress)
+    {
+        self.render_with(world, cancel, report).to_image()
+    }
+}
+
+/// a seed from the system, or from the clock if it has no random source.
+fn random_seed() -> u64 {
+    let mut buf = [0u8; 8];
+    match rand_os::OsRng::new().and_then(|mut rng| rng.try_fill_bytes(&mut buf)) {
+        Ok(())  => {u64::from_le_bytes(buf)}
+        Err(_)  => {
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64).unwrap_or(0)
         }
-        img
     }
 }
 
@@ -140,6 +355,13 @@ pub struct CameraBuilder {
     fd:  std::option::Option<f32>,
     w:   std::option::Option<usize>,
     h:   std::option::Option<usize>,
+    so:  std::option::Option<f32>,
+    sc:  std::option::Option<f32>,
+    sp:  bool,
+    sm:  std::option::Option<Pattern>,
+    ad:  std::option::Option<Adaptive>,
+    ft:  std::option::Option<Filter>,
+    sd:  std::option::Option<u64>,
 }
 
 impl CameraBuilder {
@@ -153,6 +375,13 @@ impl CameraBuilder {
             fd:  None,
             w:   None,
             h:   None,
+            so:  None,
+            sc:  None,
+            sp:  false,
+            sm:  None,
+            ad:  None,
+            ft:  None,
+            sd:  None,
         }
     }
 
@@ -188,17 +417,168 @@ impl CameraBuilder {
         self.h = Some(h);
         self
     }
+    /// time when the shutter opens. 0 by default.
+    pub fn shutter_open(mut self, t: f32) -> Self {
+        self.so = Some(t);
+        self
+    }
+    /// time when the shutter closes. the same as the open time by default.
+    pub fn shutter_close(mut self, t: f32) -> Self {
+        self.sc = Some(t);
+        self
+    }
+    /// traces wavelengths instead of RGB, e.g. for dispersion.
+    pub fn spectral(mut self, sp: bool) -> Self {
+        self.sp = sp;
+        self
+    }
+    /// sample pattern of the pixels. Owen-scrambled Sobol by default.
+    pub fn sampler(mut self, sm: Pattern) -> Self {
+        self.sm = Some(sm);
+        self
+    }
+    /// the same number of samples for all the pixels. 100 by default.
+    pub fn samples_per_pixel(mut self, spp: usize) -> Self {
+        self.ad = Some(Adaptive::fixed(spp));
+        self
+    }
+    /// adaptive sampling driven by the relative error of the pixels.
+    pub fn adaptive(mut self, min_spp: usize, max_spp: usize, threshold: f32) -> Self {
+        self.ad = Some(Adaptive{min_spp, max_spp: max_spp.max(min_spp), threshold});
+        self
+    }
+    /// reconstruction filter of the pixels. The box of a pixel by default.
+    pub fn filter(mut self, ft: Filter) -> Self {
+        self.ft = Some(ft);
+        self
+    }
+    /// seed of the samples. The same seed renders the same image. Random
+    /// by default.
+    pub fn seed(mut self, sd: u64) -> Self {
+        self.sd = Some(sd);
+        self
+    }
     pub fn build(self) -> Camera {
-        Camera::new(self.loc.unwrap(),
+        let open  = self.so.unwrap_or(0.0);
+        let close = self.sc.unwrap_or(open);
+        let mut camera = Camera::new(self.loc.unwrap(),
                     self.dir.unwrap(),
                     self.vup.unwrap(),
                     self.aov.unwrap(),
                     self.apa.unwrap(),
                     self.fd.unwrap(),
                     self.w.unwrap(),
-                    self.h.unwrap())
+                    self.h.unwrap());
+        camera.shutter  = (open, close);
+        camera.spectral = self.sp;
+        camera.pattern  = self.sm.unwrap_or(Pattern::Sobol);
+        camera.adaptive = self.ad.unwrap_or_else(|| Adaptive::fixed(100));
+        camera.filter   = self.ft.unwrap_or_else(|| Filter::make_box(0.5));
+        camera.seed     = self.sd.unwrap_or_else(random_seed);
+        camera
     }
 }
 
+#[cfg(test)]
+pub mod tests {
+    use crate::camera::*;
+    use crate::object::Object;
+    use crate::sphere::Sphere;
+    use crate::material::Material;
+    use crate::background::SkyBg;
+    use crate::color::Color;
+
+    pub fn small_scene() -> (CameraBuilder, World<SkyBg>) {
+        let builder = CameraBuilder::new()
+            .position(Vector3::new(0.0, 0.0, 3.0))
+            .direction(Vector3::new(0.0, 0.0, -1.0))
+            .view_up(Vector3::new(0.0, 1.0, 0.0))
+            .vertical_angle_of_view(40.0)
+            .diameter_of_apature(0.0)
+            .focus_distance(3.0)
+            .width(16)
+            .height(8);
+        let world = World::new(vec![
+            Object::make_sphere(Sphere::new(Vector3::new(0.0, 0.0, 0.0), 0.5),
+                                Material::make_diffuse(), RGB::new(0.5, 0.5, 0.5),
+                                RGB::new(0.0, 0.0, 0.0)),
+            Object::make_sphere(Sphere::new(Vector3::new(0.0, -100.5, 0.0), 100.0),
+                                Material::make_diffuse(), RGB::new(0.5, 0.5, 0.5),
+                                RGB::new(0.0, 0.0, 0.0)),
+        ], SkyBg::new());
+        (builder, world)
+    }
+
+    #[test]
+    fn adaptive_sampling_focuses_on_noise() {
+        let (builder, world) = small_scene();
+        let camera = builder.adaptive(8, 256, 0.01).seed(45).build();
+        let film   = camera.render_film(&world);
+        let counts: std::vec::Vec<usize> = (0..16).flat_map(|w| (0..8).map(move |h| (w, h)))
+            .map(|(w, h)| film.count(w, h)).collect();
+        assert!(counts.iter().all(|&n| (8..=256).contains(&n)));
+        // the sky at the top converges immediately, the sphere does not
+        assert_eq!(film.count(0, 7), 8);
+        assert!(film.count(8, 4) > 8);
+        for w in 0..16 {
+            for h in 0..8 {
+                let n = film.count(w, h);
+                assert!(n == 256 || film.relative_error(w, h) <= 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn filtered_tiles_cover_the_image() {
+        let (builder, world) = small_scene();
+        let camera = builder.samples_per_pixel(4).filter(Filter::make_mitchell(2.0)).seed(46).build();
+        let film   = camera.render_film(&world);
+        for w in 0..16 {
+            for h in 0..8 {
+                assert_eq!(film.count(w, h), 4);
+                let c = film.color(w, h);
+                assert!(c.r() > 0.0 && c.b() > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_progress_and_cancels() {
+        let (builder, world) = small_scene();
+        let camera = builder.width(48).height(32).samples_per_pixel(2).seed(5).build();
+        let total  = camera.tiles().len();
+        assert_eq!(total, 6);
 
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let film = camera.render_with(&world, &CancelToken::new(), |p| sender.send(*p).unwrap());
+        let reports: std::vec::Vec<Progress> = receiver.try_iter().collect();
+        assert_eq!(reports.len(), total);
+        assert_eq!(reports.iter().map(|p| p.tile).collect::<std::vec::Vec<_>>(), camera.tiles());
+        assert!(reports.windows(2).all(|w| w[0].percentage() < w[1].percentage()));
+        assert_eq!(reports[total - 1].percentage(), 100.0);
+        assert_eq!(reports[total - 1].eta(), Some(std::time::Duration::from_secs(0)));
+        assert_eq!(film.min_count(), 2);
 
+        // cancelled after two tiles, the rest of the image stays empty
+        let cancel = CancelToken::new();
+        let mut done = 0;
+        let partial = camera.render_with(&world, &cancel.clone(), |p| {
+            done = p.tiles_done;
+            if p.tiles_done == 2 {
+                cancel.cancel();
+            }
+        });
+        assert_eq!(done, 2);
+        for (i, tile) in camera.tiles().into_iter().enumerate() {
+            for (x, y) in tile.pixels() {
+                assert_eq!(partial.count(x, y), if i < 2 {2} else {0});
+            }
+        }
+        assert_eq!(partial.color(0, 0), film.color(0, 0));
+
+        // cancelled before it starts, the image is black
+        let image = camera.render_image_with(&world, &cancel, |_| panic!("no tile is done"));
+        assert_eq!(image, Image::new(48, 32));
+        assert_eq!(camera.render_image_with(&world, &CancelToken::new(), |_| {}), film.to_image());
+    }
+}
@@ -0,0 +1,65 @@
+//! checkpoints of a progressive render.
+//!
+//! The samples of a pixel are drawn from the seed of the camera and the
+//! index of the sample, so the seed and the film are the whole state of a
+//! render. Resuming from a checkpoint renders the same image as if the
+//! render had not been interrupted. The filter is stored as well, since
+//! the splats of two filters cannot be mixed.
+
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::error::{Error, Result};
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"RTCKPT01";
+
+pub struct Checkpoint {
+    pub seed:   u64,
+    pub passes: usize,
+    pub film:   Film,
+}
+
+impl Checkpoint {
+    /// writes into a temporary file first, so that a render killed while
+    /// writing leaves the previous checkpoint intact.
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        {
+            let file = std::fs::File::create(&tmp)?;
+            let mut writer = std::io::BufWriter::new(file);
+            writer.write_all(MAGIC)?;
+            writer.write_all(&self.seed.to_le_bytes())?;
+            writer.write_all(&(self.passes as u64).to_le_bytes())?;
+            self.film.filter().write_to(&mut writer)?;
+            self.film.write_to(&mut writer)?;
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn read<P>(path: P) -> Result<Checkpoint>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::parse("not a checkpoint file"));
+        }
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf)?;
+        let seed = u64::from_le_bytes(buf);
+        reader.read_exact(&mut buf)?;
+        let passes = u64::from_le_bytes(buf) as usize;
+        let filter = Filter::read_from(&mut reader)?;
+        let film   = Film::read_from(&mut reader, filter)?;
+        Ok(Checkpoint{seed, passes, film})
+    }
+}
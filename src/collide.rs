@@ -1,11 +1,78 @@
-use crate::vector::Vector3;
+use crate::vector::{Vector3, orthonormal_basis};
 use crate::ray::Ray;
+use crate::aabb::AABB;
 
+#[derive(Debug, Clone, Copy)]
 pub struct Collision {
     pub t: f32,
-    pub normal: Vector3,
+    pub normal: Vector3,    // shading normal, used by materials
+    pub uv: (f32, f32),     // surface coordinate for textures
+    pub tangent: Vector3,   // direction of increasing u, perpendicular to the normal
+    pub geometric: Vector3, // normal of the actual surface
+}
+
+impl Collision {
+    /// collision where the shading and the geometric normals are the same.
+    /// `normal` must be normalized.
+    pub fn new(t: f32, normal: Vector3, uv: (f32, f32)) -> Collision {
+        let tangent = orthonormal_basis(normal).0;
+        Collision{t, normal, uv, tangent, geometric: normal}
+    }
+
+    /// sets the tangent after projecting it onto the tangent plane.
+    pub fn with_tangent(mut self, tangent: Vector3) -> Collision {
+        let t = tangent - self.normal * Vector3::dot(tangent, self.normal);
+        if t.len_sq() > 1e-12 {
+            self.tangent = t.unit();
+        }
+        self
+    }
+
+    pub fn with_geometric(mut self, geometric: Vector3) -> Collision {
+        self.geometric = geometric;
+        self
+    }
+
+    /// replaces the shading normal, keeping the tangent perpendicular to it.
+    pub fn with_shading(mut self, normal: Vector3) -> Collision {
+        let tangent = self.tangent;
+        self.normal = normal;
+        self.tangent = orthonormal_basis(normal).0;
+        self.with_tangent(tangent)
+    }
+
+    /// tilts the shading normal so that the ray origin is above it, seen from
+    /// the same side as the geometric normal. Otherwise a material would
+    /// scatter the light into the wrong side.
+    pub fn facing(self, ray: &Ray) -> Collision {
+        const EPS: f32 = 0.01;
+        let wo   = -ray.direction.unit();
+        let side = if Vector3::dot(wo, self.geometric) < 0.0 {-1.0} else {1.0};
+        // the shading normal is on the same side as the geometric one
+        let normal = if Vector3::dot(self.normal, self.geometric) < 0.0 {
+            -self.normal
+        } else {
+            self.normal
+        };
+        let a = Vector3::dot(wo, normal) * side;
+        if a >= EPS {
+            return self.with_shading(normal);
+        }
+        self.with_shading(Vector3::unit(normal + wo * (side * (EPS - a))))
+    }
+
+    /// true if `wi` is reflected (or transmitted) w.r.t. both of the shading
+    /// and the geometric normals. `wo` points toward the ray origin.
+    pub fn agrees(&self, wo: Vector3, wi: Vector3) -> bool {
+        let shading   = Vector3::dot(wo, self.normal)    * Vector3::dot(wi, self.normal);
+        let geometric = Vector3::dot(wo, self.geometric) * Vector3::dot(wi, self.geometric);
+        (shading > 0.0) == (geometric > 0.0)
+    }
 }
 
 pub trait Collide {
     fn collide_within(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Collision>;
+
+    /// returns a box that contains the object while `time0 <= t <= time1`.
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB>;
 }
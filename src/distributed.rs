@@ -0,0 +1,367 @@
+//! rendering a frame with worker processes over TCP.
+//!
+//! The coordinator connects to the workers, sends each of them the scene
+//! file, and then hands out the tiles one by one. A worker loads the scene
+//! with the loader given by the application, renders the tiles it is asked
+//! for, and sends back the films of the tiles. The tiles of a worker that
+//! fails (refuses the connection, drops it or sends garbage) are handed to
+//! the other workers. A worker that does not answer within the timeout,
+//! one minute by default, is taken as failed too. A render that is
+//! cancelled stops handing out tiles, waits for the tiles being rendered,
+//! and gives the image rendered so far.
+//!
+//! The coordinator sends the seed of its camera with the scene, and the
+//! workers render with it instead of the seed in the scene file. So all
+//! the tiles are sampled as one image even if the file has no seed.
+//!
+//! messages, in little endian:
+//!  - scene: the length (u64) and the bytes of the file, and the seed (u64),
+//!  - tile: 1u8 and x0, y0, x1, y1 (u64), or 0u8 when there is no more,
+//!  - film: see `Film::write_to`.
+
+use crate::camera::Camera;
+use crate::world::World;
+use crate::film::{Film, Tile};
+use crate::filter::Filter;
+use crate::background::Background;
+use crate::error::{Error, ErrorKind, Result};
+use crate::progress::{Progress, CancelToken};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Condvar, Mutex};
+
+const TILE: u8 = 1;
+const DONE: u8 = 0;
+const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+fn protocol_error(msg: &str) -> Error {
+    Error::new(ErrorKind::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, msg)))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// serves the coordinators that connect to the listener, one at a time.
+pub fn serve<Bg, F>(listener: &TcpListener, loader: F) -> Result<()>
+where
+    Bg: Background,
+    F:  Fn(&[u8]) -> Result<(Camera, World<Bg>)>
+{
+    for stream in listener.incoming() {
+        // a coordinator that goes away is not a reason to stop
+        let _ = work(stream?, &loader);
+    }
+    Ok(())
+}
+
+/// renders the tiles a coordinator asks for until it has no more.
+pub fn work<Bg, F>(stream: TcpStream, loader: &F) -> Result<()>
+where
+    Bg: Background,
+    F:  Fn(&[u8]) -> Result<(Camera, World<Bg>)>
+{
+    let mut reader = std::io::BufReader::new(stream.try_clone()?);
+    let mut writer = std::io::BufWriter::new(stream);
+    let len = read_u64(&mut reader)? as usize;
+    let mut scene = std::vec::Vec::new();
+    (&mut reader).take(len as u64).read_to_end(&mut scene)?;
+    if scene.len() != len {
+        return Err(protocol_error("the scene file is cut off"));
+    }
+    let seed = read_u64(&mut reader)?;
+    let (camera, world) = loader(&scene)?;
+    let camera = camera.with_seed(seed);
+    let film = camera.film();
+    let bounds = film.bounds();
+
+    loop {
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        if tag[0] == DONE {
+            return Ok(());
+        }
+        let mut t = [0usize; 4];
+        for v in t.iter_mut() {
+            *v = read_u64(&mut reader)? as usize;
+        }
+        let tile = Tile::new(t[0], t[1], t[2], t[3]);
+        if tag[0] != TILE || tile.x0 >= tile.x1 || tile.y0 >= tile.y1 ||
+           !bounds.contains(tile.x0, tile.y0) || !bounds.contains(tile.x1 - 1, tile.y1 - 1) {
+            return Err(protocol_error("invalid tile request"));
+        }
+        camera.render_tile(&world, &film, tile).write_to(&mut writer)?;
+        writer.flush()?;
+    }
+}
+
+struct Queue {
+    pending:     std::vec::Vec<Tile>,
+    outstanding: usize, // tiles that are not merged yet
+}
+
+/// the image, and where the merged tiles are reported.
+struct Merged<F> {
+    film:   Film,
+    done:   usize,
+    total:  usize,
+    start:  std::time::Instant,
+    report: F,
+}
+
+impl<F: FnMut(&Progress)> Merged<F> {
+    fn merge(&mut self, rendered: &Film, tile: Tile) {
+        self.film.merge(rendered, tile);
+        self.done += 1;
+        (self.report)(&Progress{tile, tiles_done: self.done, tiles_total: self.total,
+                                elapsed: self.start.elapsed()});
+    }
+}
+
+pub struct Coordinator {
+    scene:   std::vec::Vec<u8>,
+    workers: std::vec::Vec<SocketAddr>,
+    timeout: std::time::Duration,
+}
+
+impl Coordinator {
+    pub fn new(scene: std::vec::Vec<u8>, workers: std::vec::Vec<SocketAddr>) -> Self {
+        Coordinator{scene, workers, timeout: DEFAULT_TIMEOUT}
+    }
+
+    /// a worker that does not answer within `seconds` is taken as failed.
+    /// It should be longer than a tile takes to render.
+    pub fn with_timeout(mut self, seconds: f32) -> Self {
+        self.timeout = std::time::Duration::from_secs_f32(seconds.max(1e-3));
+        self
+    }
+
+    /// renders the image of the camera, which should be the one in the
+    /// scene file, with the seed of the camera. fails if all the workers
+    /// fail.
+    pub fn render(&self, camera: &Camera) -> Result<Film> {
+        self.render_with(camera, &CancelToken::new(), |_| {})
+    }
+
+    /// `render`, and calls `report` each time a tile is merged. When
+    /// `cancel` is cancelled, it gives the image rendered so far.
+    pub fn render_with<F>(&self, camera: &Camera, cancel: &CancelToken, report: F) -> Result<Film>
+    where
+        F: FnMut(&Progress) + Send
+    {
+        let mut tiles = camera.tiles();
+        let total = tiles.len();
+        tiles.reverse(); // hands them out from the first one
+        let queue  = Mutex::new(Queue{outstanding: total, pending: tiles});
+        let ready  = Condvar::new();
+        let merged = Mutex::new(Merged{film: camera.film(), done: 0, total,
+                                       start: std::time::Instant::now(), report});
+        std::thread::scope(|s| {
+            for addr in self.workers.iter() {
+                let (queue, ready, merged) = (&queue, &ready, &merged);
+                s.spawn(move || self.drive(*addr, camera.seed(), queue, ready, merged, cancel));
+            }
+        });
+        if queue.into_inner().unwrap().outstanding != 0 && !cancel.is_cancelled() {
+            return Err(Error::new(ErrorKind::IoError(std::io::Error::new(
+                std::io::ErrorKind::Other, "all the workers failed"))));
+        }
+        Ok(merged.into_inner().unwrap().film)
+    }
+
+    /// hands out the tiles to the worker until there is no more or the
+    /// render is cancelled. A tile that the worker failed is put back.
+    fn drive<F>(&self, addr: SocketAddr, seed: u64, queue: &Mutex<Queue>, ready: &Condvar,
+                merged: &Mutex<Merged<F>>, cancel: &CancelToken)
+    where
+        F: FnMut(&Progress)
+    {
+        let mut stream = match self.connect(addr, seed) {
+            Ok(stream) => {stream}
+            Err(_)     => {return;}
+        };
+        let filter = merged.lock().unwrap().film.filter();
+        loop {
+            let tile = {
+                let mut q = queue.lock().unwrap();
+                loop {
+                    if cancel.is_cancelled() || q.outstanding == 0 {
+                        let _ = stream.write_all(&[DONE]);
+                        return;
+                    }
+                    if let Some(tile) = q.pending.pop() {
+                        break tile;
+                    }
+                    q = ready.wait(q).unwrap();
+                }
+            };
+            let expected = merged.lock().unwrap().film.tile(tile).bounds();
+            match request(&mut stream, tile, filter) {
+                Ok(rendered) if rendered.bounds() == expected => {
+                    merged.lock().unwrap().merge(&rendered, tile);
+                    queue.lock().unwrap().outstanding -= 1;
+                    ready.notify_all();
+                }
+                _ => {
+                    queue.lock().unwrap().pending.push(tile);
+                    ready.notify_all();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn connect(&self, addr: SocketAddr, seed: u64) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.set_nodelay(true)?;
+        stream.write_all(&(self.scene.len() as u64).to_le_bytes())?;
+        stream.write_all(&self.scene)?;
+        stream.write_all(&seed.to_le_bytes())?;
+        Ok(stream)
+    }
+}
+
+fn request(stream: &mut TcpStream, tile: Tile, filter: Filter) -> Result<Film> {
+    let mut msg = vec![TILE];
+    for v in [tile.x0, tile.y0, tile.x1, tile.y1].iter() {
+        msg.extend_from_slice(&(*v as u64).to_le_bytes());
+    }
+    stream.write_all(&msg)?;
+    Film::read_from(&mut std::io::BufReader::new(&*stream), filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::distributed::*;
+    use crate::camera::tests::small_scene;
+    use crate::background::SkyBg;
+
+    /// the scene file is just the seed, or `-` for a random one.
+    fn load(scene: &[u8]) -> Result<(Camera, World<SkyBg>)> {
+        let scene = std::str::from_utf8(scene).map_err(|_| protocol_error("not utf-8"))?.trim();
+        let (builder, world) = small_scene();
+        let builder = builder.width(40).height(20).samples_per_pixel(4);
+        if scene == "-" {
+            return Ok((builder.build(), world));
+        }
+        Ok((builder.seed(scene.parse()?).build(), world))
+    }
+
+    fn spawn_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = work(stream, &load);
+        });
+        addr
+    }
+
+    /// takes the scene, the seed and the first tile, and then goes away.
+    fn spawn_failing_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let len = read_u64(&mut stream).unwrap();
+            let mut buf = vec![0u8; len as usize + 8 + 33];
+            stream.read_exact(&mut buf).unwrap();
+        });
+        addr
+    }
+
+    /// takes the scene and a tile, and never answers.
+    fn spawn_hanging_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 64];
+            while stream.read(&mut buf).is_ok_and(|n| n > 0) {}
+        });
+        addr
+    }
+
+    fn refused() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    #[test]
+    fn workers_render_the_same_image() {
+        let (camera, world) = load(b"7").unwrap();
+        let expected = camera.render_film(&world);
+        assert!(camera.tiles().len() > 4);
+
+        let workers = vec![spawn_failing_worker(), spawn_worker(), refused(), spawn_worker()];
+        let film = Coordinator::new(b"7".to_vec(), workers).with_timeout(10.0)
+            .render(&camera).unwrap();
+        for (x, y) in expected.bounds().pixels() {
+            assert_eq!(film.count(x, y), 4);
+            assert_eq!(film.color(x, y), expected.color(x, y));
+        }
+    }
+
+    #[test]
+    fn workers_take_the_seed_of_the_coordinator() {
+        // each worker would draw a seed of its own
+        let (camera, world) = load(b"-").unwrap();
+        let expected = camera.render_film(&world);
+        let workers  = vec![spawn_worker(), spawn_worker()];
+        let film = Coordinator::new(b"-".to_vec(), workers).render(&camera).unwrap();
+        for (x, y) in expected.bounds().pixels() {
+            assert_eq!(film.color(x, y), expected.color(x, y));
+        }
+    }
+
+    #[test]
+    fn hanging_workers_time_out() {
+        let (camera, world) = load(b"8").unwrap();
+        let expected = camera.render_film(&world);
+        let workers  = vec![spawn_hanging_worker(), spawn_worker()];
+        let film = Coordinator::new(b"8".to_vec(), workers).with_timeout(0.5)
+            .render(&camera).unwrap();
+        for (x, y) in expected.bounds().pixels() {
+            assert_eq!(film.color(x, y), expected.color(x, y));
+        }
+    }
+
+    #[test]
+    fn reports_progress_and_cancels() {
+        let (camera, world) = load(b"9").unwrap();
+        let expected = camera.render_film(&world);
+        let total    = camera.tiles().len();
+
+        let cancel = CancelToken::new();
+        let mut reports = std::vec::Vec::new();
+        let film = Coordinator::new(b"9".to_vec(), vec![spawn_worker(), spawn_worker()])
+            .render_with(&camera, &cancel.clone(), |p| {
+                reports.push(*p);
+                if p.tiles_done == 2 {
+                    cancel.cancel();
+                }
+            }).unwrap();
+        // the tiles being rendered when cancelled are still merged
+        assert!(reports.len() >= 2 && reports.len() < total);
+        assert!(reports.iter().enumerate().all(|(i, p)| p.tiles_done == i + 1 && p.tiles_total == total));
+        for tile in camera.tiles() {
+            let merged = reports.iter().any(|p| p.tile == tile);
+            for (x, y) in tile.pixels() {
+                assert_eq!(film.count(x, y), if merged {4} else {0});
+                if merged {
+                    assert_eq!(film.color(x, y), expected.color(x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fails_without_workers() {
+        let (camera, _) = load(b"7").unwrap();
+        let workers = vec![refused(), spawn_failing_worker()];
+        assert!(Coordinator::new(b"7".to_vec(), workers).render(&camera).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+//! light emitted by objects.
+//!
+//! An emission is either a plain RGB or a spectral power distribution. The
+//! spectral one is evaluated at the wavelengths of the ray in the spectral
+//! mode and converted into RGB once in the RGB mode.
+//!
+//! The unit of the luminance is chosen so that the constant spectrum with
+//! value 1, and `RGB::new(1.0, 1.0, 1.0)`, have the luminance 1. If the
+//! luminance is given in cd/m^2, the image is in cd/m^2 as well.
+//!
+//! The profile scales the emission by the direction w.r.t. the normal. A
+//! photometric profile gives the luminous intensity, which is divided by
+//! the projected area of the emitter to obtain the luminance.
+//!
+//! Of the CIE F-series fluorescent illuminants, only the representative
+//! ones recommended by the CIE are tabulated: F2 (standard), F7 (broadband)
+//! and F11 (narrow tri-band). The other lamps of each group have similar
+//! spectra; use `make_tabulated` with their data if they are needed.
+
+use crate::vector::Vector3;
+use crate::color::RGB;
+use crate::ray::Ray;
+use crate::collide::Collision;
+use crate::ies::IesProfile;
+use crate::spectrum::{spectrum_to_rgb, luminance};
+
+/// relative spectral power distributions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Spectrum {
+    /// Planck's law at the temperature in kelvin
+    Blackbody(f32),
+    /// values at `start`, `start + step`, ... in nm, interpolated linearly
+    Tabulated{start: f32, step: f32, values: std::vec::Vec<f32>},
+}
+
+impl Spectrum {
+    pub fn make_blackbody(kelvin: f32) -> Self {
+        Spectrum::Blackbody(kelvin)
+    }
+    /// e.g. a measured spectrum. Beyond the ends, the first and the last
+    /// values continue.
+    pub fn make_tabulated(start: f32, step: f32, values: std::vec::Vec<f32>) -> Self {
+        Spectrum::Tabulated{start, step, values}
+    }
+    /// CIE standard illuminant D65, the average daylight
+    pub fn d65() -> Self {
+        Spectrum::make_tabulated(380.0, 10.0, CIE_D65.to_vec())
+    }
+    /// CIE standard illuminant A, the incandescent lamp. It is defined as
+    /// the black body at 2856 K.
+    pub fn a() -> Self {
+        Spectrum::Blackbody(2856.0)
+    }
+    /// CIE F2, the cool white fluorescent lamp
+    pub fn f2() -> Self {
+        Spectrum::make_tabulated(380.0, 5.0, CIE_F2.to_vec())
+    }
+    /// CIE F7, the broadband daylight fluorescent lamp
+    pub fn f7() -> Self {
+        Spectrum::make_tabulated(380.0, 5.0, CIE_F7.to_vec())
+    }
+    /// CIE F11, the narrow tri-band fluorescent lamp
+    pub fn f11() -> Self {
+        Spectrum::make_tabulated(380.0, 5.0, CIE_F11.to_vec())
+    }
+
+    pub fn at(&self, lambda: f32) -> f32 {
+        match self {
+            Spectrum::Blackbody(kelvin) => {planck(lambda, *kelvin)}
+            Spectrum::Tabulated{start, step, values} => {
+                let x = ((lambda - start) / step).max(0.0);
+                let i = x as usize;
+                if i + 1 >= values.len() {
+                    return values.last().cloned().unwrap_or(0.0);
+                }
+                let s = x - i as f32;
+                values[i] * (1.0 - s) + values[i + 1] * s
+            }
+        }
+    }
+}
+
+/// spectral radiance of a black body in W / (m^2 sr nm)
+fn planck(lambda: f32, kelvin: f32) -> f32 {
+    const H: f64 = 6.626_070_15e-34;
+    const C: f64 = 2.997_924_58e8;
+    const K: f64 = 1.380_649e-23;
+    let l = lambda as f64 * 1e-9;
+    let t = kelvin as f64;
+    let b = 2.0 * H * C * C / (l.powi(5) * ((H * C / (l * K * t)).exp() - 1.0));
+    (b * 1e-9) as f32
+}
+
+/// angular distribution of the emission.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Profile {
+    /// the same in all directions, from both sides
+    Uniform,
+    /// only to the side of the normal
+    OneSided,
+    /// one-sided with the falloff `cos^n` from the normal
+    CosinePower(f32),
+    /// the intensity from an IES file. The nadir is along the normal and
+    /// the horizontal angle 0 is along the tangent. `area` is the area of
+    /// the emitter.
+    Photometric{ies: IesProfile, area: f32},
+}
+
+impl Profile {
+    /// scale of the emission toward `wo`.
+    pub fn factor(&self, cr: &Collision, wo: Vector3) -> f32 {
+        let cos = Vector3::dot(wo, cr.normal);
+        match self {
+            Profile::Uniform => {1.0}
+            Profile::OneSided => {if cos > 0.0 {1.0} else {0.0}}
+            Profile::CosinePower(n) => {cos.max(0.0).powf(*n)}
+            Profile::Photometric{ies, area} => {
+                if cos <= 0.0 {
+                    return 0.0;
+                }
+                let b     = Vector3::cross(cr.normal, cr.tangent);
+                let theta = cos.min(1.0).acos().to_degrees();
+                let phi   = Vector3::dot(wo, b).atan2(Vector3::dot(wo, cr.tangent)).to_degrees();
+                // limited at grazing angles where the projected area vanishes
+                ies.intensity(theta, phi) / (area * cos.max(1e-2))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Emission {
+    rgb:      RGB,
+    spectrum: Option<(Spectrum, f32)>, // with the scale
+    profile:  Profile,
+}
+
+impl Emission {
+    pub fn rgb(color: RGB) -> Self {
+        Emission{rgb: color, spectrum: None, profile: Profile::Uniform}
+    }
+
+    /// emits `spectrum` scaled to the luminance.
+    pub fn spectral(spectrum: Spectrum, luminance: f32) -> Self {
+        let norm  = self::luminance(|l| spectrum.at(l), false);
+        let scale = if norm > 0.0 {luminance / norm} else {0.0};
+        let rgb   = spectrum_to_rgb(|l| spectrum.at(l)) * scale;
+        Emission{rgb, spectrum: Some((spectrum, scale)), profile: Profile::Uniform}
+    }
+
+    /// black body at the color temperature with the luminance.
+    pub fn blackbody(kelvin: f32, luminance: f32) -> Self {
+        Emission::spectral(Spectrum::make_blackbody(kelvin), luminance)
+    }
+
+    /// a diffuse emitter with the area (m^2) that emits the luminous flux
+    /// in lumens, with the luminance in cd/m^2.
+    pub fn from_lumens(spectrum: Spectrum, lumens: f32, area: f32) -> Self {
+        Emission::spectral(spectrum, lumens / (std::f32::consts::PI * area))
+    }
+
+    /// a diffuse emitter with the area (m^2) that radiates `watts` within
+    /// the visible range. The luminance is in cd/m^2.
+    pub fn from_watts(spectrum: Spectrum, watts: f32, area: f32) -> Self {
+        let power    = integrate(&spectrum); // W per unit of the spectrum
+        let efficacy = luminance(|l| spectrum.at(l), true) / power; // lm/W
+        Emission::from_lumens(spectrum, watts * efficacy, area)
+    }
+
+    /// a luminaire measured in the IES file, with the area (m^2) of the
+    /// emitting surface. The luminance is in cd/m^2.
+    pub fn from_ies(spectrum: Spectrum, ies: IesProfile, area: f32) -> Self {
+        Emission::spectral(spectrum, 1.0).with_profile(Profile::Photometric{ies, area})
+    }
+
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// the emission seen by the ray that hits the emitter at the collision.
+    pub fn radiance(&self, ray: &Ray, cr: &Collision) -> RGB {
+        if self.profile == Profile::Uniform {
+            return self.value(ray);
+        }
+        let factor = self.profile.factor(cr, -ray.direction.unit());
+        if factor > 0.0 {self.value(ray) * factor} else {RGB::new(0.0, 0.0, 0.0)}
+    }
+
+    /// the emission as it is seen by the ray, regardless of the direction.
+    pub fn value(&self, ray: &Ray) -> RGB {
+        match (&self.spectrum, &ray.wavelengths) {
+            (Some((spectrum, scale)), Some(w)) => {
+                RGB::new(spectrum.at(w.lambda[0]) * scale,
+                         spectrum.at(w.lambda[1]) * scale,
+                         spectrum.at(w.lambda[2]) * scale)
+            }
+            _ => {ray.tint(self.rgb)}
+        }
+    }
+}
+
+impl std::convert::From<RGB> for Emission {
+    fn from(color: RGB) -> Emission {
+        Emission::rgb(color)
+    }
+}
+
+fn integrate(spectrum: &Spectrum) -> f32 {
+    let (mut sum, mut lambda) = (0.0, crate::spectrum::LAMBDA_MIN + 0.5);
+    while lambda < crate::spectrum::LAMBDA_MAX {
+        sum += spectrum.at(lambda);
+        lambda += 1.0;
+    }
+    sum
+}
+
+const CIE_D65: [f32; 35] = [
+      49.975,   54.648,   82.755,   91.486,   93.432,   86.682,  104.865,  117.008,
+     117.812,  114.861,  115.923,  108.811,  109.354,  107.802,  104.790,  107.689,
+     104.405,  104.046,  100.000,   96.334,   95.788,   88.686,   90.006,   89.599,
+      87.699,   83.289,   83.699,   80.027,   80.215,   82.278,   78.284,   69.721,
+      71.609,   74.349,   61.604,
+];
+
+const CIE_F2: [f32; 69] = [
+     1.18,  1.48,  1.84,  2.15,  3.44, 15.69,  3.85,  3.74,  4.19,  4.62,
+     5.06, 34.98, 11.81,  6.27,  6.63,  6.93,  7.19,  7.40,  7.54,  7.62,
+     7.65,  7.62,  7.62,  7.45,  7.28,  7.15,  7.05,  7.04,  7.16,  7.47,
+     8.04,  8.88, 10.01, 24.88, 16.64, 14.59, 16.16, 17.56, 18.62, 21.47,
+    22.79, 19.29, 18.66, 17.73, 16.54, 15.21, 13.80, 12.36, 10.95,  9.65,
+     8.40,  7.32,  6.31,  5.43,  4.68,  4.02,  3.45,  2.96,  2.55,  2.19,
+     1.89,  1.64,  1.53,  1.27,  1.10,  0.99,  0.88,  0.76,  0.68,
+];
+
+const CIE_F7: [f32; 69] = [
+     2.56,  3.18,  3.84,  4.53,  6.15, 19.37,  7.37,  7.05,  7.71,  8.41,
+     9.15, 44.14, 17.52, 11.35, 12.00, 12.58, 13.08, 13.45, 13.71, 13.88,
+    13.95, 13.93, 13.82, 13.64, 13.43, 13.25, 13.08, 12.93, 12.78, 12.60,
+    12.44, 12.33, 12.26, 29.52, 17.05, 12.44, 12.58, 12.72, 12.83, 15.46,
+    16.75, 12.83, 12.67, 12.45, 12.19, 11.89, 11.60, 11.35, 11.12, 10.95,
+    10.76, 10.42, 10.11, 10.04, 10.02, 10.11,  9.87,  8.65,  7.27,  6.44,
+     5.83,  5.41,  5.04,  4.57,  4.12,  3.77,  3.46,  3.08,  2.73,
+];
+
+const CIE_F11: [f32; 69] = [
+     0.91,  0.63,  0.46,  0.37,  1.29, 12.68,  1.59,  1.79,  2.46,  3.33,
+     4.49, 33.94, 12.13,  6.95,  7.19,  7.12,  6.72,  6.13,  5.46,  4.79,
+     5.66, 14.29, 14.96,  8.97,  4.72,  2.33,  1.47,  1.10,  0.89,  0.83,
+     1.18,  4.90, 39.59, 72.84, 32.61,  7.52,  2.83,  1.96,  1.67,  4.43,
+    11.28, 14.76, 12.73,  9.74,  7.33,  9.72, 55.27, 42.58, 13.18, 13.16,
+    12.26,  5.11,  2.07,  2.34,  3.58,  3.01,  2.48,  2.14,  1.54,  1.33,
+     1.46,  1.94,  2.00,  1.20,  1.35,  4.10,  5.58,  2.51,  0.57,
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::emission::*;
+    use crate::color::Color;
+
+    #[test]
+    fn normalized_to_luminance() {
+        for spectrum in [Spectrum::d65(), Spectrum::a(), Spectrum::f2(), Spectrum::f11(),
+                         Spectrum::make_blackbody(5000.0)].iter() {
+            let e = Emission::spectral(spectrum.clone(), 2.5);
+            let (s, scale) = e.spectrum.clone().unwrap();
+            let y = luminance(|l| s.at(l) * scale, false);
+            assert!((y - 2.5).abs() < 1e-3, "{:?} {}", spectrum, y);
+        }
+    }
+
+    #[test]
+    fn color_temperature() {
+        let warm = Emission::blackbody(2000.0, 1.0).rgb;
+        assert!(warm.r() > warm.g() && warm.g() > warm.b());
+        let cool = Emission::blackbody(12000.0, 1.0).rgb;
+        assert!(cool.b() > cool.r());
+        let a   = Emission::spectral(Spectrum::a(),   1.0).rgb;
+        let d65 = Emission::spectral(Spectrum::d65(), 1.0).rgb;
+        assert!(a.r() / a.b() > d65.r() / d65.b());
+    }
+
+    #[test]
+    fn photometric_units() {
+        // 1000 lm from 1 m^2 is 1000 / pi cd/m^2
+        let e = Emission::from_lumens(Spectrum::d65(), 1000.0, 1.0);
+        assert!((luminance(|l| e.spectrum.as_ref().map(|(s, k)| s.at(l) * k).unwrap(), false)
+                 - 1000.0 / std::f32::consts::PI).abs() < 0.5);
+        // monochromatic-ish green light is close to 683 lm/W
+        let green = Spectrum::make_tabulated(550.0, 5.0, vec![0.0, 1.0, 0.0]);
+        let e = Emission::from_watts(green, 1.0, 1.0);
+        let l = luminance(|l| e.spectrum.as_ref().map(|(s, k)| s.at(l) * k).unwrap(), false);
+        assert!((l * std::f32::consts::PI - 683.0).abs() < 15.0, "{}", l * std::f32::consts::PI);
+    }
+
+    #[test]
+    fn measured_spectrum() {
+        // e.g. read from a file
+        let values: std::vec::Vec<f32> = "0.5 1.0 2.0".split(' ').map(|v| v.parse().unwrap()).collect();
+        let measured = Spectrum::make_tabulated(400.0, 100.0, values);
+        assert_eq!(measured.at(300.0), 0.5);
+        assert_eq!(measured.at(450.0), 0.75);
+        assert_eq!(measured.at(700.0), 2.0);
+        assert_eq!(Spectrum::make_tabulated(400.0, 100.0, vec![]).at(500.0), 0.0);
+    }
+
+    #[test]
+    fn directional_profiles() {
+        let cr = Collision::new(1.0, Vector3::new(0.0, -1.0, 0.0), (0.0, 0.0))
+            .with_tangent(Vector3::new(1.0, 0.0, 0.0));
+        let below  = Vector3::new(0.0, -1.0, 0.0);
+        let slant  = Vector3::new(1.0, -1.0, 0.0).unit();
+        let above  = Vector3::new(0.0, 1.0, 0.0);
+        assert_eq!(Profile::Uniform.factor(&cr, above), 1.0);
+        assert_eq!(Profile::OneSided.factor(&cr, below), 1.0);
+        assert_eq!(Profile::OneSided.factor(&cr, above), 0.0);
+        assert!((Profile::CosinePower(2.0).factor(&cr, slant) - 0.5).abs() < 1e-5);
+        assert_eq!(Profile::CosinePower(2.0).factor(&cr, above), 0.0);
+
+        let ray = Ray::new(Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        let e   = Emission::rgb(RGB::new(2.0, 2.0, 2.0)).with_profile(Profile::OneSided);
+        assert_eq!(e.radiance(&ray, &cr), RGB::new(2.0, 2.0, 2.0));
+        let back = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(e.radiance(&back, &cr), RGB::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn photometric_intensity() {
+        // axially symmetric, 100 cd at the nadir and 50 cd at 60 degrees
+        let ies = IesProfile::parse("IESNA:LM-63-2002\nTILT=NONE\n\
+            1 -1 1.0 3 1 1 2 0 0 0\n1 1 10\n0 60 90\n0\n100 50 0\n").unwrap();
+        let cr = Collision::new(1.0, Vector3::new(0.0, -1.0, 0.0), (0.0, 0.0));
+        let e  = Emission::from_ies(Spectrum::d65(), ies, 0.5);
+        // the luminance times the projected area is the intensity
+        let at = |dir: Vector3| {
+            let ray = Ray::new(-dir, dir);
+            let cos = Vector3::dot(-dir, cr.normal);
+            e.profile.factor(&cr, -ray.direction) * 0.5 * cos
+        };
+        assert!((at(Vector3::new(0.0, 1.0, 0.0)) - 100.0).abs() < 1e-3);
+        let sin60 = 3.0f32.sqrt() / 2.0;
+        assert!((at(Vector3::new(sin60, 0.5, 0.0)) - 50.0).abs() < 1e-2);
+        assert!((at(Vector3::new(0.0, 0.5, -sin60)) - 50.0).abs() < 1e-2);
+        // the color of the spectrum with unit luminance
+        assert!((luminance(|l| e.spectrum.as_ref().map(|(s, k)| s.at(l) * k).unwrap(), false)
+                 - 1.0).abs() < 1e-3);
+    }
+}
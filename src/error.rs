@@ -4,6 +4,9 @@
 pub enum ErrorKind {
     ParseError(std::string::String),
     IoError(std::io::Error),
+    /// the input is valid but does not fit the rest, e.g. a checkpoint of
+    /// another camera.
+    Mismatch(std::string::String),
 }
 
 #[derive(Debug)]
@@ -15,6 +18,11 @@ impl Error {
     pub fn new(kind: ErrorKind) -> Error {
         Error{kind: kind}
     }
+
+    /// an error in the format of the input.
+    pub fn parse<S: std::convert::Into<std::string::String>>(msg: S) -> Error {
+        Error::new(ErrorKind::ParseError(msg.into()))
+    }
 }
 
 impl std::convert::From<std::io::Error> for Error {
@@ -0,0 +1,418 @@
+//! film that accumulates the samples of the pixels.
+//!
+//! Each pixel keeps the running mean of the color and the variance of the
+//! luminance (Welford), so that the renderer can tell which pixels have
+//! converged. The image itself is reconstructed from the samples splatted
+//! with a filter, which may reach the neighboring pixels.
+//!
+//! A film may cover only a part of the image. A tile is rendered into a
+//! film that has a margin of the filter radius around it, and then merged
+//! into the whole film, so that the samples near the edge of a tile also
+//! contribute to the pixels of the neighboring tiles.
+//!
+//! The buffers can be written out and read back bit for bit, so that a
+//! render can be checkpointed and resumed.
+
+use crate::color::{Color, RGB};
+use crate::image::Image;
+use crate::filter::Filter;
+use crate::error::{Error, Result};
+
+/// rectangle of the pixels `x0 <= x < x1` and `y0 <= y < y1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn new(x0: usize, y0: usize, x1: usize, y1: usize) -> Tile {
+        Tile{x0, y0, x1, y1}
+    }
+
+    /// covers the image with tiles of `size` x `size`, row by row.
+    pub fn split(width: usize, height: usize, size: usize) -> std::vec::Vec<Tile> {
+        let size = size.max(1);
+        let mut tiles = std::vec::Vec::new();
+        for y0 in (0..height).step_by(size) {
+            for x0 in (0..width).step_by(size) {
+                tiles.push(Tile::new(x0, y0, (x0 + size).min(width), (y0 + size).min(height)));
+            }
+        }
+        tiles
+    }
+
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        self.x0 <= x && x < self.x1 && self.y0 <= y && y < self.y1
+    }
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+}
+
+pub struct Film {
+    bounds: Tile,
+    filter: Filter,
+    sum:    std::vec::Vec<RGB>, // weighted by the filter
+    weight: std::vec::Vec<f32>,
+    mean:   std::vec::Vec<RGB>,
+    m2:     std::vec::Vec<f32>, // sum of the squared deviations of the luminance
+    count:  std::vec::Vec<u32>,
+}
+
+/// below it, the sum of the weights of a pixel is too small to divide by.
+/// the negative lobes of some filters may nearly cancel the positive ones.
+const MIN_WEIGHT: f32 = 1e-3;
+
+fn luminance(c: RGB) -> f32 {
+    0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b()
+}
+
+impl Film {
+    /// film of the whole image with the box filter of a pixel.
+    pub fn new(width: usize, height: usize) -> Film {
+        Film::with_bounds(Tile::new(0, 0, width, height), Filter::make_box(0.5))
+    }
+
+    fn with_bounds(bounds: Tile, filter: Filter) -> Film {
+        let n = bounds.width() * bounds.height();
+        Film{bounds, filter,
+             sum:    vec![RGB::new(0.0, 0.0, 0.0); n],
+             weight: vec![0.0; n],
+             mean:   vec![RGB::new(0.0, 0.0, 0.0); n],
+             m2:     vec![0.0; n],
+             count:  vec![0; n]}
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Film {
+        self.filter = filter;
+        self
+    }
+
+    /// the pixels covered by the film.
+    pub fn bounds(&self) -> Tile {
+        self.bounds
+    }
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        debug_assert!(self.bounds.contains(x, y));
+        (y - self.bounds.y0) * self.bounds.width() + (x - self.bounds.x0)
+    }
+
+    /// adds a sample of the pixel `(x, y)` taken at `offset` in the pixel.
+    pub fn add_sample(&mut self, x: usize, y: usize, offset: (f32, f32), color: RGB) {
+        let i = self.index(x, y);
+        let old = luminance(self.mean[i]);
+        self.count[i] += 1;
+        let n = self.count[i] as f32;
+        self.mean[i] = self.mean[i] * ((n - 1.0) / n) + color / n;
+        self.m2[i]   += (luminance(color) - old) * (luminance(color) - luminance(self.mean[i]));
+        self.splat(x as f32 + offset.0, y as f32 + offset.1, color);
+    }
+
+    /// adds the color at the position `(fx, fy)` in the image to the pixels
+    /// within the filter radius. Pixels outside of the film are skipped.
+    pub fn splat(&mut self, fx: f32, fy: f32, color: RGB) {
+        let r = self.filter.radius();
+        let b = self.bounds;
+        let range = |f: f32, lo: usize, hi: usize| {
+            let first = (f - 0.5 - r).ceil().max(lo as f32) as usize;
+            let last  = ((f - 0.5 + r).floor() + 1.0).clamp(0.0, hi as f32) as usize;
+            first..last.max(first)
+        };
+        for y in range(fy, b.y0, b.y1) {
+            for x in range(fx, b.x0, b.x1) {
+                let w = self.filter.eval(x as f32 + 0.5 - fx, y as f32 + 0.5 - fy);
+                if w != 0.0 {
+                    let i = self.index(x, y);
+                    self.sum[i]    += color * w;
+                    self.weight[i] += w;
+                }
+            }
+        }
+    }
+
+    /// pixels that the samples in the tile may reach.
+    fn margin(&self, tile: Tile) -> Tile {
+        let m = (self.filter.radius() - 0.5).max(0.0).ceil() as usize;
+        let b = self.bounds;
+        Tile::new(tile.x0.saturating_sub(m).max(b.x0), tile.y0.saturating_sub(m).max(b.y0),
+                  (tile.x1 + m).min(b.x1), (tile.y1 + m).min(b.y1))
+    }
+
+    /// an empty film to render the tile into. It has the margin for the
+    /// filter and the statistics of the pixels so far.
+    pub fn tile(&self, tile: Tile) -> Film {
+        let mut film = Film::with_bounds(self.margin(tile), self.filter);
+        for (x, y) in film.bounds.pixels() {
+            let (i, j) = (self.index(x, y), film.index(x, y));
+            film.mean[j]  = self.mean[i];
+            film.m2[j]    = self.m2[i];
+            film.count[j] = self.count[i];
+        }
+        film
+    }
+
+    /// adds the splats of the film rendered by `tile` and takes over the
+    /// statistics of the pixels in the tile.
+    pub fn merge(&mut self, film: &Film, tile: Tile) {
+        for (x, y) in film.bounds.pixels() {
+            let (i, j) = (self.index(x, y), film.index(x, y));
+            self.sum[i]    += film.sum[j];
+            self.weight[i] += film.weight[j];
+            if tile.contains(x, y) {
+                self.mean[i]  = film.mean[j];
+                self.m2[i]    = film.m2[j];
+                self.count[i] = film.count[j];
+            }
+        }
+    }
+
+    pub fn count(&self, x: usize, y: usize) -> usize {
+        self.count[self.index(x, y)] as usize
+    }
+
+    /// the fewest samples of the pixels.
+    pub fn min_count(&self) -> usize {
+        self.count.iter().cloned().min().unwrap_or(0) as usize
+    }
+
+    /// the mean of the samples of the pixel.
+    pub fn mean(&self, x: usize, y: usize) -> RGB {
+        self.mean[self.index(x, y)]
+    }
+
+    /// the reconstructed color of the pixel.
+    pub fn color(&self, x: usize, y: usize) -> RGB {
+        let i = self.index(x, y);
+        if self.weight[i] <= MIN_WEIGHT {
+            return self.mean[i];
+        }
+        let c = self.sum[i] / self.weight[i];
+        // the negative lobes may overshoot
+        RGB::new(c.r().max(0.0), c.g().max(0.0), c.b().max(0.0))
+    }
+
+    /// sample variance of the luminance.
+    pub fn variance(&self, x: usize, y: usize) -> f32 {
+        let i = self.index(x, y);
+        if self.count[i] < 2 {0.0} else {self.m2[i] / (self.count[i] - 1) as f32}
+    }
+
+    /// standard error of the mean luminance relative to the mean. Dark
+    /// pixels are compared with a small floor instead.
+    pub fn relative_error(&self, x: usize, y: usize) -> f32 {
+        let n = self.count(x, y);
+        if n < 2 {
+            return f32::INFINITY;
+        }
+        let error = (self.variance(x, y) / n as f32).sqrt();
+        error / luminance(self.mean(x, y)).max(1e-2)
+    }
+
+    /// the reconstructed colors with gamma 2.
+    pub fn to_image(&self) -> Image {
+        let b = self.bounds;
+        let mut img = Image::new(b.width(), b.height());
+        for (x, y) in b.pixels() {
+            *img.at_mut(x - b.x0, y - b.y0) = std::convert::From::from(self.color(x, y).sqrt());
+        }
+        img
+    }
+
+    /// writes the bounds and the buffers in little endian. The filter is
+    /// not written.
+    pub fn write_to<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        let b = self.bounds;
+        for v in [b.x0, b.y0, b.x1, b.y1].iter() {
+            writer.write_all(&(*v as u64).to_le_bytes())?;
+        }
+        for i in 0..self.count.len() {
+            for v in [self.sum[i].r(), self.sum[i].g(), self.sum[i].b(), self.weight[i],
+                      self.mean[i].r(), self.mean[i].g(), self.mean[i].b(), self.m2[i]].iter() {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+            writer.write_all(&self.count[i].to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// reads a film written by `write_to`.
+    pub fn read_from<R: std::io::Read>(reader: &mut R, filter: Filter) -> Result<Film> {
+        let mut u64_buf = [0u8; 8];
+        let mut bounds  = [0usize; 4];
+        for v in bounds.iter_mut() {
+            reader.read_exact(&mut u64_buf)?;
+            *v = u64::from_le_bytes(u64_buf) as usize;
+        }
+        let [x0, y0, x1, y1] = bounds;
+        if x1 < x0 || y1 < y0 || (x1 - x0).saturating_mul(y1 - y0) > 1 << 28 {
+            return Err(Error::parse("invalid film bounds"));
+        }
+        let mut film = Film::with_bounds(Tile::new(x0, y0, x1, y1), filter);
+        let mut buf = [0u8; 4];
+        let mut next = |reader: &mut R| -> Result<[u8; 4]> {
+            reader.read_exact(&mut buf)?;
+            Ok(buf)
+        };
+        for i in 0..film.count.len() {
+            let mut v = [0.0f32; 8];
+            for x in v.iter_mut() {
+                *x = f32::from_le_bytes(next(reader)?);
+            }
+            film.sum[i]    = RGB::new(v[0], v[1], v[2]);
+            film.weight[i] = v[3];
+            film.mean[i]   = RGB::new(v[4], v[5], v[6]);
+            film.m2[i]     = v[7];
+            film.count[i]  = u32::from_le_bytes(next(reader)?);
+        }
+        Ok(film)
+    }
+
+    /// the number of samples, from blue (fewest) to red (most).
+    pub fn heatmap(&self) -> Image {
+        let max = self.count.iter().cloned().max().unwrap_or(0).max(1) as f32;
+        let b = self.bounds;
+        let mut img = Image::new(b.width(), b.height());
+        for (x, y) in b.pixels() {
+            let t = self.count(x, y) as f32 / max;
+            let c = RGB::new(t, 1.0 - (2.0 * t - 1.0).abs(), 1.0 - t);
+            *img.at_mut(x - b.x0, y - b.y0) = std::convert::From::from(c);
+        }
+        img
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::film::*;
+    use crate::image::RGBPixel;
+    use rand::Rng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn running_mean_and_variance() {
+        let mut film = Film::new(2, 1);
+        let values = [0.2f32, 0.4, 0.9, 0.1, 0.5];
+        for &v in values.iter() {
+            film.add_sample(1, 0, (0.5, 0.5), RGB::new(v, v, v));
+        }
+        let mean = values.iter().sum::<f32>() / 5.0;
+        let var  = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / 4.0;
+        assert_eq!(film.count(1, 0), 5);
+        assert!((film.mean(1, 0).g() - mean).abs() < 1e-6);
+        assert!((film.color(1, 0).g() - mean).abs() < 1e-6);
+        assert!((film.variance(1, 0) - var).abs() < 1e-6);
+        assert!((film.relative_error(1, 0) - (var / 5.0).sqrt() / mean).abs() < 1e-5);
+        assert_eq!(film.relative_error(0, 0), f32::INFINITY);
+    }
+
+    #[test]
+    fn heatmap_ramp() {
+        let mut film = Film::new(2, 1);
+        for _ in 0..4 {
+            film.add_sample(0, 0, (0.5, 0.5), RGB::new(1.0, 1.0, 1.0));
+        }
+        film.add_sample(1, 0, (0.5, 0.5), RGB::new(1.0, 1.0, 1.0));
+        let img = film.heatmap();
+        assert_eq!(*img.at(0, 0), RGBPixel::new(255, 0, 0));
+        assert_eq!(*img.at(1, 0), RGBPixel::from(RGB::new(0.25, 0.5, 0.75)));
+    }
+
+    #[test]
+    fn splat_reaches_neighbors() {
+        let mut film = Film::new(4, 4).with_filter(Filter::make_tent(1.5));
+        film.add_sample(1, 1, (0.5, 0.5), RGB::new(1.0, 0.0, 0.0));
+        film.add_sample(2, 1, (0.5, 0.5), RGB::new(0.0, 1.0, 0.0));
+        // the samples are only counted in their own pixels
+        assert_eq!(film.count(1, 1), 1);
+        assert_eq!(film.count(1, 2), 0);
+        // but the colors are blended by the filter
+        let c = film.color(1, 1);
+        assert!((c.r() - 1.5 / 2.0).abs() < 1e-5 && (c.g() - 0.5 / 2.0).abs() < 1e-5, "{:?}", c);
+        let c = film.color(3, 1);
+        assert!(c.r() == 0.0 && (c.g() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn negative_weights_fall_back_to_the_mean() {
+        let mut film = Film::new(3, 1).with_filter(Filter::make_lanczos(2.0));
+        film.add_sample(1, 0, (0.5, 0.5), RGB::new(0.5, 0.5, 0.5));
+        // bright samples of the neighbors in the negative lobe of the pixel
+        let lobe = film.filter().eval(1.5, 0.0);
+        assert!(lobe < 0.0);
+        for _ in 0..16 {
+            film.splat(0.0, 0.5, RGB::new(1.0, 1.0, 1.0));
+        }
+        assert!(1.0 + 16.0 * lobe < 0.0);
+        assert_eq!(film.color(1, 0), RGB::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn tiles_match_whole_film() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(11);
+        let filter  = Filter::make_gaussian(2.0, 0.7);
+        let samples: std::vec::Vec<(usize, usize, (f32, f32), RGB)> = (0..2000).map(|_| {
+            (rng.gen_range(0, 13), rng.gen_range(0, 9),
+             (rng.gen_range(0.0f32, 1.0f32), rng.gen_range(0.0f32, 1.0f32)),
+             RGB::new(rng.gen_range(0.0f32, 1.0f32), 0.5, 1.0))
+        }).collect();
+
+        let mut whole = Film::new(13, 9).with_filter(filter);
+        for &(x, y, o, c) in samples.iter() {
+            whole.add_sample(x, y, o, c);
+        }
+        let mut tiled = Film::new(13, 9).with_filter(filter);
+        let tiles = Tile::split(13, 9, 4);
+        assert_eq!(tiles.len(), 12);
+        assert_eq!(tiles.iter().map(|t| t.width() * t.height()).sum::<usize>(), 13 * 9);
+        for tile in tiles {
+            let mut film = tiled.tile(tile);
+            for &(x, y, o, c) in samples.iter().filter(|s| tile.contains(s.0, s.1)) {
+                film.add_sample(x, y, o, c);
+            }
+            tiled.merge(&film, tile);
+        }
+        for y in 0..9 {
+            for x in 0..13 {
+                assert_eq!(whole.count(x, y), tiled.count(x, y));
+                assert!((whole.color(x, y).r() - tiled.color(x, y).r()).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let mut rng  = rand_xorshift::XorShiftRng::seed_from_u64(5);
+        let whole    = Film::new(9, 7).with_filter(Filter::make_mitchell(2.0));
+        let mut film = whole.tile(Tile::new(4, 3, 6, 5));
+        for _ in 0..50 {
+            let (x, y) = (rng.gen_range(4, 6), rng.gen_range(3, 5));
+            let offset = (rng.gen_range(0.0f32, 1.0f32), rng.gen_range(0.0f32, 1.0f32));
+            film.add_sample(x, y, offset, RGB::new(rng.gen_range(0.0f32, 1.0f32), 0.2, 0.3));
+        }
+        let mut bytes = std::vec::Vec::new();
+        film.write_to(&mut bytes).unwrap();
+        let read = Film::read_from(&mut &bytes[..], film.filter()).unwrap();
+        assert_eq!(read.bounds(), Tile::new(2, 1, 8, 7));
+        assert_eq!(read.bounds(), film.bounds());
+        for (x, y) in film.bounds().pixels() {
+            assert_eq!(read.count(x, y), film.count(x, y));
+            assert_eq!(read.color(x, y), film.color(x, y));
+            assert_eq!(read.variance(x, y).to_bits(), film.variance(x, y).to_bits());
+        }
+        assert!(Film::read_from(&mut &bytes[..bytes.len() - 1], film.filter()).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! pixel reconstruction filters.
+//!
+//! All of them are separable, `f(x, y) = f(x) f(y)`, and vanish outside of
+//! the radius. The film normalizes by the sum of the weights, so the
+//! filters do not have to integrate to one.
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box{radius: f32},
+    Tent{radius: f32},
+    Gaussian{radius: f32, sigma: f32},
+    /// cubic with the parameters `b` and `c` (Mitchell & Netravali 1988)
+    Mitchell{radius: f32, b: f32, c: f32},
+    /// sinc windowed by the central lobe of a wider sinc
+    Lanczos{radius: f32},
+    BlackmanHarris{radius: f32},
+}
+
+impl Filter {
+    pub fn make_box(radius: f32) -> Self {
+        Filter::Box{radius}
+    }
+    pub fn make_tent(radius: f32) -> Self {
+        Filter::Tent{radius}
+    }
+    pub fn make_gaussian(radius: f32, sigma: f32) -> Self {
+        Filter::Gaussian{radius, sigma}
+    }
+    /// with the recommended `b = c = 1/3`.
+    pub fn make_mitchell(radius: f32) -> Self {
+        Filter::Mitchell{radius, b: 1.0 / 3.0, c: 1.0 / 3.0}
+    }
+    pub fn make_lanczos(radius: f32) -> Self {
+        Filter::Lanczos{radius}
+    }
+    pub fn make_blackman_harris(radius: f32) -> Self {
+        Filter::BlackmanHarris{radius}
+    }
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box{radius}            => {radius}
+            Filter::Tent{radius}           => {radius}
+            Filter::Gaussian{radius, ..}   => {radius}
+            Filter::Mitchell{radius, ..}   => {radius}
+            Filter::Lanczos{radius}        => {radius}
+            Filter::BlackmanHarris{radius} => {radius}
+        }
+    }
+
+    /// writes the kind and the parameters in little endian.
+    pub fn write_to<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        let (kind, params) = match *self {
+            Filter::Box{radius}              => {(0u8, [radius, 0.0, 0.0])}
+            Filter::Tent{radius}             => {(1u8, [radius, 0.0, 0.0])}
+            Filter::Gaussian{radius, sigma}  => {(2u8, [radius, sigma, 0.0])}
+            Filter::Mitchell{radius, b, c}   => {(3u8, [radius, b, c])}
+            Filter::Lanczos{radius}          => {(4u8, [radius, 0.0, 0.0])}
+            Filter::BlackmanHarris{radius}   => {(5u8, [radius, 0.0, 0.0])}
+        };
+        writer.write_all(&[kind])?;
+        for p in params.iter() {
+            writer.write_all(&p.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// reads a filter written by `write_to`.
+    pub fn read_from<R: std::io::Read>(reader: &mut R) -> Result<Filter> {
+        let mut kind = [0u8; 1];
+        reader.read_exact(&mut kind)?;
+        let mut p = [0.0f32; 3];
+        for v in p.iter_mut() {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            *v = f32::from_le_bytes(buf);
+        }
+        let radius = p[0];
+        match kind[0] {
+            0 => {Ok(Filter::Box{radius})}
+            1 => {Ok(Filter::Tent{radius})}
+            2 => {Ok(Filter::Gaussian{radius, sigma: p[1]})}
+            3 => {Ok(Filter::Mitchell{radius, b: p[1], c: p[2]})}
+            4 => {Ok(Filter::Lanczos{radius})}
+            5 => {Ok(Filter::BlackmanHarris{radius})}
+            k => {Err(Error::parse(format!("unknown filter: {}", k)))}
+        }
+    }
+
+    /// weight of a sample at the offset `(dx, dy)` from the pixel center.
+    pub fn eval(&self, dx: f32, dy: f32) -> f32 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+
+    fn eval_1d(&self, x: f32) -> f32 {
+        let r = self.radius();
+        let x = x.abs();
+        if x > r {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box{..}  => {1.0}
+            Filter::Tent{..} => {r - x}
+            Filter::Gaussian{sigma, ..} => {
+                let g = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                (g(x) - g(r)).max(0.0)
+            }
+            Filter::Mitchell{b, c, ..} => {
+                let x = 2.0 * x / r;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x +
+                     (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x +
+                     (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            }
+            Filter::Lanczos{..} => {sinc(x) * sinc(x / r)}
+            Filter::BlackmanHarris{..} => {
+                let t = 2.0 * std::f32::consts::PI * (x + r) / (2.0 * r);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        1.0
+    } else {
+        let px = std::f32::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::*;
+
+    fn all() -> [Filter; 6] {
+        [Filter::make_box(0.5), Filter::make_tent(1.0), Filter::make_gaussian(1.5, 0.5),
+         Filter::make_mitchell(2.0), Filter::make_lanczos(3.0), Filter::make_blackman_harris(2.0)]
+    }
+
+    #[test]
+    fn peak_at_center_and_vanish_outside() {
+        for f in all().iter() {
+            let r = f.radius();
+            let peak = f.eval(0.0, 0.0);
+            assert!(peak > 0.0, "{:?}", f);
+            for i in 1..100 {
+                let x = r * i as f32 / 100.0;
+                assert!(f.eval(x, 0.0) <= peak + 1e-6, "{:?} {}", f, x);
+                assert_eq!(f.eval(x, 0.0), f.eval(-x, 0.0));
+            }
+            assert_eq!(f.eval(r * 1.01, 0.0), 0.0);
+            assert_eq!(f.eval(0.0, r * 1.01), 0.0);
+        }
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        for f in all().iter() {
+            let mut bytes = std::vec::Vec::new();
+            f.write_to(&mut bytes).unwrap();
+            assert_eq!(Filter::read_from(&mut &bytes[..]).unwrap(), *f);
+        }
+        assert!(Filter::read_from(&mut &[9u8; 13][..]).is_err());
+    }
+
+    #[test]
+    fn filter_shapes() {
+        // the negative lobes of the sharpening filters
+        assert!(Filter::make_mitchell(2.0).eval(1.5, 0.0) < 0.0);
+        assert!(Filter::make_lanczos(3.0).eval(1.5, 0.0) < 0.0);
+        // Mitchell is continuous at |x| = r / 2 and reaches zero at r
+        let m = Filter::make_mitchell(2.0);
+        assert!((m.eval(0.999, 0.0) - m.eval(1.001, 0.0)).abs() < 1e-3);
+        assert!(m.eval(1.999, 0.0).abs() < 1e-4);
+        // Blackman-Harris is almost zero at the edge
+        assert!(Filter::make_blackman_harris(2.0).eval(2.0, 0.0) < 1e-4);
+        assert!((Filter::make_tent(1.0).eval(0.5, 0.5) - 0.25).abs() < 1e-6);
+    }
+}
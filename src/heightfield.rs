@@ -0,0 +1,459 @@
+//! terrain made from a regular grid of heights.
+//!
+//! Each grid cell is split into two triangles and shaded with normals
+//! interpolated from the grid points. The ray walks over the grid by 2D DDA.
+//! To skip empty space, it starts from the coarsest level of a min/max
+//! mip hierarchy and goes down into a cell only if the ray passes through
+//! the height range of the cell.
+
+use crate::vector::Vector3;
+use crate::ray::Ray;
+use crate::aabb::AABB;
+use crate::collide::{Collision, Collide};
+use crate::error::{Error, Result};
+use crate::image::header_tokens;
+use crate::mesh::collide_triangle;
+
+/// 2D grid of heights. `values[z * width + x]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightMap {
+    width:  usize,
+    height: usize,
+    values: std::vec::Vec<f32>,
+}
+
+impl HeightMap {
+    /// fails unless the map has at least 2 x 2 values.
+    pub fn new(width: usize, height: usize, values: std::vec::Vec<f32>) -> Result<HeightMap> {
+        if grid_size(width, height)? != values.len() {
+            return Err(Error::parse(format!("{} values for the height map of {}x{}",
+                                            values.len(), width, height)));
+        }
+        Ok(HeightMap{width, height, values})
+    }
+
+    pub fn width (&self) -> usize {self.width}
+    pub fn height(&self) -> usize {self.height}
+    #[cfg(test)]
+    pub fn at(&self, x: usize, z: usize) -> f32 {
+        self.values[z * self.width + x]
+    }
+
+    /// reads 8 or 16-bit PGM (P5 or P2). heights are normalized into [0, 1].
+    pub fn read_pgm<P>(path: P) -> Result<HeightMap>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        HeightMap::parse_pgm(&std::fs::read(path)?)
+    }
+
+    /// reads grayscale (Pf) or color (PF) PFM. For a color PFM, the first
+    /// channel is used. In PFM, rows are stored from the bottom.
+    pub fn read_pfm<P>(path: P) -> Result<HeightMap>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        HeightMap::parse_pfm(&std::fs::read(path)?)
+    }
+
+    pub fn parse_pgm(bytes: &[u8]) -> Result<HeightMap> {
+        let (tokens, offset) = header_tokens(bytes, 4)?;
+        let width:  usize = tokens[1].parse()?;
+        let height: usize = tokens[2].parse()?;
+        let maxval: u32   = tokens[3].parse()?;
+        if maxval == 0 || maxval > 65535 {
+            return Err(Error::parse(format!("invalid PGM maxval: {}", maxval)));
+        }
+        let n     = grid_size(width, height)?;
+        let scale = 1.0 / maxval as f32;
+
+        let values = match tokens[0].as_str() {
+            "P5" => {
+                let body  = bytes.get(offset..).unwrap_or(&[]);
+                let bytes_per_pixel = if maxval < 256 {1} else {2};
+                if body.len() / bytes_per_pixel < n {
+                    return Err(Error::parse("PGM is too short"));
+                }
+                if bytes_per_pixel == 1 {
+                    body[..n].iter().map(|&v| v as f32 * scale).collect()
+                } else {
+                    // 16-bit PGM is big endian
+                    body[..2*n].chunks_exact(2)
+                        .map(|v| u16::from_be_bytes([v[0], v[1]]) as f32 * scale)
+                        .collect()
+                }
+            }
+            "P2" => {
+                let body = std::str::from_utf8(bytes.get(offset..).unwrap_or(&[]))
+                    .map_err(|e| Error::parse(format!("{:?}", e)))?;
+                let values = body.split_whitespace()
+                    .take(n)
+                    .map(|v| v.parse::<u32>().map(|v| v as f32 * scale))
+                    .collect::<std::result::Result<std::vec::Vec<f32>, _>>()?;
+                if values.len() < n {
+                    return Err(Error::parse("PGM is too short"));
+                }
+                values
+            }
+            magic => {
+                return Err(Error::parse(format!("not a PGM: {}", magic)));
+            }
+        };
+        HeightMap::new(width, height, values)
+    }
+
+    pub fn parse_pfm(bytes: &[u8]) -> Result<HeightMap> {
+        let (tokens, offset) = header_tokens(bytes, 4)?;
+        let channels = match tokens[0].as_str() {
+            "Pf" => 1,
+            "PF" => 3,
+            magic => {return Err(Error::parse(format!("not a PFM: {}", magic)));}
+        };
+        let width:  usize = tokens[1].parse()?;
+        let height: usize = tokens[2].parse()?;
+        let scale:  f32   = tokens[3].parse()?;
+        let little_endian = scale < 0.0;
+        let n = grid_size(width, height)?;
+
+        let body = bytes.get(offset..).unwrap_or(&[]);
+        if body.len() / (channels * 4) < n {
+            return Err(Error::parse("PFM is too short"));
+        }
+        let mut values = std::vec::Vec::with_capacity(n);
+        for z in 0..height {
+            let row = height - 1 - z;
+            for x in 0..width {
+                let i = ((row * width + x) * channels) * 4;
+                let b = [body[i], body[i+1], body[i+2], body[i+3]];
+                values.push(if little_endian {
+                    f32::from_le_bytes(b)
+                } else {
+                    f32::from_be_bytes(b)
+                });
+            }
+        }
+        HeightMap::new(width, height, values)
+    }
+}
+
+/// the number of grid points. A heightfield needs at least 2 x 2 of them.
+fn grid_size(width: usize, height: usize) -> Result<usize> {
+    if width < 2 || height < 2 {
+        return Err(Error::parse(format!("height map must be at least 2x2: {}x{}", width, height)));
+    }
+    width.checked_mul(height).ok_or_else(|| Error::parse("height map is too large"))
+}
+
+/// one level of the min/max mip hierarchy
+struct MipLevel {
+    width:  usize, // number of cells
+    height: usize,
+    range:  std::vec::Vec<(f32, f32)>,
+}
+
+pub struct Heightfield {
+    nx:       usize, // number of grid points
+    nz:       usize,
+    heights:  std::vec::Vec<f32>,
+    normals:  std::vec::Vec<Vector3>,
+    corner:   Vector3,
+    cell:     (f32, f32),
+    levels:   std::vec::Vec<MipLevel>,
+    bounds:   AABB,
+}
+
+impl Heightfield {
+    /// places the map on the xz plane from `corner` to `corner + extent`.
+    /// The height at a grid point is `corner.y + map.at(x, z) * extent.y`.
+    /// fails unless the extent on the xz plane is positive.
+    pub fn new(map: &HeightMap, corner: Vector3, extent: Vector3) -> Result<Heightfield> {
+        let positive = |v: f32| v > 0.0 && v.is_finite();
+        if !positive(extent[0]) || !positive(extent[2]) {
+            return Err(Error::parse(format!("invalid extent of a heightfield: {:?}", extent)));
+        }
+        let nx = map.width();
+        let nz = map.height();
+        let heights: std::vec::Vec<f32> = map.values.iter()
+            .map(|h| corner[1] + h * extent[1]).collect();
+        let cell = (extent[0] / (nx - 1) as f32, extent[2] / (nz - 1) as f32);
+
+        let h = |x: usize, z: usize| heights[z * nx + x];
+        let mut normals = std::vec::Vec::with_capacity(nx * nz);
+        for z in 0..nz {
+            for x in 0..nx {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(nx - 1));
+                let (z0, z1) = (z.saturating_sub(1), (z + 1).min(nz - 1));
+                let dhdx = (h(x1, z) - h(x0, z)) / ((x1 - x0) as f32 * cell.0);
+                let dhdz = (h(x, z1) - h(x, z0)) / ((z1 - z0) as f32 * cell.1);
+                normals.push(Vector3::unit(Vector3::new(-dhdx, 1.0, -dhdz)));
+            }
+        }
+
+        let mut range = std::vec::Vec::with_capacity((nx - 1) * (nz - 1));
+        for z in 0..nz-1 {
+            for x in 0..nx-1 {
+                let hs = [h(x, z), h(x+1, z), h(x, z+1), h(x+1, z+1)];
+                let lo = hs.iter().cloned().fold(f32::INFINITY, f32::min);
+                let hi = hs.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                range.push((lo, hi));
+            }
+        }
+        let mut levels = vec![MipLevel{width: nx - 1, height: nz - 1, range}];
+        while levels.last().is_some_and(|l| l.width > 1 || l.height > 1) {
+            let prev   = levels.last().unwrap();
+            let width  = (prev.width + 1) / 2;
+            let height = (prev.height + 1) / 2;
+            let mut range = std::vec::Vec::with_capacity(width * height);
+            for z in 0..height {
+                for x in 0..width {
+                    let mut r = (f32::INFINITY, f32::NEG_INFINITY);
+                    for cz in 2*z..(2*z+2).min(prev.height) {
+                        for cx in 2*x..(2*x+2).min(prev.width) {
+                            let c = prev.range[cz * prev.width + cx];
+                            r = (r.0.min(c.0), r.1.max(c.1));
+                        }
+                    }
+                    range.push(r);
+                }
+            }
+            levels.push(MipLevel{width, height, range});
+        }
+
+        let top = levels.last().unwrap().range[0];
+        let bounds = AABB::new(Vector3::new(corner[0], top.0, corner[2]),
+                               Vector3::new(corner[0] + extent[0], top.1,
+                                            corner[2] + extent[2]));
+
+        Ok(Heightfield{nx, nz, heights, normals, corner, cell, levels, bounds})
+    }
+
+    fn point(&self, x: usize, z: usize) -> Vector3 {
+        Vector3::new(self.corner[0] + x as f32 * self.cell.0,
+                     self.heights[z * self.nx + x],
+                     self.corner[2] + z as f32 * self.cell.1)
+    }
+    fn normal(&self, x: usize, z: usize) -> Vector3 {
+        self.normals[z * self.nx + x]
+    }
+
+    /// intersects the two triangles in the cell (x, z)
+    fn collide_cell(&self, ray: &Ray, x: usize, z: usize, t_min: f32, t_max: f32)
+        -> Option<Collision>
+    {
+        let tris = [[(x, z), (x+1, z), (x+1, z+1)],
+                    [(x, z), (x+1, z+1), (x, z+1)]];
+        let mut nearest: Option<Collision> = None;
+        let mut t_max = t_max;
+        for tri in tris.iter() {
+            let p = [self.point(tri[0].0, tri[0].1),
+                     self.point(tri[1].0, tri[1].1),
+                     self.point(tri[2].0, tri[2].1)];
+            if let Some((t, u, v)) = collide_triangle(ray, p, t_min, t_max) {
+                let normal = Vector3::unit(
+                    (1.0 - u - v) * self.normal(tri[0].0, tri[0].1) +
+                                u * self.normal(tri[1].0, tri[1].1) +
+                                v * self.normal(tri[2].0, tri[2].1));
+                let hit = ray.at(t);
+                let uv  = ((hit[0] - self.corner[0]) / (self.cell.0 * (self.nx - 1) as f32),
+                           (hit[2] - self.corner[2]) / (self.cell.1 * (self.nz - 1) as f32));
+                let geometric = Vector3::unit(Vector3::cross(p[1] - p[0], p[2] - p[0]));
+                let geometric = if Vector3::dot(geometric, normal) < 0.0 {-geometric} else {geometric};
+                t_max   = t;
+                nearest = Some(Collision::new(t, normal, uv)
+                               .with_tangent(Vector3::new(1.0, 0.0, 0.0))
+                               .with_geometric(geometric));
+            }
+        }
+        nearest
+    }
+
+    /// walks over the cells of `level` in `[x0, x1) x [z0, z1)` by 2D DDA
+    /// between t0 and t1, and returns the first collision.
+    fn traverse(&self, ray: &Ray, level: usize, (x0, x1): (usize, usize),
+                (z0, z1): (usize, usize), t0: f32, t1: f32) -> Option<Collision>
+    {
+        let mip  = &self.levels[level];
+        let x1   = x1.min(mip.width);
+        let z1   = z1.min(mip.height);
+        let size = (self.cell.0 * (1 << level) as f32,
+                    self.cell.1 * (1 << level) as f32);
+
+        let dir = [ray.direction[0], ray.direction[2]];
+        let org = [ray.origin[0] - self.corner[0], ray.origin[2] - self.corner[2]];
+        let sz  = [size.0, size.1];
+        let lo  = [x0 as isize, z0 as isize];
+        let hi  = [x1 as isize, z1 as isize];
+
+        let start = ray.at(t0);
+        let start = [start[0] - self.corner[0], start[2] - self.corner[2]];
+        let mut cell  = [0isize; 2];
+        let mut step  = [0isize; 2];
+        let mut t_next  = [f32::INFINITY; 2];
+        let mut t_delta = [f32::INFINITY; 2];
+        for i in 0..2 {
+            let c = (start[i] / sz[i]).floor() as isize;
+            cell[i] = c.max(lo[i]).min(hi[i] - 1);
+            if dir[i] > 0.0 {
+                step[i]    = 1;
+                t_next[i]  = ((cell[i] + 1) as f32 * sz[i] - org[i]) / dir[i];
+                t_delta[i] = sz[i] / dir[i];
+            } else if dir[i] < 0.0 {
+                step[i]    = -1;
+                t_next[i]  = (cell[i] as f32 * sz[i] - org[i]) / dir[i];
+                t_delta[i] = -sz[i] / dir[i];
+            }
+        }
+
+        let mut t_enter = t0;
+        while t_enter <= t1 {
+            let t_exit = t_next[0].min(t_next[1]).min(t1);
+            let (cx, cz) = (cell[0] as usize, cell[1] as usize);
+
+            let (lo_y, hi_y) = mip.range[cz * mip.width + cx];
+            let y_in  = ray.origin[1] + ray.direction[1] * t_enter;
+            let y_out = ray.origin[1] + ray.direction[1] * t_exit;
+            if y_in.min(y_out) <= hi_y && lo_y <= y_in.max(y_out) {
+                let found = if level == 0 {
+                    self.collide_cell(ray, cx, cz, t0, t1)
+                } else {
+                    self.traverse(ray, level - 1, (2*cx, 2*cx+2), (2*cz, 2*cz+2),
+                                  t_enter, t_exit)
+                };
+                if found.is_some() {
+                    return found;
+                }
+            }
+
+            let axis = if t_next[0] < t_next[1] {0} else {1};
+            cell[axis]   += step[axis];
+            t_enter       = t_next[axis];
+            t_next[axis] += t_delta[axis];
+            if step[axis] == 0 || cell[axis] < lo[axis] || hi[axis] <= cell[axis] {
+                break;
+            }
+        }
+        None
+    }
+}
+
+impl Collide for Heightfield {
+    fn collide_within(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Collision> {
+        let (t0, t1) = self.bounds.hit(ray, t_min, t_max)?;
+        let top = self.levels.len() - 1;
+        self.traverse(ray, top, (0, 1), (0, 1), t0, t1)
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        Some(self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heightfield::*;
+    use rand::Rng;
+    use rand_core::SeedableRng;
+
+    fn hills() -> Heightfield {
+        let (w, h) = (37, 23);
+        let values = (0..w*h).map(|i| {
+            let (x, z) = ((i % w) as f32, (i / w) as f32);
+            0.5 + 0.25 * (x * 0.3).sin() * (z * 0.4).cos()
+        }).collect();
+        Heightfield::new(&HeightMap::new(w, h, values).unwrap(),
+                         Vector3::new(-2.0, 0.0, -3.0), Vector3::new(4.0, 1.0, 3.0)).unwrap()
+    }
+
+    fn brute_force(hf: &Heightfield, ray: &Ray) -> Option<f32> {
+        let mut nearest: Option<f32> = None;
+        for z in 0..hf.nz-1 {
+            for x in 0..hf.nx-1 {
+                if let Some(cr) = hf.collide_cell(ray, x, z, 0.0001, f32::INFINITY) {
+                    if nearest.map_or(true, |t| cr.t < t) {
+                        nearest = Some(cr.t);
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    #[test]
+    fn flat_ground() {
+        let map = HeightMap::new(3, 3, vec![0.0; 9]).unwrap();
+        let hf  = Heightfield::new(&map, Vector3::new(-1.0, 0.0, -1.0),
+                                   Vector3::new(2.0, 1.0, 2.0)).unwrap();
+        let ray = Ray::new(Vector3::new(0.3, 1.0, 0.2), Vector3::new(0.1, -1.0, 0.0));
+        let cr  = hf.collide_within(&ray, 0.0001, f32::INFINITY).unwrap();
+        assert!((ray.at(cr.t)[1]).abs() < 1e-5);
+        assert!((cr.normal[1] - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn traversal_matches_brute_force() {
+        let hf  = hills();
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        for _ in 0..500 {
+            let org = Vector3::new(rng.gen_range(-3.0f32, 3.0f32),
+                                   rng.gen_range( 0.0f32, 2.0f32),
+                                   rng.gen_range(-4.0f32, 1.0f32));
+            let dst = Vector3::new(rng.gen_range(-2.0f32, 2.0f32),
+                                   rng.gen_range( 0.0f32, 1.0f32),
+                                   rng.gen_range(-3.0f32, 0.0f32));
+            let ray = Ray::new(org, dst - org);
+            let fast = hf.collide_within(&ray, 0.0001, f32::INFINITY).map(|cr| cr.t);
+            let slow = brute_force(&hf, &ray);
+            match (fast, slow) {
+                (Some(a), Some(b)) => assert!((a - b).abs() < 1e-3, "{} {}", a, b),
+                (None, None) => {}
+                (a, b) => panic!("{:?} != {:?}", a, b),
+            }
+        }
+    }
+
+    #[test]
+    fn pgm_16bit() {
+        let mut bytes = b"P5\n# comment\n2 2\n65535\n".to_vec();
+        for v in &[0u16, 65535, 32768, 1] {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        }
+        let map = HeightMap::parse_pgm(&bytes).unwrap();
+        assert_eq!(map.width(),  2);
+        assert_eq!(map.height(), 2);
+        assert_eq!(map.at(0, 0), 0.0);
+        assert_eq!(map.at(1, 0), 1.0);
+        assert!((map.at(0, 1) - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn pfm_rows_from_bottom() {
+        let mut bytes = b"Pf\n2 2\n-1.0\n".to_vec();
+        for v in &[1.0f32, 2.0, 3.0, 4.0] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        let map = HeightMap::parse_pfm(&bytes).unwrap();
+        assert_eq!(map.at(0, 0), 3.0);
+        assert_eq!(map.at(1, 0), 4.0);
+        assert_eq!(map.at(0, 1), 1.0);
+        assert_eq!(map.at(1, 1), 2.0);
+    }
+
+    #[test]
+    fn not_a_pgm() {
+        assert!(HeightMap::parse_pgm(b"P6\n2 2\n255\n").is_err());
+        // the header ends at the end of the file
+        assert!(HeightMap::parse_pgm(b"P5 2 2 255").is_err());
+        assert!(HeightMap::parse_pfm(b"Pf 2 2 -1.0").is_err());
+        assert!(HeightMap::parse_pgm(b"P2 3 1 255 0 1 2").is_err());
+        assert!(HeightMap::parse_pgm(b"P5 18446744073709551615 2 255 ").is_err());
+    }
+
+    #[test]
+    fn invalid_maps() {
+        assert!(HeightMap::new(1, 4, vec![0.0; 4]).is_err());
+        assert!(HeightMap::new(2, 2, vec![0.0; 3]).is_err());
+        let map = HeightMap::new(2, 2, vec![0.0; 4]).unwrap();
+        assert!(Heightfield::new(&map, Vector3::zero(), Vector3::new(1.0, 1.0, 0.0)).is_err());
+        assert!(Heightfield::new(&map, Vector3::zero(), Vector3::new(1.0, 1.0, 1.0)).is_ok());
+    }
+}
@@ -0,0 +1,174 @@
+//! IES LM-63 photometric files.
+//!
+//! Only the type C photometry, the one used by almost all luminaires, is
+//! supported. The vertical angle is measured from the nadir (the direction
+//! the luminaire faces) and the horizontal angle around it.
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IesProfile {
+    vertical:   std::vec::Vec<f32>, // degrees, ascending
+    horizontal: std::vec::Vec<f32>, // degrees, ascending
+    candela:    std::vec::Vec<f32>, // vertical angles are contiguous
+}
+
+
+impl IesProfile {
+    pub fn read<P>(path: P) -> Result<IesProfile>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        IesProfile::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile> {
+        let mut lines = text.lines();
+        // the header and the keywords end with the TILT line
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => {
+                    break line.trim_start()["TILT=".len()..].trim().to_string();
+                }
+                Some(_) => {}
+                None    => {return Err(Error::parse("IES file without TILT"));}
+            }
+        };
+        let mut numbers = lines.flat_map(|l| l.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<f32>());
+        let mut next = || -> Result<f32> {
+            Ok(numbers.next().ok_or_else(|| Error::parse("IES file is too short"))??)
+        };
+        if tilt == "INCLUDE" {
+            // lamp-to-luminaire geometry, then the tilt angles and factors
+            next()?;
+            let n = next()? as usize;
+            for _ in 0..2 * n {
+                next()?;
+            }
+        } else if tilt != "NONE" {
+            return Err(Error::parse("TILT file references are not supported"));
+        }
+
+        let _lamps     = next()?;
+        let _lumens    = next()?;
+        let multiplier = next()?;
+        let n_vertical   = next()? as usize;
+        let n_horizontal = next()? as usize;
+        let photometric  = next()?;
+        let _units = next()?;
+        let (_width, _length, _height) = (next()?, next()?, next()?);
+        let ballast = next()?;
+        let _future = next()?;
+        let _watts  = next()?;
+        if photometric != 1.0 {
+            return Err(Error::parse("only the type C photometry is supported"));
+        }
+        if n_vertical == 0 || n_horizontal == 0 {
+            return Err(Error::parse("IES file without angles"));
+        }
+
+        let vertical   = (0..n_vertical).map(|_| next()).collect::<Result<std::vec::Vec<_>>>()?;
+        let horizontal = (0..n_horizontal).map(|_| next()).collect::<Result<std::vec::Vec<_>>>()?;
+        let candela    = (0..n_vertical * n_horizontal)
+            .map(|_| next().map(|c| c * multiplier * ballast))
+            .collect::<Result<std::vec::Vec<_>>>()?;
+        let ascending = |a: &[f32]| a.windows(2).all(|w| w[0] < w[1]);
+        if !ascending(&vertical) || !ascending(&horizontal) {
+            return Err(Error::parse("IES angles are not ascending"));
+        }
+        Ok(IesProfile{vertical, horizontal, candela})
+    }
+
+    /// the largest intensity in cd.
+    #[cfg(test)]
+    pub fn peak(&self) -> f32 {
+        self.candela.iter().cloned().fold(0.0, f32::max)
+    }
+
+    /// luminous intensity in cd. `theta` is the angle from the nadir and
+    /// `phi` the angle around it, both in degrees.
+    pub fn intensity(&self, theta: f32, phi: f32) -> f32 {
+        let (v0, v1) = (self.vertical[0], self.vertical[self.vertical.len() - 1]);
+        if theta < v0 || v1 < theta {
+            return 0.0;
+        }
+        // fold by the symmetry given by the last horizontal angle
+        let phi  = phi.rem_euclid(360.0);
+        let last = self.horizontal[self.horizontal.len() - 1];
+        let phi  = if last <= 0.0 {
+            0.0
+        } else if last <= 90.0 {
+            let p = if phi > 180.0 {360.0 - phi} else {phi};
+            if p > 90.0 {180.0 - p} else {p}
+        } else if last <= 180.0 {
+            if phi > 180.0 {360.0 - phi} else {phi}
+        } else {
+            phi
+        };
+
+        let (i, s) = locate(&self.vertical, theta);
+        let (j, u) = locate(&self.horizontal, phi);
+        let n = self.vertical.len();
+        let at = |i: usize, j: usize| self.candela[j * n + i];
+        let c0 = at(i, j) * (1.0 - s) + at((i + 1).min(n - 1), j) * s;
+        if u == 0.0 {
+            return c0;
+        }
+        let c1 = at(i, j + 1) * (1.0 - s) + at((i + 1).min(n - 1), j + 1) * s;
+        c0 * (1.0 - u) + c1 * u
+    }
+}
+
+/// index of the interval that contains `x` and the fraction in it.
+fn locate(angles: &[f32], x: f32) -> (usize, f32) {
+    if angles.len() == 1 || x <= angles[0] {
+        return (0, 0.0);
+    }
+    match angles.windows(2).position(|w| x <= w[1]) {
+        Some(i) => {(i, (x - angles[i]) / (angles[i + 1] - angles[i]))}
+        None    => {(angles.len() - 1, 0.0)}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ies::*;
+
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] downlight
+[MANUFAC] none
+TILT=NONE
+1 1000 2.0 5 3 1 2 0.1 0.1 0.0
+1.0 1.0 20
+0 22.5 45 67.5 90
+0 45 90
+500 400 200 50 0
+500 400 200 50 0
+500 300 100 25 0
+";
+
+    #[test]
+    fn parse_and_interpolate() {
+        let ies = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(ies.peak(), 1000.0);
+        assert_eq!(ies.intensity(0.0, 0.0), 1000.0);
+        assert_eq!(ies.intensity(22.5, 0.0), 800.0);
+        assert!((ies.intensity(11.25, 0.0) - 900.0).abs() < 1e-3);
+        // between the horizontal angles 45 and 90
+        assert!((ies.intensity(22.5, 67.5) - 700.0).abs() < 1e-3);
+        // quadrant symmetry
+        assert_eq!(ies.intensity(22.5, 270.0), ies.intensity(22.5, 90.0));
+        assert_eq!(ies.intensity(22.5, 135.0), ies.intensity(22.5, 45.0));
+        // no light above the horizon
+        assert_eq!(ies.intensity(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn malformed_files() {
+        assert!(IesProfile::parse("IESNA:LM-63-2002\n1 2 3").is_err());
+        assert!(IesProfile::parse(&DOWNLIGHT[..DOWNLIGHT.len() - 10]).is_err());
+        assert!(IesProfile::parse(&DOWNLIGHT.replace("TILT=NONE", "TILT=lamp.tlt")).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 //! image stuff
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::color::{Color, RGBA, RGB};
 use crate::util::clamp;
 use std::io::Write;
@@ -94,3 +94,31 @@ impl Image {
         Ok(())
     }
 }
+
+/// reads the first `n` whitespace-separated tokens of a netpbm header,
+/// skipping comments. returns the tokens and the offset of the binary part.
+pub(crate) fn header_tokens(bytes: &[u8], n: usize) -> Result<(std::vec::Vec<std::string::String>, usize)> {
+    let mut tokens = std::vec::Vec::new();
+    let mut i = 0;
+    while tokens.len() < n {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i < bytes.len() && bytes[i] == b'#' {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if start == i {
+            return Err(Error::parse("unexpected end of header"));
+        }
+        tokens.push(std::string::String::from_utf8_lossy(&bytes[start..i]).into_owned());
+    }
+    // exactly one whitespace separates the header and the data
+    Ok((tokens, i + 1))
+}
@@ -4,15 +4,183 @@ mod image;
 mod vector;
 mod ray;
 mod camera;
+mod sampler;
+mod film;
+mod filter;
+mod progressive;
+mod checkpoint;
+mod distributed;
+mod progress;
 mod sphere;
+mod sdf;
+mod heightfield;
+mod mesh;
+mod aabb;
+mod motion;
+mod medium;
+mod voxel;
 mod collide;
 mod material;
+mod microfacet;
+mod thinfilm;
+mod metal;
+mod texture;
+mod bump;
 mod background;
 mod world;
 mod color;
+mod spectrum;
+mod emission;
+mod ies;
 mod object;
+mod scene;
+
+const USAGE: &str = "usage:
+    rustracer                                     renders the example into example.ppm
+    rustracer render      <scene> <out.ppm>
+    rustracer progressive <scene> <out.ppm> [options]
+        --snapshot-passes  <n>    writes the image every n passes
+        --snapshot-seconds <s>    writes the image every s seconds
+        --budget           <s>    stops after the pass that exceeds s seconds
+        --target-spp       <n>    stops when every pixel has n samples
+        --pass-spp         <n>    samples added to a pixel in a pass, 1 by default
+        --checkpoint       <file> writes a checkpoint when it stops
+        --checkpoint-passes  <n>  and every n passes
+        --checkpoint-seconds <s>  and every s seconds
+        --resume                  continues from the checkpoint if there is one,
+                                  with the seed of the checkpoint
+        --heatmap          <file> writes the samples per pixel at the end
+        --progress                shows the progress of the pass on stderr
+    rustracer worker      <address>               e.g. 127.0.0.1:0
+    rustracer coordinate  <scene> <out.ppm> [--timeout <s>] <worker address>...
+        --timeout          <s>    gives a tile to another worker after s seconds";
+
+/// the value of an option, e.g. `--budget 60`.
+fn option_value<T>(name: &str, value: Option<&std::string::String>) -> error::Result<T>
+where
+    T: std::str::FromStr
+{
+    value.and_then(|v| v.parse().ok())
+        .ok_or_else(|| error::Error::parse(format!("{} needs a valid value", name)))
+}
+
+/// overwrites the line on stderr with the progress of the pass.
+fn report_progress(progress: &progress::Progress) {
+    match progress.eta() {
+        Some(eta) => {eprint!("\r{:5.1}% {:6.1}s left ", progress.percentage(), eta.as_secs_f32())}
+        None      => {eprint!("\r{:5.1}%               ", progress.percentage())}
+    }
+    if progress.tiles_done == progress.tiles_total {
+        eprintln!();
+    }
+}
+
+/// refines the image pass by pass and writes it into `out` on the way.
+fn progressive(scene: &str, out: &str, options: &[std::string::String]) -> error::Result<()> {
+    let (camera, world) = scene::read(scene)?;
+    let mut renderer = progressive::Progressive::new();
+    let (mut passes, mut seconds) = (None, None);
+    let (mut checkpoint, mut checkpoint_passes, mut checkpoint_seconds) = (None, None, None);
+    let mut resume  = false;
+    let mut heatmap = None;
+    let mut options = options.iter();
+    while let Some(name) = options.next() {
+        if name == "--resume" {
+            resume = true;
+            continue;
+        }
+        if name == "--progress" {
+            renderer = renderer.with_progress(report_progress);
+            continue;
+        }
+        let value = options.next();
+        match name.as_str() {
+            "--snapshot-passes"  => {passes   = Some(option_value(name, value)?);}
+            "--snapshot-seconds" => {seconds  = Some(option_value(name, value)?);}
+            "--budget"           => {renderer = renderer.with_time_budget(option_value(name, value)?);}
+            "--target-spp"       => {renderer = renderer.with_target_spp(option_value(name, value)?);}
+            "--pass-spp"         => {renderer = renderer.with_pass_spp(option_value(name, value)?);}
+            "--checkpoint"         => {checkpoint = Some(option_value::<std::string::String>(name, value)?);}
+            "--checkpoint-passes"  => {checkpoint_passes  = Some(option_value(name, value)?);}
+            "--checkpoint-seconds" => {checkpoint_seconds = Some(option_value(name, value)?);}
+            "--heatmap"            => {heatmap = Some(option_value::<std::string::String>(name, value)?);}
+            _ => {return Err(error::Error::parse(format!("unknown option: {}", name)));}
+        }
+    }
+    let renderer = renderer.with_snapshot(out, passes, seconds);
+    let film = match checkpoint {
+        None if resume => {return Err(error::Error::parse("--resume needs --checkpoint"));}
+        None => {renderer.render(&camera, &world)?}
+        Some(path) => {
+            let renderer = renderer.with_checkpoint(&path, checkpoint_passes, checkpoint_seconds);
+            if resume && std::path::Path::new(&path).exists() {
+                let checkpoint = checkpoint::Checkpoint::read(&path)?;
+                let camera = camera.with_seed(checkpoint.seed);
+                renderer.resume_from(&camera, &world, checkpoint)?
+            } else {
+                renderer.render(&camera, &world)?
+            }
+        }
+    };
+    match heatmap {
+        Some(path) => {film.heatmap().write_ppm(path)}
+        None       => {Ok(())}
+    }
+}
+
+/// the modes other than the example.
+fn run(args: &[std::string::String]) -> error::Result<()> {
+    match args {
+        [mode, scene, out] if mode == "render" => {
+            let (camera, world) = scene::read(scene)?;
+            camera.render(world).write_ppm(out)
+        }
+        [mode, scene, out, options @ ..] if mode == "progressive" => {
+            progressive(scene, out, options)
+        }
+        [mode, address] if mode == "worker" => {
+            let listener = std::net::TcpListener::bind(address.as_str())?;
+            // the port may be chosen by the system
+            println!("listening on {}", listener.local_addr()?);
+            std::io::Write::flush(&mut std::io::stdout())?;
+            distributed::serve(&listener, scene::parse)
+        }
+        [mode, scene, out, workers @ ..] if mode == "coordinate" && !workers.is_empty() => {
+            let bytes = std::fs::read(scene)?;
+            let (camera, _) = scene::parse(&bytes)?;
+            let (timeout, workers) = match workers {
+                [name, value, workers @ ..] if name == "--timeout" => {
+                    (Some(option_value(name, Some(value))?), workers)
+                }
+                _ => {(None, workers)}
+            };
+            let workers = workers.iter()
+                .map(|w| w.parse().map_err(|_| error::Error::parse(format!("invalid address: {}", w))))
+                .collect::<error::Result<std::vec::Vec<std::net::SocketAddr>>>()?;
+            let coordinator = distributed::Coordinator::new(bytes, workers);
+            let coordinator = match timeout {
+                Some(seconds) => {coordinator.with_timeout(seconds)}
+                None          => {coordinator}
+            };
+            coordinator.render(&camera)?.to_image().write_ppm(out)
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
 
 fn main() {
+    let args: std::vec::Vec<std::string::String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = run(&args) {
+            eprintln!("error: {:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     use crate::vector::Vector3;
     use crate::material::Material;
     use crate::sphere::Sphere;
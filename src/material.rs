@@ -1,23 +1,57 @@
 use crate::vector::*;
-use crate::color::RGB;
+use crate::color::{Color, RGB};
 use crate::collide::Collision;
 use crate::ray::Ray;
 use crate::util::*;
+use crate::microfacet::{GGX, fresnel_conductor_rgb, fresnel_dielectric};
+use crate::metal::{Metal, RGB_WAVELENGTHS};
+use crate::texture::Texture;
+use crate::thinfilm::ThinFilm;
+use crate::spectrum::Dispersion;
 use rand::Rng;
 
 pub trait Scatter {
     /// returns the next ray and the attenuation of the color.
-    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> Ray;
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB);
+
+    /// BSDF times the cosine for the outgoing direction `wi`. It is zero for
+    /// materials that only have delta lobes.
+    #[cfg(test)]
+    fn eval(&self, _ray: &Ray, _cr: &Collision, _wi: Vector3) -> RGB {
+        RGB::new(0.0, 0.0, 0.0)
+    }
+    /// probability density of `scatter` choosing `wi` w.r.t. the solid angle.
+    #[cfg(test)]
+    fn pdf(&self, _ray: &Ray, _cr: &Collision, _wi: Vector3) -> f32 {
+        0.0
+    }
+}
+
+/// local frame around the normal on the side of the ray origin, and the
+/// direction toward the origin in that frame.
+fn local_frame(ray: &Ray, cr: &Collision) -> (Frame, Vector3) {
+    let normal = if Vector3::dot(ray.direction, cr.normal) > 0.0 {
+        -cr.normal
+    } else {
+        cr.normal
+    };
+    let frame = Frame::with_tangent(normal, cr.tangent);
+    (frame, frame.to_local(-ray.direction.unit()))
+}
+
+fn sample_cosine<R: Rng>(rng: &mut R) -> Vector3 {
+    let (x, y) = pick_in_circle(rng);
+    Vector3::new(x, y, (1.0 - x * x - y * y).max(0.0).sqrt())
 }
 
 #[derive(Debug)]
 pub struct Diffuse;
 
 impl Scatter for Diffuse {
-    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> Ray {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
         let start = ray.at(cr.t);
         let dir   = cr.normal + pick_in_sphere(&mut *rng);
-        Ray::new(start, dir)
+        (ray.spawn(start, dir), RGB::new(1.0, 1.0, 1.0))
     }
 }
 
@@ -33,7 +67,7 @@ impl Metalic {
 }
 
 impl Scatter for Metalic {
-    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> Ray {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
         let start = ray.at(cr.t);
         let reflected = if self.fuzziness == 0.0 {
             reflect(ray.direction, cr.normal)
@@ -41,54 +75,748 @@ impl Scatter for Metalic {
             reflect(ray.direction, cr.normal) +
                 self.fuzziness * pick_in_sphere(&mut *rng)
         };
-        Ray::new(start, reflected)
+        (ray.spawn(start, reflected), RGB::new(1.0, 1.0, 1.0))
     }
 }
 
 #[derive(Debug)]
 pub struct Dielectric {
-    refidx: f32,
+    refidx:     f32,
+    film:       Option<ThinFilm>,
+    dispersion: Option<Dispersion>,
 }
 
 impl Dielectric {
     pub fn new(refidx: f32) -> Self {
-        Dielectric{refidx}
+        Dielectric{refidx, film: None, dispersion: None}
+    }
+
+    /// coats the surface with a thin film.
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
     }
 
-    pub fn schlick(&self, cosine: f32) -> f32 {
-        let r0 = (1.0 - self.refidx) / (1.0 + self.refidx);
-        let r0 = r0 * r0;
-        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    /// makes the index of refraction depend on the wavelength. Without the
+    /// spectral mode, the index at the green wavelength is used.
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.refidx     = dispersion.ior(RGB_WAVELENGTHS[1]);
+        self.dispersion = Some(dispersion);
+        self
     }
 }
 
+fn schlick(refidx: f32, cosine: f32) -> f32 {
+    let r0 = (1.0 - refidx) / (1.0 + refidx);
+    let r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+/// wavelengths of the ray, or the representative wavelengths of RGB.
+fn wavelengths_of(ray: &Ray) -> [f32; 3] {
+    ray.wavelengths.map_or(RGB_WAVELENGTHS, |w| w.lambda)
+}
+
 impl Scatter for Dielectric {
-    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> Ray {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
         let start = ray.at(cr.t);
+        // a dispersive refraction separates the wavelengths, so only the
+        // hero wavelength can follow the path.
+        let (refidx, weight, ray) = match (&self.dispersion, &ray.wavelengths) {
+            (Some(d), Some(w)) => {
+                let (weight, w) = w.terminate_secondary();
+                (d.ior(w.hero()), weight, ray.clone().with_wavelengths(w))
+            }
+            _ => (self.refidx, RGB::new(1.0, 1.0, 1.0), ray.clone()),
+        };
+        let entering = Vector3::dot(ray.direction, cr.normal) <= 0.0;
         let (out_normal, ni_over_nt, cosine) =
-            if Vector3::dot(ray.direction, cr.normal) > 0.0 {
-                (-cr.normal,       self.refidx,  ray.direction.dot(cr.normal))
+            if !entering {
+                (-cr.normal,       refidx,  ray.direction.dot(cr.normal))
             } else {
-                ( cr.normal, 1.0 / self.refidx, -ray.direction.dot(cr.normal))
+                ( cr.normal, 1.0 / refidx, -ray.direction.dot(cr.normal))
             };
 
         let reflected = reflect(ray.direction, cr.normal);
         if let Some(refracted) = refract(ray.direction, out_normal, ni_over_nt) {
-            if rng.gen_range(0.0f32, 1.0f32) < self.schlick(cosine) {
-                Ray::new(start, reflected)
+            if let Some(film) = &self.film {
+                // the film is on the outer side of the surface
+                let cosine = cosine / ray.direction.len();
+                let (n1, n3) = if entering {(1.0, refidx)} else {(refidx, 1.0)};
+                let zero = RGB::new(0.0, 0.0, 0.0);
+                let r = film.reflectance_at(cosine, n1, RGB::new(n3, n3, n3), zero,
+                                            wavelengths_of(&ray));
+                let p = clamp((r.r() + r.g() + r.b()) / 3.0, 1e-4, 1.0 - 1e-4);
+                return if rng.gen_range(0.0f32, 1.0f32) < p {
+                    (ray.spawn(start, reflected), weight * r / p)
+                } else {
+                    let t = RGB::new(1.0 - r.r(), 1.0 - r.g(), 1.0 - r.b());
+                    (ray.spawn(start, refracted), weight * t / (1.0 - p))
+                };
+            }
+            if rng.gen_range(0.0f32, 1.0f32) < schlick(refidx, cosine) {
+                (ray.spawn(start, reflected), weight)
             } else {
-                Ray::new(start, refracted)
+                (ray.spawn(start, refracted), weight)
+            }
+        } else {
+            (ray.spawn(start, reflected), weight)
+        }
+    }
+}
+
+/// Oren-Nayar rough diffuse surface. `sigma` is the standard deviation of
+/// the facet angles in radians. `sigma = 0` reduces to Lambertian.
+#[derive(Debug)]
+pub struct OrenNayar {
+    a: f32,
+    b: f32,
+}
+
+impl OrenNayar {
+    pub fn new(sigma: f32) -> Self {
+        let s2 = sigma * sigma;
+        OrenNayar{a: 1.0 - 0.5 * s2 / (s2 + 0.33), b: 0.45 * s2 / (s2 + 0.09)}
+    }
+
+    /// BSDF times pi, in the local frame.
+    fn reflectance(&self, wo: Vector3, wi: Vector3) -> f32 {
+        let sin_o = (1.0 - wo[2] * wo[2]).max(0.0).sqrt();
+        let sin_i = (1.0 - wi[2] * wi[2]).max(0.0).sqrt();
+        let cos_dphi = if sin_o > 1e-4 && sin_i > 1e-4 {
+            ((wo[0] * wi[0] + wo[1] * wi[1]) / (sin_o * sin_i)).max(0.0)
+        } else {
+            0.0
+        };
+        // sin(alpha) tan(beta) where alpha = max(theta), beta = min(theta)
+        let (sin_a, tan_b) = if wi[2] > wo[2] {
+            (sin_o, sin_i / wi[2])
+        } else {
+            (sin_i, sin_o / wo[2].max(1e-4))
+        };
+        self.a + self.b * cos_dphi * sin_a * tan_b
+    }
+}
+
+impl Scatter for OrenNayar {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        let (frame, wo) = local_frame(ray, &cr);
+        let wi = sample_cosine(rng);
+        let r  = self.reflectance(wo, wi);
+        (ray.spawn(ray.at(cr.t), frame.to_world(wi)), RGB::new(r, r, r))
+    }
+    #[cfg(test)]
+    fn eval(&self, ray: &Ray, cr: &Collision, wi: Vector3) -> RGB {
+        let (frame, wo) = local_frame(ray, cr);
+        let wi = frame.to_local(wi.unit());
+        if wi[2] <= 0.0 {
+            return RGB::new(0.0, 0.0, 0.0);
+        }
+        let f = self.reflectance(wo, wi) * wi[2] / std::f32::consts::PI;
+        RGB::new(f, f, f)
+    }
+    #[cfg(test)]
+    fn pdf(&self, ray: &Ray, cr: &Collision, wi: Vector3) -> f32 {
+        let (frame, _) = local_frame(ray, cr);
+        frame.to_local(wi.unit())[2].max(0.0) / std::f32::consts::PI
+    }
+}
+
+/// retro-reflector that sends the light back toward where it came from.
+/// The retro lobe is a normalized cosine power around the incident
+/// direction and `diffuse` is the fraction reflected as Lambertian.
+#[derive(Debug)]
+pub struct RetroReflective {
+    exponent: f32,
+    diffuse:  f32,
+}
+
+impl RetroReflective {
+    pub fn new(exponent: f32, diffuse: f32) -> Self {
+        RetroReflective{exponent: exponent.max(0.0), diffuse: clamp(diffuse, 0.0, 1.0)}
+    }
+
+    fn eval_local(&self, wo: Vector3, wi: Vector3) -> f32 {
+        if wi[2] <= 0.0 {
+            return 0.0;
+        }
+        let n     = self.exponent;
+        let retro = (n + 2.0) / (2.0 * std::f32::consts::PI) *
+                    Vector3::dot(wi, wo).max(0.0).powf(n);
+        (self.diffuse / std::f32::consts::PI + (1.0 - self.diffuse) * retro) * wi[2]
+    }
+
+    fn pdf_local(&self, wo: Vector3, wi: Vector3) -> f32 {
+        let n     = self.exponent;
+        let retro = (n + 1.0) / (2.0 * std::f32::consts::PI) *
+                    Vector3::dot(wi, wo).max(0.0).powf(n);
+        self.diffuse * wi[2].max(0.0) / std::f32::consts::PI + (1.0 - self.diffuse) * retro
+    }
+}
+
+impl Scatter for RetroReflective {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        let (frame, wo) = local_frame(ray, &cr);
+        let wi = if rng.gen_range(0.0f32, 1.0f32) < self.diffuse {
+            sample_cosine(rng)
+        } else {
+            let cos_t = rng.gen_range(0.0f32, 1.0f32).powf(1.0 / (self.exponent + 1.0));
+            let sin_t = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+            let phi   = rng.gen_range(0.0f32, 2.0 * std::f32::consts::PI);
+            Frame::new(wo).to_world(Vector3::new(sin_t * phi.cos(), sin_t * phi.sin(), cos_t))
+        };
+        let next = ray.spawn(ray.at(cr.t), frame.to_world(wi));
+        let pdf  = self.pdf_local(wo, wi);
+        if pdf <= 0.0 {
+            return (next, RGB::new(0.0, 0.0, 0.0));
+        }
+        let w = self.eval_local(wo, wi) / pdf;
+        (next, RGB::new(w, w, w))
+    }
+    #[cfg(test)]
+    fn eval(&self, ray: &Ray, cr: &Collision, wi: Vector3) -> RGB {
+        let (frame, wo) = local_frame(ray, cr);
+        let f = self.eval_local(wo, frame.to_local(wi.unit()));
+        RGB::new(f, f, f)
+    }
+    #[cfg(test)]
+    fn pdf(&self, ray: &Ray, cr: &Collision, wi: Vector3) -> f32 {
+        let (frame, wo) = local_frame(ray, cr);
+        self.pdf_local(wo, frame.to_local(wi.unit()))
+    }
+}
+
+/// microfacet conductor with the GGX distribution. The Fresnel term is
+/// computed from the complex index of refraction.
+#[derive(Debug)]
+pub struct Conductor {
+    eta:   RGB,
+    k:     RGB,
+    ggx:   GGX,
+    film:  Option<ThinFilm>,
+    metal: Option<Metal>, // for the index at arbitrary wavelengths
+}
+
+impl Conductor {
+    /// `alpha_x` and `alpha_y` are the roughness along the two tangents.
+    pub fn new(eta: RGB, k: RGB, alpha_x: f32, alpha_y: f32) -> Self {
+        Conductor{eta, k, ggx: GGX::new(alpha_x, alpha_y), film: None, metal: None}
+    }
+    pub fn from_metal(metal: Metal, roughness: f32) -> Self {
+        let (eta, k) = metal.ior_rgb();
+        Conductor{eta, k, ggx: GGX::from_roughness(clamp(roughness, 0.0, 1.0)), film: None,
+                  metal: Some(metal)}
+    }
+
+    /// coats the surface with a thin film, e.g. an oxide layer.
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    fn fresnel(&self, cos_i: f32, ray: &Ray) -> RGB {
+        let lambda = wavelengths_of(ray);
+        let (eta, k) = match (&self.metal, &ray.wavelengths) {
+            (Some(metal), Some(_)) => {
+                let ior = [metal.ior_at(lambda[0]), metal.ior_at(lambda[1]), metal.ior_at(lambda[2])];
+                (RGB::new(ior[0].0, ior[1].0, ior[2].0), RGB::new(ior[0].1, ior[1].1, ior[2].1))
+            }
+            _ => (self.eta, self.k),
+        };
+        match &self.film {
+            None       => {fresnel_conductor_rgb(cos_i, eta, k)}
+            Some(film) => {film.reflectance_at(cos_i, 1.0, eta, k, lambda)}
+        }
+    }
+}
+
+impl Scatter for Conductor {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        let start = ray.at(cr.t);
+        let normal = if Vector3::dot(ray.direction, cr.normal) > 0.0 {
+            -cr.normal
+        } else {
+            cr.normal
+        };
+        let frame = Frame::with_tangent(normal, cr.tangent);
+        let wo    = frame.to_local(-ray.direction);
+
+        if self.ggx.is_smooth() {
+            let f = self.fresnel(wo[2], ray);
+            return (ray.spawn(start, reflect(ray.direction, normal)), f);
+        }
+
+        let h  = self.ggx.sample_visible(wo, rng.gen_range(0.0f32, 1.0f32),
+                                             rng.gen_range(0.0f32, 1.0f32));
+        let wi = reflect(-wo, h);
+        let next = ray.spawn(start, frame.to_world(wi));
+        if wi[2] <= 0.0 {
+            return (next, RGB::new(0.0, 0.0, 0.0));
+        }
+        let f = self.fresnel(Vector3::dot(wo, h), ray);
+        (next, f * (self.ggx.g2(wo, wi) / self.ggx.g1(wo)))
+    }
+}
+
+/// rough dielectric with the GGX distribution for both reflection and
+/// refraction. The light inside is absorbed following the Beer-Lambert law.
+#[derive(Debug)]
+pub struct RoughDielectric {
+    refidx:     f32,
+    ggx:        GGX,
+    absorption: RGB, // per unit length
+}
+
+impl RoughDielectric {
+    pub fn new(refidx: f32, roughness: f32, absorption: RGB) -> Self {
+        RoughDielectric{refidx, ggx: GGX::from_roughness(clamp(roughness, 0.0, 1.0)),
+                        absorption}
+    }
+}
+
+impl Scatter for RoughDielectric {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        let start    = ray.at(cr.t);
+        let entering = Vector3::dot(ray.direction, cr.normal) < 0.0;
+        let (normal, eta) = if entering {
+            ( cr.normal, self.refidx)
+        } else {
+            (-cr.normal, 1.0 / self.refidx)
+        };
+        // the ray has travelled inside of the medium
+        let transmittance = if entering {
+            RGB::new(1.0, 1.0, 1.0)
+        } else {
+            let absorption = ray.tint(self.absorption);
+            RGB::new((-absorption.r() * cr.t).exp(),
+                     (-absorption.g() * cr.t).exp(),
+                     (-absorption.b() * cr.t).exp())
+        };
+
+        let frame = Frame::with_tangent(normal, cr.tangent);
+        let wo = frame.to_local(-ray.direction);
+        let (h, smooth) = if self.ggx.is_smooth() {
+            (Vector3::new(0.0, 0.0, 1.0), true)
+        } else {
+            (self.ggx.sample_visible(wo, rng.gen_range(0.0f32, 1.0f32),
+                                         rng.gen_range(0.0f32, 1.0f32)), false)
+        };
+
+        // reflection and refraction are chosen by the Fresnel term, so it
+        // cancels out from the weight.
+        let cos_o = Vector3::dot(wo, h);
+        let f  = fresnel_dielectric(cos_o, eta);
+        let wi = if rng.gen_range(0.0f32, 1.0f32) < f {
+            reflect(-wo, h)
+        } else {
+            match refract(-wo, h, 1.0 / eta) {
+                Some(wi) => wi,
+                None     => reflect(-wo, h),
             }
+        };
+        let next = ray.spawn(start, frame.to_world(wi));
+
+        let reflected = wi[2] > 0.0;
+        let flipped   = (Vector3::dot(wi, h) > 0.0) != reflected;
+        if flipped {
+            // the sampled direction goes into the wrong side of the surface
+            return (next, RGB::new(0.0, 0.0, 0.0));
+        }
+        if smooth {
+            (next, transmittance)
         } else {
-            Ray::new(start, reflected)
+            (next, transmittance * (self.ggx.g2(wo, wi) / self.ggx.g1(wo)))
         }
     }
 }
 
+/// Disney-style principled material. All the parameters are textures, so
+/// they can vary over the surface. Scalar parameters use the red channel.
+#[derive(Debug)]
+pub struct Principled {
+    base_color:      Texture,
+    metallic:        Texture,
+    roughness:       Texture,
+    specular:        Texture,
+    sheen:           Texture,
+    clearcoat:       Texture,
+    clearcoat_gloss: Texture,
+    transmission:    Texture,
+    subsurface:      Texture,
+    ior:             Texture,
+}
+
+impl Principled {
+    pub fn new<T: Into<Texture>>(base_color: T) -> Self {
+        Principled{
+            base_color:      base_color.into(),
+            metallic:        Texture::from(0.0),
+            roughness:       Texture::from(0.5),
+            specular:        Texture::from(0.5),
+            sheen:           Texture::from(0.0),
+            clearcoat:       Texture::from(0.0),
+            clearcoat_gloss: Texture::from(1.0),
+            transmission:    Texture::from(0.0),
+            subsurface:      Texture::from(0.0),
+            ior:             Texture::from(1.5),
+        }
+    }
+    pub fn metallic<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.metallic = t.into(); self
+    }
+    pub fn roughness<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.roughness = t.into(); self
+    }
+    /// 0.5 corresponds to the reflectance 4% at normal incidence.
+    pub fn specular<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.specular = t.into(); self
+    }
+    /// color of the retro-reflection at grazing angles, e.g. for cloth.
+    pub fn sheen<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.sheen = t.into(); self
+    }
+    pub fn clearcoat<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.clearcoat = t.into(); self
+    }
+    pub fn clearcoat_gloss<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.clearcoat_gloss = t.into(); self
+    }
+    pub fn transmission<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.transmission = t.into(); self
+    }
+    /// blends the diffuse lobe toward the Hanrahan-Krueger-like flattening.
+    pub fn subsurface<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.subsurface = t.into(); self
+    }
+    /// index of refraction of the transmissive part.
+    pub fn ior<T: Into<Texture>>(mut self, t: T) -> Self {
+        self.ior = t.into(); self
+    }
+}
+
+fn schlick_weight(cosine: f32) -> f32 {
+    (1.0 - clamp(cosine, 0.0, 1.0)).powi(5)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+fn lerp_rgb(a: RGB, b: RGB, t: f32) -> RGB {
+    a * (1.0 - t) + b * t
+}
+
+impl Scatter for Principled {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        let start = ray.at(cr.t);
+        let (uv, p) = (cr.uv, start);
+        let white = RGB::new(1.0, 1.0, 1.0);
+        let black = RGB::new(0.0, 0.0, 0.0);
+
+        let base         = ray.tint(self.base_color.value(uv, p));
+        let metallic     = clamp(self.metallic.scalar(uv, p),     0.0, 1.0);
+        let roughness    = clamp(self.roughness.scalar(uv, p),    0.0, 1.0);
+        let transmission = clamp(self.transmission.scalar(uv, p), 0.0, 1.0);
+        let ior          = self.ior.scalar(uv, p).max(1.0);
+        let ggx          = GGX::from_roughness(roughness);
+
+        let entering = Vector3::dot(ray.direction, cr.normal) < 0.0;
+        let normal   = if entering {cr.normal} else {-cr.normal};
+        let frame    = Frame::with_tangent(normal, cr.tangent);
+        let wo       = frame.to_local(-ray.direction);
+        let sample_h = |ggx: &GGX, rng: &mut R| if ggx.is_smooth() {
+            Vector3::new(0.0, 0.0, 1.0)
+        } else {
+            ggx.sample_visible(wo, rng.gen_range(0.0f32, 1.0f32), rng.gen_range(0.0f32, 1.0f32))
+        };
+        let shadowing = |ggx: &GGX, wi: Vector3| if ggx.is_smooth() {
+            1.0
+        } else {
+            ggx.g2(wo, wi) / ggx.g1(wo)
+        };
+
+        // lobes are selected in proportion to their weights, so the weight
+        // of the sampled lobe becomes the sum of the weights.
+        let w_diffuse   = (1.0 - metallic) * (1.0 - transmission);
+        let w_transmit  = (1.0 - metallic) * transmission;
+
+        if !entering && w_transmit > 0.0 {
+            // leaving the transmissive part, it is a rough dielectric boundary.
+            // an opaque surface looks the same from both sides.
+            let h  = sample_h(&ggx, rng);
+            let f  = fresnel_dielectric(Vector3::dot(wo, h), 1.0 / ior);
+            let wi = if rng.gen_range(0.0f32, 1.0f32) < f {
+                reflect(-wo, h)
+            } else {
+                refract(-wo, h, ior).unwrap_or_else(|| reflect(-wo, h))
+            };
+            let next = ray.spawn(start, frame.to_world(wi));
+            return (next, white * shadowing(&ggx, wi));
+        }
+
+        let w_specular  = 1.0 - w_transmit;
+        let w_clearcoat = 0.25 * clamp(self.clearcoat.scalar(uv, p), 0.0, 1.0);
+        let w_total     = w_diffuse + w_transmit + w_specular + w_clearcoat;
+        let u = rng.gen_range(0.0f32, 1.0f32) * w_total;
+
+        if u < w_diffuse {
+            let wi = sample_cosine(rng);
+            let next = ray.spawn(start, frame.to_world(wi));
+            let cos_d = Vector3::dot(wi, Vector3::unit(wi + wo));
+            let (fl, fv) = (schlick_weight(wi[2]), schlick_weight(wo[2]));
+
+            let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+            let fd   = lerp(1.0, fd90, fl) * lerp(1.0, fd90, fv);
+            let fss90 = roughness * cos_d * cos_d;
+            let fss  = lerp(1.0, fss90, fl) * lerp(1.0, fss90, fv);
+            let ss   = 1.25 * (fss * (1.0 / (wi[2] + wo[2]).max(1e-4) - 0.5) + 0.5);
+            let subsurface = clamp(self.subsurface.scalar(uv, p), 0.0, 1.0);
+
+            // cosine sampling cancels the cosine and the 1/pi of the lobes
+            let sheen = ray.tint(self.sheen.value(uv, p)) * (schlick_weight(cos_d) * std::f32::consts::PI);
+            let f = base * lerp(fd, ss, subsurface) + sheen;
+            return (next, f * w_total);
+        }
+
+        if u < w_diffuse + w_transmit {
+            let h    = sample_h(&ggx, rng);
+            let f    = fresnel_dielectric(Vector3::dot(wo, h), ior);
+            let (wi, tint) = if rng.gen_range(0.0f32, 1.0f32) < f {
+                (reflect(-wo, h), white)
+            } else {
+                match refract(-wo, h, 1.0 / ior) {
+                    Some(wi) => (wi, base),
+                    None     => (reflect(-wo, h), white),
+                }
+            };
+            let next = ray.spawn(start, frame.to_world(wi));
+            if (Vector3::dot(wi, h) > 0.0) != (wi[2] > 0.0) {
+                return (next, black);
+            }
+            return (next, tint * (shadowing(&ggx, wi) * w_total));
+        }
+
+        let clearcoat = u >= w_diffuse + w_transmit + w_specular;
+        let (ggx, f0) = if clearcoat {
+            let gloss = clamp(self.clearcoat_gloss.scalar(uv, p), 0.0, 1.0);
+            let alpha = lerp(0.1, 0.001, gloss);
+            (GGX::new(alpha, alpha), RGB::new(0.04, 0.04, 0.04))
+        } else {
+            let specular = 0.08 * self.specular.scalar(uv, p).max(0.0);
+            let f0 = lerp_rgb(RGB::new(specular, specular, specular), base, metallic);
+            (ggx, f0)
+        };
+        let h  = sample_h(&ggx, rng);
+        let wi = reflect(-wo, h);
+        let next = ray.spawn(start, frame.to_world(wi));
+        if wi[2] <= 0.0 {
+            return (next, black);
+        }
+        let f = lerp_rgb(f0, white, schlick_weight(Vector3::dot(wo, h)));
+        (next, f * (shadowing(&ggx, wi) * w_total))
+    }
+}
+
+/// smooth dielectric coat over another material, e.g. car paint or
+/// varnished wood. The light refracted into the coat is absorbed on the way
+/// to the base and back, and it bounces between the base and the coat until
+/// it escapes or russian roulette ends it. The coat is assumed to be thin,
+/// so the base is evaluated at the same point. `color` tints the light
+/// reflected by the base; the reflection on the coat itself is not
+/// affected by it.
+#[derive(Debug)]
+pub struct Layered {
+    base:       Box<Material>,
+    color:      RGB,
+    refidx:     f32,
+    thickness:  f32,
+    absorption: RGB, // per unit length
+}
+
+impl Layered {
+    pub fn new(base: Material, color: RGB, refidx: f32, thickness: f32, absorption: RGB) -> Self {
+        Layered{base: Box::new(base), color, refidx, thickness: thickness.max(0.0), absorption}
+    }
+
+    /// transmittance of the coat along a direction with `cos_t` to the normal
+    fn coat_transmittance(&self, cos_t: f32, ray: &Ray) -> RGB {
+        let len = self.thickness / cos_t.abs().max(1e-4);
+        let absorption = ray.tint(self.absorption);
+        RGB::new((-absorption.r() * len).exp(),
+                 (-absorption.g() * len).exp(),
+                 (-absorption.b() * len).exp())
+    }
+}
+
+impl Scatter for Layered {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        const ROULETTE_AFTER: usize = 8; // bounces inside the coat
+        if Vector3::dot(ray.direction, cr.normal) > 0.0 {
+            // hit from the back side. the coat is only on the front.
+            return self.base.scatter(ray, cr, rng);
+        }
+        let start  = ray.at(cr.t);
+        let normal = cr.normal;
+        let dir    = ray.direction.unit();
+
+        let f = fresnel_dielectric(-Vector3::dot(dir, normal), self.refidx);
+        if rng.gen_range(0.0f32, 1.0f32) < f {
+            return (ray.spawn(start, reflect(dir, normal)), RGB::new(1.0, 1.0, 1.0));
+        }
+        let mut inside = match refract(dir, normal, 1.0 / self.refidx) {
+            Some(d) => d.unit(),
+            None    => {
+                return (ray.spawn(start, reflect(dir, normal)), RGB::new(1.0, 1.0, 1.0));
+            }
+        };
+
+        let mut weight = RGB::new(1.0, 1.0, 1.0);
+        let mut bounce = 0;
+        loop {
+            // down to the base. it is at the same point, so the ray starts
+            // there and hits it at t = 0.
+            weight *= self.coat_transmittance(Vector3::dot(inside, normal), ray);
+            let arriving   = ray.spawn(start, inside);
+            let at_base    = Collision{t: 0.0, normal, uv: cr.uv, tangent: cr.tangent,
+                                       geometric: cr.geometric};
+            let (next, w)  = self.base.scatter(&arriving, at_base, rng);
+            weight *= ray.tint(self.color) * w;
+
+            let up = next.direction.unit();
+            if Vector3::dot(up, normal) <= 0.0 {
+                // transmitted through the base
+                return (ray.spawn(start, up), weight);
+            }
+            // up to the coat
+            weight *= self.coat_transmittance(Vector3::dot(up, normal), ray);
+            let f = fresnel_dielectric(-Vector3::dot(up, normal), self.refidx);
+            if rng.gen_range(0.0f32, 1.0f32) >= f {
+                if let Some(out) = refract(up, -normal, self.refidx) {
+                    return (ray.spawn(start, out), weight);
+                }
+            }
+            inside = reflect(up, normal);
+
+            // russian roulette, so that no energy is cut off
+            bounce += 1;
+            if bounce >= ROULETTE_AFTER {
+                let q = weight.r().max(weight.g()).max(weight.b()).min(0.95);
+                if rng.gen_range(0.0f32, 1.0f32) >= q {
+                    return (ray.spawn(start, reflect(dir, normal)), RGB::new(0.0, 0.0, 0.0));
+                }
+                weight /= q;
+            }
+        }
+    }
+}
+
+/// Lambertian sheet that reflects `reflectance` to the side of the ray and
+/// transmits `transmittance` to the other side, e.g. paper or a leaf.
+#[derive(Debug)]
+pub struct DiffuseTransmission {
+    reflectance:   RGB,
+    transmittance: RGB,
+}
+
+impl DiffuseTransmission {
+    pub fn new(reflectance: RGB, transmittance: RGB) -> Self {
+        DiffuseTransmission{reflectance, transmittance}
+    }
+
+    /// probability of sampling the transmission lobe.
+    fn transmit_probability(&self) -> f32 {
+        let r = self.reflectance.r()   + self.reflectance.g()   + self.reflectance.b();
+        let t = self.transmittance.r() + self.transmittance.g() + self.transmittance.b();
+        if r + t > 0.0 {t / (r + t)} else {0.5}
+    }
+}
+
+impl Scatter for DiffuseTransmission {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        let (frame, _) = local_frame(ray, &cr);
+        let wi = sample_cosine(rng);
+        let p  = self.transmit_probability();
+        let start = ray.at(cr.t);
+        if rng.gen_range(0.0f32, 1.0f32) < p {
+            let wi = Vector3::new(wi[0], wi[1], -wi[2]);
+            (ray.spawn(start, frame.to_world(wi)), self.transmittance / p)
+        } else {
+            (ray.spawn(start, frame.to_world(wi)), self.reflectance / (1.0 - p))
+        }
+    }
+    #[cfg(test)]
+    fn eval(&self, ray: &Ray, cr: &Collision, wi: Vector3) -> RGB {
+        let (frame, _) = local_frame(ray, cr);
+        let cos = frame.to_local(wi.unit())[2];
+        let color = if cos >= 0.0 {self.reflectance} else {self.transmittance};
+        color * (cos.abs() / std::f32::consts::PI)
+    }
+    #[cfg(test)]
+    fn pdf(&self, ray: &Ray, cr: &Collision, wi: Vector3) -> f32 {
+        let (frame, _) = local_frame(ray, cr);
+        let cos = frame.to_local(wi.unit())[2];
+        let p   = self.transmit_probability();
+        let lobe = if cos >= 0.0 {1.0 - p} else {p};
+        lobe * cos.abs() / std::f32::consts::PI
+    }
+}
+
+/// infinitely thin dielectric slab, e.g. a window pane or a bubble. The
+/// transmitted ray keeps its direction, and the reflectance includes the
+/// inter-reflections inside the slab.
+#[derive(Debug)]
+pub struct ThinDielectric {
+    refidx: f32,
+}
+
+impl ThinDielectric {
+    pub fn new(refidx: f32) -> Self {
+        ThinDielectric{refidx}
+    }
+
+    pub fn reflectance(&self, cosine: f32) -> f32 {
+        let r = fresnel_dielectric(cosine.abs(), self.refidx);
+        if r < 1.0 {2.0 * r / (1.0 + r)} else {1.0}
+    }
+}
+
+impl Scatter for ThinDielectric {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        let (frame, wo) = local_frame(ray, &cr);
+        let start = ray.at(cr.t);
+        if rng.gen_range(0.0f32, 1.0f32) < self.reflectance(wo[2]) {
+            (ray.spawn(start, reflect(ray.direction, frame.to_world(Vector3::new(0.0, 0.0, 1.0)))),
+             RGB::new(1.0, 1.0, 1.0))
+        } else {
+            (ray.spawn(start, ray.direction), RGB::new(1.0, 1.0, 1.0))
+        }
+    }
+}
+
+/// invisible boundary. It is used to make a shape contain a medium.
+#[derive(Debug)]
+pub struct Interface;
+
+impl Scatter for Interface {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, _rng: &mut R) -> (Ray, RGB) {
+        (ray.spawn(ray.at(cr.t), ray.direction), RGB::new(1.0, 1.0, 1.0))
+    }
+}
+
+#[derive(Debug)]
 pub enum Material {
     Diffuse(Diffuse),
     Metalic(Metalic),
     Dielectric(Dielectric),
+    OrenNayar(OrenNayar),
+    RetroReflective(RetroReflective),
+    Conductor(Conductor),
+    RoughDielectric(RoughDielectric),
+    Principled(Box<Principled>),
+    Layered(Layered),
+    DiffuseTransmission(DiffuseTransmission),
+    ThinDielectric(ThinDielectric),
+    Interface(Interface),
 }
 
 impl Material {
@@ -101,14 +829,425 @@ impl Material {
     pub fn make_dielectric(n: f32) -> Self {
         Material::Dielectric(Dielectric::new(n))
     }
+    /// dielectric whose index depends on the wavelength, e.g. a prism.
+    pub fn make_dispersive(dispersion: Dispersion) -> Self {
+        Material::Dielectric(Dielectric::new(1.0).with_dispersion(dispersion))
+    }
+    /// dielectric coated with a thin film, e.g. a soap bubble.
+    pub fn make_film_dielectric(n: f32, film: ThinFilm) -> Self {
+        Material::Dielectric(Dielectric::new(n).with_film(film))
+    }
+    /// `sigma` is the roughness in radians.
+    pub fn make_oren_nayar(sigma: f32) -> Self {
+        Material::OrenNayar(OrenNayar::new(sigma))
+    }
+    pub fn make_retro_reflective(exponent: f32, diffuse: f32) -> Self {
+        Material::RetroReflective(RetroReflective::new(exponent, diffuse))
+    }
+    pub fn make_conductor(eta: RGB, k: RGB, alpha_x: f32, alpha_y: f32) -> Self {
+        Material::Conductor(Conductor::new(eta, k, alpha_x, alpha_y))
+    }
+    pub fn make_metal(metal: Metal, roughness: f32) -> Self {
+        Material::Conductor(Conductor::from_metal(metal, roughness))
+    }
+    pub fn make_film_metal(metal: Metal, roughness: f32, film: ThinFilm) -> Self {
+        Material::Conductor(Conductor::from_metal(metal, roughness).with_film(film))
+    }
+    /// `absorption` is the absorption coefficient per unit length inside.
+    pub fn make_rough_dielectric(n: f32, roughness: f32, absorption: RGB) -> Self {
+        Material::RoughDielectric(RoughDielectric::new(n, roughness, absorption))
+    }
+    pub fn make_principled(principled: Principled) -> Self {
+        Material::Principled(Box::new(principled))
+    }
+    /// coats `base` with a dielectric layer of `thickness`.
+    pub fn make_layered(base: Material, color: RGB, n: f32, thickness: f32, absorption: RGB) -> Self {
+        Material::Layered(Layered::new(base, color, n, thickness, absorption))
+    }
+    pub fn make_diffuse_transmission(reflectance: RGB, transmittance: RGB) -> Self {
+        Material::DiffuseTransmission(DiffuseTransmission::new(reflectance, transmittance))
+    }
+    pub fn make_thin_dielectric(n: f32) -> Self {
+        Material::ThinDielectric(ThinDielectric::new(n))
+    }
+    pub fn make_interface() -> Self {
+        Material::Interface(Interface)
+    }
 }
 
 impl Scatter for Material {
-    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> Ray {
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
         match self {
             Material::Diffuse(mt)    => {mt.scatter(ray, cr, rng)}
             Material::Metalic(mt)    => {mt.scatter(ray, cr, rng)}
             Material::Dielectric(mt) => {mt.scatter(ray, cr, rng)}
+            Material::OrenNayar(mt)  => {mt.scatter(ray, cr, rng)}
+            Material::RetroReflective(mt) => {mt.scatter(ray, cr, rng)}
+            Material::Conductor(mt)  => {mt.scatter(ray, cr, rng)}
+            Material::RoughDielectric(mt) => {mt.scatter(ray, cr, rng)}
+            Material::Principled(mt) => {mt.scatter(ray, cr, rng)}
+            Material::Layered(mt)    => {mt.scatter(ray, cr, rng)}
+            Material::DiffuseTransmission(mt) => {mt.scatter(ray, cr, rng)}
+            Material::ThinDielectric(mt) => {mt.scatter(ray, cr, rng)}
+            Material::Interface(mt)  => {mt.scatter(ray, cr, rng)}
+        }
+    }
+    #[cfg(test)]
+    fn eval(&self, ray: &Ray, cr: &Collision, wi: Vector3) -> RGB {
+        match self {
+            Material::OrenNayar(mt)       => {mt.eval(ray, cr, wi)}
+            Material::RetroReflective(mt) => {mt.eval(ray, cr, wi)}
+            Material::DiffuseTransmission(mt) => {mt.eval(ray, cr, wi)}
+            _ => {RGB::new(0.0, 0.0, 0.0)}
+        }
+    }
+    #[cfg(test)]
+    fn pdf(&self, ray: &Ray, cr: &Collision, wi: Vector3) -> f32 {
+        match self {
+            Material::OrenNayar(mt)       => {mt.pdf(ray, cr, wi)}
+            Material::RetroReflective(mt) => {mt.pdf(ray, cr, wi)}
+            Material::DiffuseTransmission(mt) => {mt.pdf(ray, cr, wi)}
+            _ => {0.0}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::material::*;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn smooth_rough_dielectric_follows_snell() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let mt  = RoughDielectric::new(1.5, 0.0, RGB::new(0.0, 0.0, 0.0));
+        let dir = Vector3::new(1.0, -1.0, 0.0).unit();
+        let ray = Ray::new(Vector3::new(-1.0, 1.0, 0.0), dir);
+        let n   = Vector3::new(0.0, 1.0, 0.0);
+        for _ in 0..100 {
+            let cr = Collision::new(2.0f32.sqrt(), n, (0.0, 0.0));
+            let (next, w) = mt.scatter(&ray, cr, &mut rng);
+            assert_eq!(w, RGB::new(1.0, 1.0, 1.0));
+            let d = next.direction;
+            if d[1] > 0.0 {
+                assert!((d - reflect(dir, n)).len() < 1e-4);
+            } else {
+                // sin(45 deg) = 1.5 sin(theta_t)
+                let sin_t = (d[0] * d[0] + d[2] * d[2]).sqrt();
+                assert!((sin_t * 1.5 - 0.5f32.sqrt()).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn anisotropy_follows_the_tangent() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(31);
+        let mt  = Conductor::new(RGB::new(0.2, 0.2, 0.2), RGB::new(3.0, 3.0, 3.0), 0.05, 0.5);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        for &tangent in [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0)].iter() {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0))
+                .with_tangent(tangent);
+            let (mut along, mut across) = (0.0, 0.0);
+            for _ in 0..1000 {
+                let d = mt.scatter(&ray, cr, &mut rng).0.direction.unit();
+                along  += Vector3::dot(d, tangent).abs();
+                across += Vector3::dot(d, Vector3::cross(tangent, cr.normal)).abs();
+            }
+            // the lobe is narrow along the tangent
+            assert!(along * 4.0 < across, "{} {}", along, across);
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_conserves_energy() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(987654321);
+        let mt  = RoughDielectric::new(1.5, 0.5, RGB::new(0.0, 0.0, 0.0));
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.3, -1.0, 0.1));
+        let n   = 10000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+            let (_, w) = mt.scatter(&ray, cr, &mut rng);
+            assert!(w.r() <= 1.0);
+            sum += w.r();
+        }
+        let albedo = sum / n as f32;
+        assert!(0.9 < albedo && albedo <= 1.0, "{}", albedo);
+    }
+
+    #[test]
+    fn absorption_inside() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let mt  = RoughDielectric::new(1.0, 0.0, RGB::new(1.0, 0.5, 0.0));
+        // going out of the medium after travelling 2.0
+        let ray = Ray::new(Vector3::zero(), Vector3::new(0.0, 1.0, 0.0));
+        let cr  = Collision::new(2.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+        let (_, w) = mt.scatter(&ray, cr, &mut rng);
+        assert!((w.r() - (-2.0f32).exp()).abs() < 1e-5);
+        assert!((w.g() - (-1.0f32).exp()).abs() < 1e-5);
+        assert_eq!(w.b(), 1.0);
+    }
+
+    fn principled_albedo(mt: &Principled, n: usize) -> RGB {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.3, -1.0, 0.1));
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+            let (_, w) = mt.scatter(&ray, cr, &mut rng);
+            sum += w;
+        }
+        sum / n as f32
+    }
+
+    #[test]
+    fn principled_metal_conserves_energy() {
+        let mt = Principled::new(RGB::new(1.0, 1.0, 1.0)).metallic(1.0).roughness(0.5);
+        let albedo = principled_albedo(&mt, 10000);
+        assert!(0.85 < albedo.r() && albedo.r() <= 1.0, "{:?}", albedo);
+    }
+
+    #[test]
+    fn principled_parameters_are_textures() {
+        let checker = Texture::make_checker(RGB::new(1.0, 1.0, 1.0), RGB::new(0.0, 0.0, 0.0), 1.0);
+        let mt = Principled::new(RGB::new(0.5, 0.5, 0.5)).metallic(checker).roughness(0.0);
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let n = Vector3::new(0.0, 1.0, 0.0);
+        // metallic = 1 at the origin cell: only the mirror reflection remains
+        let ray = Ray::new(Vector3::new(0.5, 1.5, 0.5), Vector3::new(0.0, -1.0, 0.0));
+        for _ in 0..100 {
+            let cr = Collision::new(1.0, n, (0.0, 0.0));
+            let (next, _) = mt.scatter(&ray, cr, &mut rng);
+            assert!((next.direction - n).len() < 1e-3);
+        }
+        // metallic = 0 at the neighbor: the diffuse lobe spreads the rays
+        let ray = Ray::new(Vector3::new(1.5, 1.5, 0.5), Vector3::new(0.0, -1.0, 0.0));
+        let spread = (0..100).any(|_| {
+            let cr = Collision::new(1.0, n, (0.0, 0.0));
+            let (next, _) = mt.scatter(&ray, cr, &mut rng);
+            (next.direction - n).len() > 1e-3
+        });
+        assert!(spread);
+    }
+
+    #[test]
+    fn principled_transmission_passes_light() {
+        let mt = Principled::new(RGB::new(1.0, 1.0, 1.0)).transmission(1.0).roughness(0.0);
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let through = (0..1000).filter(|_| {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+            let (next, _) = mt.scatter(&ray, cr, &mut rng);
+            next.direction[1] < 0.0
+        }).count();
+        assert!(through > 900, "{}", through);
+    }
+
+    #[test]
+    fn principled_ior_is_a_texture() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(7);
+        let dir = Vector3::new(1.0, -1.0, 0.0).unit();
+        let ray = Ray::new(Vector3::new(-1.0, 1.0, 0.0), dir);
+        let n   = Vector3::new(0.0, 1.0, 0.0);
+        for &(ior, bends) in [(1.0, false), (1.5, true)].iter() {
+            let mt = Principled::new(RGB::new(1.0, 1.0, 1.0)).transmission(1.0).roughness(0.0)
+                .specular(0.0).ior(Texture::from(ior));
+            let d = (0..100).map(|_| mt.scatter(&ray, Collision::new(1.0, n, (0.0, 0.0)), &mut rng).0)
+                .find(|next| next.direction[1] < 0.0).unwrap().direction.unit();
+            assert_eq!((d - dir).len() > 1e-3, bends, "{:?}", d);
+        }
+    }
+
+    #[test]
+    fn principled_opaque_back_face_reflects() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(8);
+        let mt  = Principled::new(RGB::new(0.8, 0.8, 0.8));
+        // from below a surface whose normal points up
+        let ray = Ray::new(Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.2, 1.0, 0.0));
+        for _ in 0..100 {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+            let (next, w) = mt.scatter(&ray, cr, &mut rng);
+            assert!(next.direction[1] < 0.0 || w.r() == 0.0);
+        }
+    }
+
+    #[test]
+    fn oren_nayar_reduces_to_lambert() {
+        let mt  = OrenNayar::new(0.0);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.3, -1.0, 0.1));
+        let cr  = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+        let wi  = Vector3::new(-0.5, 0.8, 0.2).unit();
+        let f   = mt.eval(&ray, &cr, wi);
+        assert!((f.r() - wi[1] / std::f32::consts::PI).abs() < 1e-5);
+        assert!((mt.pdf(&ray, &cr, wi) - wi[1] / std::f32::consts::PI).abs() < 1e-5);
+    }
+
+    #[test]
+    fn scatter_weight_is_eval_over_pdf() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.3, -1.0, 0.1));
+        let cr  = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+        let mts = [Material::make_oren_nayar(0.5), Material::make_retro_reflective(20.0, 0.3)];
+        for mt in mts.iter() {
+            for _ in 0..100 {
+                let (next, w) = mt.scatter(&ray, cr, &mut rng);
+                let pdf = mt.pdf(&ray, &cr, next.direction);
+                if pdf > 0.0 {
+                    let expected = mt.eval(&ray, &cr, next.direction).r() / pdf;
+                    assert!((w.r() - expected).abs() < 1e-3 * expected.max(1.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn retro_reflection_goes_back() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let mt  = RetroReflective::new(100.0, 0.0);
+        let dir = Vector3::new(1.0, -1.0, 0.0).unit();
+        let ray = Ray::new(Vector3::new(-1.0, 1.0, 0.0), dir);
+        let mut sum = 0.0;
+        for _ in 0..1000 {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+            let (next, _) = mt.scatter(&ray, cr, &mut rng);
+            sum += Vector3::dot(next.direction.unit(), -dir);
+        }
+        assert!(sum / 1000.0 > 0.95);
+    }
+
+    #[test]
+    fn layered_coat_over_white_diffuse() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let clear  = Layered::new(Material::make_oren_nayar(0.0), RGB::new(1.0, 1.0, 1.0),
+                                  1.5, 0.1, RGB::new(0.0, 0.0, 0.0));
+        let tinted = Layered::new(Material::make_oren_nayar(0.0), RGB::new(1.0, 1.0, 1.0),
+                                  1.5, 0.1, RGB::new(10.0, 0.0, 0.0));
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.3, -1.0, 0.1));
+        let n   = 10000;
+        let (mut sum_clear, mut sum_tinted) = (RGB::new(0.0, 0.0, 0.0), RGB::new(0.0, 0.0, 0.0));
+        for _ in 0..n {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+            let (next, w) = clear.scatter(&ray, cr, &mut rng);
+            assert!(next.direction[1] > 0.0);
+            sum_clear += w;
+            let (_, w) = tinted.scatter(&ray, cr, &mut rng);
+            sum_tinted += w;
+        }
+        // a white base under a clear coat loses nothing
+        assert!((sum_clear.r() / n as f32 - 1.0).abs() < 1e-3);
+        // the absorbing coat removes red except for the reflection on top
+        let red = sum_tinted.r() / n as f32;
+        assert!(0.03 < red && red < 0.2, "{}", red);
+        assert!((sum_tinted.g() / n as f32 - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn layered_traps_light_without_losing_it() {
+        // most of the light is reflected back by a coat of a high index
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(35);
+        let mt  = Layered::new(Material::make_oren_nayar(0.0), RGB::new(1.0, 1.0, 1.0),
+                               3.0, 0.0, RGB::new(0.0, 0.0, 0.0));
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.3, -1.0, 0.1));
+        let n   = 20000;
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+            sum += mt.scatter(&ray, cr, &mut rng).1;
+        }
+        assert!((sum.g() / n as f32 - 1.0).abs() < 0.03, "{:?}", sum / n as f32);
+    }
+
+    #[test]
+    fn film_dielectric_conserves_energy() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let mt  = Dielectric::new(1.0).with_film(ThinFilm::new(300.0, 1.33));
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.3, -1.0, 0.1));
+        let n   = 10000;
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let cr = Collision::new(1.0, Vector3::new(0.0, 1.0, 0.0), (0.0, 0.0));
+            let (_, w) = mt.scatter(&ray, cr, &mut rng);
+            sum += w;
+        }
+        let mean = sum / n as f32;
+        assert!((mean.r() - 1.0).abs() < 0.05 && (mean.b() - 1.0).abs() < 0.05, "{:?}", mean);
+    }
+
+    #[test]
+    fn dispersion_separates_wavelengths() {
+        use crate::spectrum::Wavelengths;
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let mt  = Dielectric::new(1.0).with_dispersion(Dispersion::diamond());
+        let dir = Vector3::new(1.0, -1.0, 0.0).unit();
+        let n   = Vector3::new(0.0, 1.0, 0.0);
+        let refracted = |lambda: f32, rng: &mut rand_xorshift::XorShiftRng| loop {
+            let u   = (lambda - crate::spectrum::LAMBDA_MIN) /
+                      (crate::spectrum::LAMBDA_MAX - crate::spectrum::LAMBDA_MIN);
+            let ray = Ray::new(Vector3::new(-1.0, 1.0, 0.0), dir)
+                .with_wavelengths(Wavelengths::sample(u));
+            let cr  = Collision::new(2.0f32.sqrt(), n, (0.0, 0.0));
+            let (next, w) = mt.scatter(&ray, cr, rng);
+            assert_eq!(w, RGB::new(3.0, 0.0, 0.0));
+            assert!(next.wavelengths.unwrap().terminated);
+            if next.direction[1] < 0.0 {
+                return next.direction;
+            }
+        };
+        let blue = refracted(420.0, &mut rng);
+        let red  = refracted(700.0, &mut rng);
+        // blue bends more
+        assert!(blue[0] < red[0]);
+    }
+
+    #[test]
+    fn diffuse_transmission_from_both_sides() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1357);
+        let mt = DiffuseTransmission::new(RGB::new(0.2, 0.2, 0.2), RGB::new(0.6, 0.6, 0.6));
+        let n  = Vector3::new(0.0, 1.0, 0.0);
+        for &(origin, dir) in &[(1.0f32, -1.0f32), (-1.0, 1.0)] {
+            let ray = Ray::new(Vector3::new(0.0, origin, 0.0), Vector3::new(0.3, dir, 0.0));
+            let cr  = Collision::new(1.0, n, (0.0, 0.0));
+            let count = 100000;
+            let (mut reflected, mut transmitted) = (0.0, 0.0);
+            for _ in 0..count {
+                let (next, w) = mt.scatter(&ray, cr, &mut rng);
+                // the weight matches eval / pdf
+                let f = mt.eval(&ray, &cr, next.direction) / mt.pdf(&ray, &cr, next.direction);
+                assert!((f.r() - w.r()).abs() < 1e-4);
+                if next.direction[1] * dir > 0.0 {
+                    transmitted += w.r();
+                } else {
+                    reflected += w.r();
+                }
+            }
+            assert!((reflected   / count as f32 - 0.2).abs() < 0.01);
+            assert!((transmitted / count as f32 - 0.6).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn thin_dielectric_does_not_offset() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(2468);
+        let mt  = ThinDielectric::new(1.5);
+        let dir = Vector3::new(1.0, -1.0, 0.0).unit();
+        let ray = Ray::new(Vector3::new(-1.0, 1.0, 0.0), dir);
+        let count = 100000;
+        let mut reflected = 0;
+        // the normal may face either way
+        for &n in &[Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0)] {
+            for _ in 0..count {
+                let (next, _) = mt.scatter(&ray, Collision::new(2.0f32.sqrt(), n, (0.0, 0.0)), &mut rng);
+                if next.direction[1] > 0.0 {
+                    reflected += 1;
+                    assert!((next.direction - Vector3::new(1.0, 1.0, 0.0).unit()).len() < 1e-4);
+                } else {
+                    assert!((next.direction - dir).len() < 1e-5);
+                }
+            }
         }
+        let r = fresnel_dielectric(0.5f32.sqrt(), 1.5);
+        let expected = 2.0 * r / (1.0 + r);
+        assert!((reflected as f32 / (2 * count) as f32 - expected).abs() < 0.005);
+        assert!(expected > r);
     }
 }
@@ -0,0 +1,382 @@
+//! participating media.
+//!
+//! A medium is either the interior of an object or the global fog of the
+//! world. The distance to the next scattering event is sampled in proportion
+//! to the transmittance (free-flight sampling). The extinction coefficient is
+//! gray, so the sampling is exact and the path weight is the albedo.
+//!
+//! For a heterogeneous medium, free-flight distances are sampled by delta
+//! tracking and the transmittance is estimated by ratio tracking, both using
+//! the majorants of the bricks in the voxel grid.
+//!
+//! The subsurface medium has a chromatic extinction coefficient. The color
+//! channel to sample the distance with is chosen uniformly, and the weight
+//! is the one-sample MIS of the channels (spectral MIS).
+
+use crate::vector::{Vector3, orthonormal_basis};
+use crate::color::{Color, RGB};
+use crate::ray::Ray;
+use crate::util::clamp;
+use crate::voxel::VoxelGrid;
+use rand::Rng;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HenyeyGreenstein {
+    g: f32,
+}
+
+impl HenyeyGreenstein {
+    /// `g` is the mean cosine of the scattering angle, in (-1, 1).
+    pub fn new(g: f32) -> Self {
+        HenyeyGreenstein{g: clamp(g, -0.999, 0.999)}
+    }
+
+    /// `cosine` is between the incoming and the outgoing direction.
+    #[cfg(test)]
+    pub fn p(&self, cosine: f32) -> f32 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cosine;
+        (1.0 - g * g) / (4.0 * std::f32::consts::PI * denom * denom.sqrt())
+    }
+
+    /// samples the outgoing direction of a ray travelling in `dir`.
+    pub fn sample<R: Rng>(&self, dir: Vector3, rng: &mut R) -> Vector3 {
+        let g = self.g;
+        let u = rng.gen_range(0.0f32, 1.0f32);
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rng.gen_range(0.0f32, 1.0f32) * 2.0 * std::f32::consts::PI;
+
+        let w = dir.unit();
+        let (u, v) = orthonormal_basis(w);
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+    }
+}
+
+/// result of the free-flight sampling along a ray segment
+pub enum Event {
+    /// scattered at `t`. the path throughput is multiplied by `weight`.
+    Scatter{t: f32, weight: RGB},
+    /// passed through the segment.
+    Pass{weight: RGB},
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Homogeneous {
+    sigma_t: f32,
+    albedo:  RGB,
+    phase:   HenyeyGreenstein,
+}
+
+impl Homogeneous {
+    /// `absorption` and `scattering` are coefficients per unit length. The
+    /// scattered light is tinted by `color`.
+    pub fn new(absorption: f32, scattering: f32, color: RGB, g: f32) -> Self {
+        let sigma_t = absorption + scattering;
+        let albedo  = if sigma_t > 0.0 {color * (scattering / sigma_t)} else {color};
+        Homogeneous{sigma_t, albedo, phase: HenyeyGreenstein::new(g)}
+    }
+
+    pub fn sample<R: Rng>(&self, t_max: f32, rng: &mut R) -> Event {
+        if self.sigma_t <= 0.0 {
+            return Event::Pass{weight: RGB::new(1.0, 1.0, 1.0)};
+        }
+        let u = rng.gen_range(0.0f32, 1.0f32);
+        let t = -(1.0 - u).ln() / self.sigma_t;
+        if t < t_max {
+            Event::Scatter{t, weight: self.albedo}
+        } else {
+            Event::Pass{weight: RGB::new(1.0, 1.0, 1.0)}
+        }
+    }
+
+    #[cfg(test)]
+    pub fn transmittance(&self, t: f32) -> RGB {
+        let tr = (-self.sigma_t * t).exp();
+        RGB::new(tr, tr, tr)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heterogeneous {
+    grid:   VoxelGrid,
+    scale:  f32,
+    albedo: RGB,
+    phase:  HenyeyGreenstein,
+}
+
+impl Heterogeneous {
+    /// the extinction coefficient is `scale` times the density in the grid.
+    /// `albedo` is the ratio of scattering to extinction.
+    pub fn new(grid: VoxelGrid, scale: f32, albedo: RGB, g: f32) -> Self {
+        Heterogeneous{grid, scale, albedo, phase: HenyeyGreenstein::new(g)}
+    }
+
+    /// delta tracking
+    pub fn sample<R: Rng>(&self, ray: &Ray, t_max: f32, rng: &mut R) -> Event {
+        for (t0, t1, majorant) in self.grid.segments(ray, 0.0, t_max) {
+            let majorant = majorant * self.scale;
+            if majorant <= 0.0 {
+                continue;
+            }
+            let mut t = t0;
+            loop {
+                t -= (1.0 - rng.gen_range(0.0f32, 1.0f32)).ln() / majorant;
+                if t1 <= t {
+                    break;
+                }
+                let sigma_t = self.grid.density(ray.at(t)) * self.scale;
+                if rng.gen_range(0.0f32, 1.0f32) * majorant < sigma_t {
+                    return Event::Scatter{t, weight: self.albedo};
+                }
+            }
+        }
+        Event::Pass{weight: RGB::new(1.0, 1.0, 1.0)}
+    }
+
+    /// ratio tracking
+    #[cfg(test)]
+    pub fn transmittance<R: Rng>(&self, ray: &Ray, t_max: f32, rng: &mut R) -> RGB {
+        let mut tr = 1.0;
+        for (t0, t1, majorant) in self.grid.segments(ray, 0.0, t_max) {
+            let majorant = majorant * self.scale;
+            if majorant <= 0.0 {
+                continue;
+            }
+            let mut t = t0;
+            loop {
+                t -= (1.0 - rng.gen_range(0.0f32, 1.0f32)).ln() / majorant;
+                if t1 <= t {
+                    break;
+                }
+                tr *= 1.0 - self.grid.density(ray.at(t)) * self.scale / majorant;
+            }
+        }
+        RGB::new(tr, tr, tr)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subsurface {
+    sigma_t: RGB,
+    albedo:  RGB,
+    phase:   HenyeyGreenstein,
+}
+
+impl Subsurface {
+    /// `color` is the multiple-scattering albedo, i.e. the color of a thick
+    /// slab, and `mfp` is the mean free path of each channel.
+    pub fn new(color: RGB, mfp: RGB, g: f32) -> Self {
+        let sigma_t = RGB::new(1.0 / mfp.r().max(1e-6), 1.0 / mfp.g().max(1e-6),
+                               1.0 / mfp.b().max(1e-6));
+        let albedo  = RGB::new(single_scattering_albedo(color.r()),
+                               single_scattering_albedo(color.g()),
+                               single_scattering_albedo(color.b()));
+        Subsurface{sigma_t, albedo, phase: HenyeyGreenstein::new(g)}
+    }
+
+    pub fn sample<R: Rng>(&self, ray: &Ray, t_max: f32, rng: &mut R) -> Event {
+        let sigma_t = ray.tint(self.sigma_t);
+        let channel = rng.gen_range(0usize, 3usize);
+        let u = rng.gen_range(0.0f32, 1.0f32);
+        let t = -(1.0 - u).ln() / [sigma_t.r(), sigma_t.g(), sigma_t.b()][channel];
+        let t = t.min(t_max);
+        let tr = RGB::new((-sigma_t.r() * t).exp(), (-sigma_t.g() * t).exp(),
+                          (-sigma_t.b() * t).exp());
+        if t < t_max {
+            let density = sigma_t * tr;
+            let pdf = (density.r() + density.g() + density.b()) / 3.0;
+            Event::Scatter{t, weight: density * ray.tint(self.albedo) / pdf}
+        } else {
+            let pdf = (tr.r() + tr.g() + tr.b()) / 3.0;
+            Event::Pass{weight: tr / pdf}
+        }
+    }
+
+    #[cfg(test)]
+    pub fn transmittance(&self, ray: &Ray, t: f32) -> RGB {
+        let sigma_t = ray.tint(self.sigma_t);
+        RGB::new((-sigma_t.r() * t).exp(), (-sigma_t.g() * t).exp(), (-sigma_t.b() * t).exp())
+    }
+}
+
+/// inverts the multiple-scattering albedo of a half space into the
+/// single-scattering albedo (Chiang et al. 2016).
+fn single_scattering_albedo(color: f32) -> f32 {
+    let a = clamp(color, 0.0, 1.0);
+    let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    1.0 - s * s
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Medium {
+    Homogeneous(Homogeneous),
+    Heterogeneous(Heterogeneous),
+    Subsurface(Subsurface),
+}
+
+impl Medium {
+    pub fn make_homogeneous(absorption: f32, scattering: f32, color: RGB, g: f32) -> Self {
+        Medium::Homogeneous(Homogeneous::new(absorption, scattering, color, g))
+    }
+    pub fn make_heterogeneous(grid: VoxelGrid, scale: f32, albedo: RGB, g: f32) -> Self {
+        Medium::Heterogeneous(Heterogeneous::new(grid, scale, albedo, g))
+    }
+    pub fn make_subsurface(color: RGB, mfp: RGB, g: f32) -> Self {
+        Medium::Subsurface(Subsurface::new(color, mfp, g))
+    }
+
+    /// samples the next event along the ray in `(0, t_max)`. The weight is
+    /// in the channels of the ray, i.e. tinted in the spectral mode.
+    pub fn sample<R: Rng>(&self, ray: &Ray, t_max: f32, rng: &mut R) -> Event {
+        let event = match self {
+            Medium::Homogeneous(m)   => {m.sample(t_max, rng)}
+            Medium::Heterogeneous(m) => {m.sample(ray, t_max, rng)}
+            Medium::Subsurface(m)    => {return m.sample(ray, t_max, rng);}
+        };
+        match event {
+            Event::Scatter{t, weight} => {Event::Scatter{t, weight: ray.tint(weight)}}
+            Event::Pass{weight}       => {Event::Pass{weight: ray.tint(weight)}}
+        }
+    }
+
+    /// transmittance from the origin of the ray to `t`.
+    #[cfg(test)]
+    pub fn transmittance<R: Rng>(&self, ray: &Ray, t: f32, rng: &mut R) -> RGB {
+        match self {
+            Medium::Homogeneous(m)   => {m.transmittance(t)}
+            Medium::Heterogeneous(m) => {m.transmittance(ray, t, rng)}
+            Medium::Subsurface(m)    => {m.transmittance(ray, t)}
+        }
+    }
+
+    pub fn phase(&self) -> &HenyeyGreenstein {
+        match self {
+            Medium::Homogeneous(m)   => {&m.phase}
+            Medium::Heterogeneous(m) => {&m.phase}
+            Medium::Subsurface(m)    => {&m.phase}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::medium::*;
+    use crate::aabb::AABB;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn henyey_greenstein_mean_cosine() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let dir = Vector3::new(1.0, 2.0, 3.0).unit();
+        for &g in &[-0.5f32, 0.0, 0.3, 0.8] {
+            let hg = HenyeyGreenstein::new(g);
+            let n  = 100000;
+            let mean = (0..n).map(|_| Vector3::dot(dir, hg.sample(dir, &mut rng)))
+                .sum::<f32>() / n as f32;
+            assert!((mean - g).abs() < 0.01, "{} {}", mean, g);
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_normalized() {
+        let hg = HenyeyGreenstein::new(0.7);
+        // integrate over the sphere: 2 pi int p(cos) dcos
+        let n = 100000;
+        let sum = (0..n).map(|i| {
+            let c = -1.0 + 2.0 * (i as f32 + 0.5) / n as f32;
+            hg.p(c)
+        }).sum::<f32>() * 2.0 / n as f32 * 2.0 * std::f32::consts::PI;
+        assert!((sum - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn free_flight_follows_transmittance() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(987654321);
+        let medium = Medium::make_homogeneous(0.5, 1.5, RGB::new(1.0, 1.0, 1.0), 0.0);
+        let ray = Ray::new(Vector3::zero(), Vector3::new(1.0, 0.0, 0.0));
+        let n = 100000;
+        let passed = (0..n).filter(|_| {
+            match medium.sample(&ray, 0.5, &mut rng) {
+                Event::Pass{..} => true,
+                Event::Scatter{..} => false,
+            }
+        }).count();
+        let expected = medium.transmittance(&ray, 0.5, &mut rng).r();
+        assert!((passed as f32 / n as f32 - expected).abs() < 0.01);
+    }
+
+    fn constant_grid(density: f32) -> VoxelGrid {
+        let bounds = AABB::new(Vector3::zero(), Vector3::new(1.0, 1.0, 1.0));
+        VoxelGrid::new([10, 10, 10], vec![density; 1000], bounds).unwrap()
+    }
+
+    #[test]
+    fn ratio_tracking_of_constant_grid() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let medium = Medium::make_heterogeneous(
+            constant_grid(1.0), 2.0, RGB::new(1.0, 1.0, 1.0), 0.0);
+        let ray = Ray::new(Vector3::new(-1.0, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0));
+        let n  = 10000;
+        let tr = (0..n).map(|_| medium.transmittance(&ray, 10.0, &mut rng).r())
+            .sum::<f32>() / n as f32;
+        assert!((tr - (-2.0f32).exp()).abs() < 0.01, "{}", tr);
+    }
+
+    #[test]
+    fn delta_tracking_of_half_filled_grid() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(987654321);
+        let bounds = AABB::new(Vector3::zero(), Vector3::new(2.0, 1.0, 1.0));
+        // the half of the grid is empty, and the other half has density 1.
+        let values = (0..32*8*8).map(|i| if i % 32 < 16 {0.0} else {1.0}).collect();
+        let grid = VoxelGrid::new([32, 8, 8], values, bounds).unwrap();
+        let medium = Medium::make_heterogeneous(grid, 1.5, RGB::new(1.0, 1.0, 1.0), 0.0);
+        let ray = Ray::new(Vector3::new(-1.0, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0));
+
+        let n = 100000;
+        let mut passed = 0;
+        for _ in 0..n {
+            match medium.sample(&ray, 10.0, &mut rng) {
+                Event::Pass{..} => {passed += 1;}
+                Event::Scatter{t, ..} => {assert!(t > 1.9);}
+            }
+        }
+        // the optical depth is 1.5 even though the density ramps up
+        // between the voxel centers at the middle of the grid.
+        let expected = (-1.5f32).exp();
+        assert!((passed as f32 / n as f32 - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn albedo_inversion() {
+        assert!(single_scattering_albedo(0.0).abs() < 1e-3);
+        assert!((single_scattering_albedo(1.0) - 1.0).abs() < 1e-3);
+        assert!(single_scattering_albedo(0.5) > 0.5);
+        assert!(single_scattering_albedo(0.3) < single_scattering_albedo(0.6));
+    }
+
+    #[test]
+    fn chromatic_free_flight_is_unbiased() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(192837465);
+        let medium = Medium::make_subsurface(RGB::new(1.0, 1.0, 1.0),
+                                             RGB::new(1.0, 0.5, 0.25), 0.0);
+        let ray = Ray::new(Vector3::zero(), Vector3::new(1.0, 0.0, 0.0));
+        let n = 100000;
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            if let Event::Pass{weight} = medium.sample(&ray, 0.5, &mut rng) {
+                sum += weight;
+            }
+        }
+        let mean = sum / n as f32;
+        let expected = medium.transmittance(&ray, 0.5, &mut rng);
+        assert!((mean.r() - expected.r()).abs() < 0.01, "{:?} {:?}", mean, expected);
+        assert!((mean.g() - expected.g()).abs() < 0.01, "{:?} {:?}", mean, expected);
+        assert!((mean.b() - expected.b()).abs() < 0.01, "{:?} {:?}", mean, expected);
+    }
+}
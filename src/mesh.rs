@@ -0,0 +1,299 @@
+//! triangle meshes with texture coordinates.
+//!
+//! Normals are interpolated from the vertices for shading, and tangents
+//! are derived from the texture coordinates. A mesh can be tessellated
+//! and displaced by a height texture to add actual geometric detail.
+//!
+//! The triangles are kept in a bounding volume hierarchy, split at the
+//! median along the longest axis, so that a finely tessellated mesh can be
+//! traced quickly.
+
+use crate::vector::Vector3;
+use crate::ray::Ray;
+use crate::aabb::AABB;
+use crate::texture::Texture;
+use crate::collide::{Collision, Collide};
+
+pub struct Mesh {
+    positions: std::vec::Vec<Vector3>,
+    uvs:       std::vec::Vec<(f32, f32)>,
+    normals:   std::vec::Vec<Vector3>,
+    triangles: std::vec::Vec<[usize; 3]>, // in the order of the leaves
+    nodes:     std::vec::Vec<Node>,
+}
+
+const LEAF_SIZE: usize = 4;
+
+/// node of the hierarchy. A leaf has `count` triangles from `first`. An
+/// inner node has `count == 0`, its first child is next to it and the
+/// second child is at `first`.
+struct Node {
+    bounds: AABB,
+    first:  usize,
+    count:  usize,
+}
+
+fn triangle_bounds(positions: &[Vector3], tri: &[usize; 3]) -> AABB {
+    tri.iter().map(|&i| AABB::new(positions[i], positions[i]))
+        .reduce(|l, r| l.union(&r)).unwrap()
+}
+
+/// builds the subtree of `triangles`, which start at `offset` in the mesh.
+fn build(nodes: &mut std::vec::Vec<Node>, positions: &[Vector3],
+         triangles: &mut [[usize; 3]], offset: usize) {
+    let bounds = triangles.iter().map(|tri| triangle_bounds(positions, tri))
+        .reduce(|l, r| l.union(&r))
+        .unwrap_or_else(|| AABB::new(Vector3::zero(), Vector3::zero()));
+    let index = nodes.len();
+    nodes.push(Node{bounds, first: offset, count: triangles.len()});
+    if triangles.len() <= LEAF_SIZE {
+        return;
+    }
+    let centroid = |tri: &[usize; 3]| {
+        (positions[tri[0]] + positions[tri[1]] + positions[tri[2]]) * (1.0 / 3.0)
+    };
+    let extent = bounds.max - bounds.min;
+    let axis = if extent[0] > extent[1] && extent[0] > extent[2] {0}
+               else if extent[1] > extent[2] {1} else {2};
+    let mid = triangles.len() / 2;
+    triangles.select_nth_unstable_by(mid, |a, b| {
+        centroid(a)[axis].total_cmp(&centroid(b)[axis])
+    });
+    let (left, right) = triangles.split_at_mut(mid);
+    build(nodes, positions, left, offset);
+    let second = nodes.len();
+    build(nodes, positions, right, offset + mid);
+    nodes[index].first = second;
+    nodes[index].count = 0;
+}
+
+impl Mesh {
+    /// `uvs` has the texture coordinate of each vertex. The vertex normals
+    /// are the area-weighted average of the face normals.
+    pub fn new(positions: std::vec::Vec<Vector3>, uvs: std::vec::Vec<(f32, f32)>,
+               triangles: std::vec::Vec<[usize; 3]>) -> Mesh {
+        assert_eq!(positions.len(), uvs.len());
+        assert!(triangles.iter().flatten().all(|&i| i < positions.len()));
+
+        let mut normals = vec![Vector3::zero(); positions.len()];
+        for tri in triangles.iter() {
+            let [a, b, c] = *tri;
+            let n = Vector3::cross(positions[b] - positions[a], positions[c] - positions[a]);
+            for &i in tri.iter() {
+                normals[i] += n;
+            }
+        }
+        let normals = normals.into_iter().map(|n| {
+            if n.len_sq() > 0.0 {n.unit()} else {Vector3::new(0.0, 1.0, 0.0)}
+        }).collect();
+
+        let mut triangles = triangles;
+        let mut nodes = std::vec::Vec::new();
+        build(&mut nodes, &positions, &mut triangles, 0);
+
+        Mesh{positions, uvs, normals, triangles, nodes}
+    }
+
+    /// a parallelogram spanned by `edge_u` and `edge_v` from `corner`,
+    /// tessellated into `nu` x `nv` quads.
+    pub fn grid(corner: Vector3, edge_u: Vector3, edge_v: Vector3, nu: usize, nv: usize) -> Mesh {
+        let (nu, nv) = (nu.max(1), nv.max(1));
+        let mut positions = std::vec::Vec::new();
+        let mut uvs       = std::vec::Vec::new();
+        for j in 0..=nv {
+            for i in 0..=nu {
+                let (u, v) = (i as f32 / nu as f32, j as f32 / nv as f32);
+                positions.push(corner + edge_u * u + edge_v * v);
+                uvs.push((u, v));
+            }
+        }
+        let mut triangles = std::vec::Vec::new();
+        for j in 0..nv {
+            for i in 0..nu {
+                let idx = |i: usize, j: usize| j * (nu + 1) + i;
+                triangles.push([idx(i, j), idx(i+1, j), idx(i+1, j+1)]);
+                triangles.push([idx(i, j), idx(i+1, j+1), idx(i, j+1)]);
+            }
+        }
+        Mesh::new(positions, uvs, triangles)
+    }
+
+    #[cfg(test)]
+    pub fn num_triangles(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// splits each triangle into four at the midpoints of the edges.
+    pub fn subdivide(&self) -> Mesh {
+        let mut positions = self.positions.clone();
+        let mut uvs       = self.uvs.clone();
+        let mut midpoints = std::collections::HashMap::new();
+        let mut midpoint  = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push((positions[a] + positions[b]) * 0.5);
+                uvs.push(((uvs[a].0 + uvs[b].0) * 0.5, (uvs[a].1 + uvs[b].1) * 0.5));
+                positions.len() - 1
+            })
+        };
+        let mut triangles = std::vec::Vec::with_capacity(self.triangles.len() * 4);
+        for &[a, b, c] in self.triangles.iter() {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            triangles.push([a, ab, ca]);
+            triangles.push([ab, b, bc]);
+            triangles.push([ca, bc, c]);
+            triangles.push([ab, bc, ca]);
+        }
+        Mesh::new(positions, uvs, triangles)
+    }
+
+    /// moves each vertex along its normal by `scale` times the height
+    /// texture. The mesh should be tessellated finely enough beforehand.
+    pub fn displace(&self, height: &Texture, scale: f32) -> Mesh {
+        let positions = self.positions.iter().zip(self.uvs.iter()).zip(self.normals.iter())
+            .map(|((p, uv), n)| *p + *n * (scale * height.scalar(*uv, *p)))
+            .collect();
+        Mesh::new(positions, self.uvs.clone(), self.triangles.clone())
+    }
+
+    fn collision(&self, tri: [usize; 3], t: f32, u: f32, v: f32) -> Collision {
+        let [a, b, c] = tri;
+        let w  = 1.0 - u - v;
+        let normal = Vector3::unit(self.normals[a] * w + self.normals[b] * u + self.normals[c] * v);
+        let uv = (self.uvs[a].0 * w + self.uvs[b].0 * u + self.uvs[c].0 * v,
+                  self.uvs[a].1 * w + self.uvs[b].1 * u + self.uvs[c].1 * v);
+
+        let e1 = self.positions[b] - self.positions[a];
+        let e2 = self.positions[c] - self.positions[a];
+        let geometric = Vector3::unit(Vector3::cross(e1, e2));
+        let geometric = if Vector3::dot(geometric, normal) < 0.0 {-geometric} else {geometric};
+
+        // dp/du from the texture coordinates of the triangle
+        let (du1, dv1) = (self.uvs[b].0 - self.uvs[a].0, self.uvs[b].1 - self.uvs[a].1);
+        let (du2, dv2) = (self.uvs[c].0 - self.uvs[a].0, self.uvs[c].1 - self.uvs[a].1);
+        let det = du1 * dv2 - du2 * dv1;
+        let cr  = Collision::new(t, normal, uv).with_geometric(geometric);
+        if det.abs() < 1e-12 {
+            cr
+        } else {
+            cr.with_tangent((e1 * dv2 - e2 * dv1) / det)
+        }
+    }
+}
+
+/// Moller-Trumbore. returns t and the barycentric coordinates of the hit
+/// point w.r.t. `p[1]` and `p[2]`.
+pub fn collide_triangle(ray: &Ray, p: [Vector3; 3], t_min: f32, t_max: f32)
+    -> Option<(f32, f32, f32)>
+{
+    let e1 = p[1] - p[0];
+    let e2 = p[2] - p[0];
+    let pv = Vector3::cross(ray.direction, e2);
+    let det = Vector3::dot(e1, pv);
+    if det.abs() < 1e-12 {return None;}
+    let rdet = 1.0 / det;
+
+    let tv = ray.origin - p[0];
+    let u  = Vector3::dot(tv, pv) * rdet;
+    if !(0.0..=1.0).contains(&u) {return None;}
+
+    let qv = Vector3::cross(tv, e1);
+    let v  = Vector3::dot(ray.direction, qv) * rdet;
+    if v < 0.0 || 1.0 < u + v {return None;}
+
+    let t = Vector3::dot(e2, qv) * rdet;
+    if t < t_min || t_max < t {return None;}
+    Some((t, u, v))
+}
+
+impl Collide for Mesh {
+    fn collide_within(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Collision> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let mut t_max = t_max;
+        let mut nearest = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds.hit(ray, t_min, t_max).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(index + 1);
+                continue;
+            }
+            for tri in self.triangles[node.first..node.first + node.count].iter() {
+                let p = [self.positions[tri[0]], self.positions[tri[1]], self.positions[tri[2]]];
+                if let Some((t, u, v)) = collide_triangle(ray, p, t_min, t_max) {
+                    t_max   = t;
+                    nearest = Some((*tri, t, u, v));
+                }
+            }
+        }
+        nearest.map(|(tri, t, u, v)| self.collision(tri, t, u, v))
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
+        Some(self.nodes[0].bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mesh::*;
+    use crate::color::RGB;
+    use rand::Rng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn grid_uv_and_tangent() {
+        let mesh = Mesh::grid(Vector3::new(-1.0, 0.0, 1.0), Vector3::new(2.0, 0.0, 0.0),
+                              Vector3::new(0.0, 0.0, -2.0), 4, 4);
+        assert_eq!(mesh.num_triangles(), 32);
+        let ray = Ray::new(Vector3::new(0.5, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let cr  = mesh.collide_within(&ray, 1e-4, f32::INFINITY).unwrap();
+        assert!((cr.t - 1.0).abs() < 1e-5);
+        assert!((cr.uv.0 - 0.75).abs() < 1e-5 && (cr.uv.1 - 0.5).abs() < 1e-5);
+        assert!((cr.tangent - Vector3::new(1.0, 0.0, 0.0)).len() < 1e-5);
+        assert!((cr.normal.dot(Vector3::new(0.0, 1.0, 0.0)) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn displacement_moves_surface() {
+        let flat = Mesh::grid(Vector3::new(-1.0, 0.0, 1.0), Vector3::new(2.0, 0.0, 0.0),
+                              Vector3::new(0.0, 0.0, -2.0), 1, 1);
+        let fine = flat.subdivide().subdivide().subdivide();
+        assert_eq!(fine.num_triangles(), 2 * 64);
+        let bumpy = fine.displace(&Texture::from(1.0), 0.25);
+        let ray = Ray::new(Vector3::new(0.1, 1.0, 0.2), Vector3::new(0.0, -1.0, 0.0));
+        let cr  = bumpy.collide_within(&ray, 1e-4, f32::INFINITY).unwrap();
+        assert!((cr.t - 0.75).abs() < 1e-4);
+        let bb = bumpy.bounding_box(0.0, 0.0).unwrap();
+        assert!((bb.min[1] - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn hierarchy_matches_brute_force() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(39);
+        let mesh = Mesh::grid(Vector3::new(-1.0, 0.0, 1.0), Vector3::new(2.0, 0.0, 0.0),
+                              Vector3::new(0.0, 0.0, -2.0), 8, 8)
+            .subdivide().displace(&Texture::make_checker(RGB::new(1.0, 1.0, 1.0),
+                                                        RGB::new(0.0, 0.0, 0.0), 0.3), 0.2);
+        assert!(mesh.nodes.len() > 1);
+        for _ in 0..500 {
+            let origin = Vector3::new(rng.gen_range(-1.5, 1.5), rng.gen_range(0.5, 2.0),
+                                      rng.gen_range(-1.5, 1.5));
+            let dir = Vector3::new(rng.gen_range(-1.0, 1.0), -1.0, rng.gen_range(-1.0, 1.0));
+            let ray = Ray::new(origin, dir);
+            let brute = mesh.triangles.iter().filter_map(|tri| {
+                let p = [mesh.positions[tri[0]], mesh.positions[tri[1]], mesh.positions[tri[2]]];
+                collide_triangle(&ray, p, 1e-4, f32::INFINITY).map(|hit| hit.0)
+            }).fold(f32::INFINITY, f32::min);
+            match mesh.collide_within(&ray, 1e-4, f32::INFINITY) {
+                Some(cr) => {assert_eq!(cr.t, brute);}
+                None     => {assert_eq!(brute, f32::INFINITY);}
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! measured complex indices of refraction of metals.
+//!
+//! The tables are sampled from the measurements by Johnson & Christy (1972)
+//! for gold, copper and silver, and by Rakic (1995) for aluminium.
+
+use crate::color::RGB;
+
+/// wavelengths (nm) used as representatives of the RGB channels.
+pub const RGB_WAVELENGTHS: [f32; 3] = [650.0, 550.0, 450.0];
+
+const WAVELENGTHS: [f32; 7] = [400.0, 450.0, 500.0, 550.0, 600.0, 650.0, 700.0];
+
+/// (n, k) at WAVELENGTHS
+const GOLD: [(f32, f32); 7] = [
+    (1.658, 1.956), (1.510, 1.880), (0.970, 1.870), (0.430, 2.455),
+    (0.250, 2.970), (0.166, 3.150), (0.160, 3.800),
+];
+const COPPER: [(f32, f32); 7] = [
+    (1.180, 2.210), (1.170, 2.400), (1.120, 2.600), (0.950, 2.580),
+    (0.270, 3.410), (0.210, 3.670), (0.210, 4.200),
+];
+const SILVER: [(f32, f32); 7] = [
+    (0.050, 2.070), (0.040, 2.650), (0.050, 3.090), (0.060, 3.590),
+    (0.060, 4.150), (0.050, 4.480), (0.040, 4.840),
+];
+const ALUMINIUM: [(f32, f32); 7] = [
+    (0.490, 4.860), (0.620, 5.470), (0.770, 6.080), (0.960, 6.690),
+    (1.200, 7.260), (1.470, 7.790), (1.830, 8.310),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Metal {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl Metal {
+    fn table(&self) -> &'static [(f32, f32); 7] {
+        match self {
+            Metal::Gold      => {&GOLD}
+            Metal::Copper    => {&COPPER}
+            Metal::Aluminium => {&ALUMINIUM}
+            Metal::Silver    => {&SILVER}
+        }
+    }
+
+    /// linearly interpolated (n, k) at `lambda` nm.
+    pub fn ior_at(&self, lambda: f32) -> (f32, f32) {
+        let table = self.table();
+        if lambda <= WAVELENGTHS[0] {
+            return table[0];
+        }
+        for i in 1..WAVELENGTHS.len() {
+            if lambda <= WAVELENGTHS[i] {
+                let s = (lambda - WAVELENGTHS[i-1]) / (WAVELENGTHS[i] - WAVELENGTHS[i-1]);
+                return (table[i-1].0 * (1.0 - s) + table[i].0 * s,
+                        table[i-1].1 * (1.0 - s) + table[i].1 * s);
+            }
+        }
+        table[table.len() - 1]
+    }
+
+    /// (n, k) at the RGB_WAVELENGTHS
+    pub fn ior_rgb(&self) -> (RGB, RGB) {
+        let r = self.ior_at(RGB_WAVELENGTHS[0]);
+        let g = self.ior_at(RGB_WAVELENGTHS[1]);
+        let b = self.ior_at(RGB_WAVELENGTHS[2]);
+        (RGB::new(r.0, g.0, b.0), RGB::new(r.1, g.1, b.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metal::*;
+    use crate::color::Color;
+    use crate::microfacet::fresnel_conductor_rgb;
+
+    #[test]
+    fn metal_colors() {
+        let (eta, k) = Metal::Gold.ior_rgb();
+        let f0 = fresnel_conductor_rgb(1.0, eta, k);
+        assert!(f0.r() > f0.g() && f0.g() > f0.b());
+
+        let (eta, k) = Metal::Copper.ior_rgb();
+        let f0 = fresnel_conductor_rgb(1.0, eta, k);
+        assert!(f0.r() > f0.g() && f0.g() > f0.b());
+
+        for metal in &[Metal::Silver, Metal::Aluminium] {
+            let (eta, k) = metal.ior_rgb();
+            let f0 = fresnel_conductor_rgb(1.0, eta, k);
+            assert!(f0.r() > 0.85 && f0.g() > 0.85 && f0.b() > 0.85);
+        }
+    }
+
+    #[test]
+    fn interpolation() {
+        assert_eq!(Metal::Gold.ior_at(300.0), GOLD[0]);
+        assert_eq!(Metal::Gold.ior_at(550.0), GOLD[3]);
+        let (n, k) = Metal::Gold.ior_at(525.0);
+        assert!((n - 0.5 * (GOLD[2].0 + GOLD[3].0)).abs() < 1e-5);
+        assert!((k - 0.5 * (GOLD[2].1 + GOLD[3].1)).abs() < 1e-5);
+    }
+}
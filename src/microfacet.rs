@@ -0,0 +1,217 @@
+//! microfacet distribution and Fresnel terms.
+//!
+//! All the directions are in the local frame where the macro surface normal
+//! is the z axis, and they point away from the surface.
+
+use crate::vector::Vector3;
+use crate::color::{Color, RGB};
+
+/// anisotropic GGX (Trowbridge-Reitz) distribution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GGX {
+    alpha_x: f32,
+    alpha_y: f32,
+}
+
+impl GGX {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> GGX {
+        GGX{alpha_x: alpha_x.max(1e-4), alpha_y: alpha_y.max(1e-4)}
+    }
+
+    /// uses the squared perceptual roughness as alpha.
+    pub fn from_roughness(roughness: f32) -> GGX {
+        GGX::new(roughness * roughness, roughness * roughness)
+    }
+
+    /// true if the surface is too smooth to be sampled as a distribution.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    #[cfg(test)]
+    pub fn d(&self, h: Vector3) -> f32 {
+        if h[2] <= 0.0 {
+            return 0.0;
+        }
+        let x = h[0] / self.alpha_x;
+        let y = h[1] / self.alpha_y;
+        let e = x * x + y * y + h[2] * h[2];
+        1.0 / (std::f32::consts::PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    pub fn lambda(&self, w: Vector3) -> f32 {
+        if w[2] == 0.0 {
+            return f32::INFINITY;
+        }
+        let a2 = (self.alpha_x * w[0]).powi(2) + (self.alpha_y * w[1]).powi(2);
+        0.5 * (-1.0 + (1.0 + a2 / (w[2] * w[2])).sqrt())
+    }
+
+    /// Smith masking
+    pub fn g1(&self, w: Vector3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// height-correlated Smith masking-shadowing
+    pub fn g2(&self, wo: Vector3, wi: Vector3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// samples a microfacet normal visible from `wo` (Heitz 2018).
+    /// `u1` and `u2` are uniform in [0, 1).
+    pub fn sample_visible(&self, wo: Vector3, u1: f32, u2: f32) -> Vector3 {
+        let flip = wo[2] < 0.0;
+        let wo = if flip {-wo} else {wo};
+        let vh = Vector3::unit(Vector3::new(self.alpha_x * wo[0], self.alpha_y * wo[1], wo[2]));
+        let lensq = vh[0] * vh[0] + vh[1] * vh[1];
+        let t1 = if lensq > 0.0 {
+            Vector3::new(-vh[1], vh[0], 0.0) / lensq.sqrt()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vector3::cross(vh, t1);
+
+        let r   = u1.sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let p1  = r * phi.cos();
+        let p2  = r * phi.sin();
+        let s   = 0.5 * (1.0 + vh[2]);
+        let p2  = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        let h  = Vector3::unit(Vector3::new(self.alpha_x * nh[0], self.alpha_y * nh[1],
+                                            nh[2].max(1e-6)));
+        if flip {-h} else {h}
+    }
+
+    /// pdf of `sample_visible` w.r.t. the solid angle of h
+    #[cfg(test)]
+    pub fn pdf_visible(&self, wo: Vector3, h: Vector3) -> f32 {
+        if wo[2] == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * Vector3::dot(wo, h).max(0.0) * self.d(h) / wo[2].abs()
+    }
+}
+
+/// exact Fresnel reflectance of a dielectric interface. `eta` is the ratio
+/// of the index of the transmitted side to that of the incident side. If
+/// `cos_i` is negative, the light comes from the other side.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_i < 0.0 {(-cos_i, 1.0 / eta)} else {(cos_i, eta)};
+    let cos_i  = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0; // total internal reflection
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Fresnel reflectance of a conductor with the complex index of refraction
+/// `eta + i k` relative to the outside.
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2   = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2plusb2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2plusb2 + cos2;
+    let a  = (0.5 * (a2plusb2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2plusb2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+pub fn fresnel_conductor_rgb(cos_i: f32, eta: RGB, k: RGB) -> RGB {
+    RGB::new(fresnel_conductor(cos_i, eta.r(), k.r()),
+             fresnel_conductor(cos_i, eta.g(), k.g()),
+             fresnel_conductor(cos_i, eta.b(), k.b()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::microfacet::*;
+    use rand::Rng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn ndf_is_normalized() {
+        // int D(h) cos(h) dh = 1
+        let ggx = GGX::new(0.3, 0.6);
+        let n   = 400;
+        let mut sum = 0.0f64;
+        for i in 0..n {
+            let cos_t = (i as f32 + 0.5) / n as f32;
+            let sin_t = (1.0 - cos_t * cos_t).sqrt();
+            for j in 0..n {
+                let phi = 2.0 * std::f32::consts::PI * (j as f32 + 0.5) / n as f32;
+                let h = Vector3::new(sin_t * phi.cos(), sin_t * phi.sin(), cos_t);
+                sum += (ggx.d(h) * cos_t) as f64;
+            }
+        }
+        let integral = sum * 2.0 * std::f64::consts::PI / (n * n) as f64;
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+    }
+
+    #[test]
+    fn visible_normals_face_the_viewer() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(123456789);
+        let ggx = GGX::new(0.5, 0.2);
+        let wo  = Vector3::new(0.6, 0.3, 0.5).unit();
+        for _ in 0..1000 {
+            let h = ggx.sample_visible(wo, rng.gen_range(0.0f32, 1.0f32),
+                                           rng.gen_range(0.0f32, 1.0f32));
+            assert!((h.len() - 1.0).abs() < 1e-4);
+            assert!(h[2] > 0.0);
+            assert!(Vector3::dot(h, wo) >= -1e-4);
+        }
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_one() {
+        let ggx = GGX::new(0.4, 0.4);
+        let wo  = Vector3::new(0.5, 0.0, 0.5).unit();
+        let n   = 400;
+        let mut sum = 0.0f64;
+        for i in 0..n {
+            let cos_t = (i as f32 + 0.5) / n as f32;
+            let sin_t = (1.0 - cos_t * cos_t).sqrt();
+            for j in 0..n {
+                let phi = 2.0 * std::f32::consts::PI * (j as f32 + 0.5) / n as f32;
+                let h = Vector3::new(sin_t * phi.cos(), sin_t * phi.sin(), cos_t);
+                sum += ggx.pdf_visible(wo, h) as f64;
+            }
+        }
+        let integral = sum * 2.0 * std::f64::consts::PI / (n * n) as f64;
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+    }
+
+    #[test]
+    fn dielectric_fresnel() {
+        let eta = 1.5f32;
+        let r0  = ((eta - 1.0) / (eta + 1.0)).powi(2);
+        assert!((fresnel_dielectric( 1.0, eta) - r0).abs() < 1e-6);
+        assert!((fresnel_dielectric(-1.0, eta) - r0).abs() < 1e-6);
+        assert!((fresnel_dielectric( 0.0, eta) - 1.0).abs() < 1e-6);
+        // total internal reflection from inside
+        assert_eq!(fresnel_dielectric(-0.5, eta), 1.0);
+        assert!(fresnel_dielectric(0.5, eta) < 1.0);
+    }
+
+    #[test]
+    fn conductor_at_normal_incidence() {
+        let (n, k) = (0.2f32, 3.9f32);
+        let expected = ((n - 1.0).powi(2) + k * k) / ((n + 1.0).powi(2) + k * k);
+        assert!((fresnel_conductor(1.0, n, k) - expected).abs() < 1e-5);
+        assert!((fresnel_conductor(0.0, n, k) - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::vector::Vector3;
+use crate::error::{Error, Result};
+
+/// piecewise linear translation over time. Before the first key and after
+/// the last key, the object stays at the first and last offset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Motion {
+    keys: std::vec::Vec<(f32, Vector3)>,
+}
+
+impl Motion {
+    /// moves by `offset` from `time0` to `time1` at a constant velocity.
+    pub fn linear(time0: f32, time1: f32, offset: Vector3) -> Motion {
+        Motion{keys: vec![(time0, Vector3::zero()), (time1, offset)]}
+    }
+
+    /// pairs of time and offset. they are sorted by time. fails if there is
+    /// no key or a time is not finite.
+    pub fn keyframes(mut keys: std::vec::Vec<(f32, Vector3)>) -> Result<Motion> {
+        if keys.is_empty() {
+            return Err(Error::parse("motion without keyframes"));
+        }
+        if let Some((t, _)) = keys.iter().find(|(t, _)| !t.is_finite()) {
+            return Err(Error::parse(format!("keyframe at time {}", t)));
+        }
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Motion{keys})
+    }
+
+    pub fn offset_at(&self, time: f32) -> Vector3 {
+        let first = self.keys[0];
+        if time <= first.0 {
+            return first.1;
+        }
+        for w in self.keys.windows(2) {
+            let (t0, p0) = w[0];
+            let (t1, p1) = w[1];
+            if time <= t1 {
+                let s = (time - t0) / (t1 - t0);
+                return p0 * (1.0 - s) + p1 * s;
+            }
+        }
+        self.keys[self.keys.len() - 1].1
+    }
+
+    /// offsets at the both ends and at the keys between them. Since the
+    /// motion is linear between the keys, they contain the whole trajectory.
+    pub fn offsets_within(&self, time0: f32, time1: f32) -> std::vec::Vec<Vector3> {
+        let mut offsets = vec![self.offset_at(time0), self.offset_at(time1)];
+        offsets.extend(self.keys.iter()
+            .filter(|(t, _)| time0 < *t && *t < time1)
+            .map(|(_, p)| *p));
+        offsets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::motion::*;
+
+    #[test]
+    fn interpolation() {
+        let m = Motion::keyframes(vec![
+            (1.0, Vector3::new(1.0, 0.0, 0.0)),
+            (0.0, Vector3::zero()),
+            (2.0, Vector3::new(1.0, 2.0, 0.0)),
+        ]).unwrap();
+        assert_eq!(m.offset_at(-1.0), Vector3::zero());
+        assert_eq!(m.offset_at( 0.5), Vector3::new(0.5, 0.0, 0.0));
+        assert_eq!(m.offset_at( 1.5), Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(m.offset_at( 3.0), Vector3::new(1.0, 2.0, 0.0));
+        assert_eq!(m.offsets_within(0.5, 1.5).len(), 3);
+    }
+
+    #[test]
+    fn invalid_keyframes() {
+        assert!(Motion::keyframes(vec![]).is_err());
+        assert!(Motion::keyframes(vec![(0.0, Vector3::zero()), (f32::NAN, Vector3::zero())]).is_err());
+    }
+}
@@ -1,25 +1,136 @@
 use crate::vector::Vector3;
 use crate::color::RGB;
 use crate::ray::Ray;
+use crate::aabb::AABB;
 use crate::sphere::Sphere;
+use crate::sdf::Sdf;
+use crate::heightfield::Heightfield;
+use crate::mesh::Mesh;
 use crate::collide::{Collision, Collide};
 use crate::material::{Scatter, Material};
+use crate::medium::Medium;
+use crate::emission::Emission;
+use crate::bump::NormalMap;
+use crate::texture::Texture;
 use rand::Rng;
 
 pub enum Shape {
     Sphere(Sphere),
+    Sdf(Sdf),
+    Heightfield(Heightfield),
+    Mesh(Mesh),
+}
+
+impl Shape {
+    /// returns a box that contains the shape while `time0 <= t <= time1`.
+    pub fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        match self {
+            Shape::Sphere(sphere)  => {sphere.bounding_box(time0, time1)}
+            Shape::Sdf(sdf)        => {sdf.bounding_box(time0, time1)}
+            Shape::Heightfield(hf) => {hf.bounding_box(time0, time1)}
+            Shape::Mesh(mesh)      => {mesh.bounding_box(time0, time1)}
+        }
+    }
+}
+
+/// how an opacity texture cuts out the surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cutout {
+    /// the surface exists where the opacity is at least the threshold
+    Threshold(f32),
+    /// the surface is hit with the probability of the opacity
+    Stochastic,
 }
 
 pub struct Object {
     pub shape:    Shape,
     pub albedo:   RGB,
-    pub emission: RGB,
+    pub emission: Emission,
     pub material: Material,
+    pub medium:   Option<Medium>, // inside of the shape
+    pub normal_map: Option<NormalMap>,
+    pub opacity:  Option<(Texture, Cutout)>,
 }
 
 impl Object {
+    fn with_shape(shape: Shape, material: Material, albedo: RGB, emission: RGB) -> Object {
+        Object{shape, albedo, emission: Emission::from(emission), material, medium: None,
+               normal_map: None, opacity: None}
+    }
     pub fn make_sphere(sphere: Sphere, material: Material, albedo: RGB, emission: RGB) -> Object {
-        Object{shape: Shape::Sphere(sphere), albedo, emission, material}
+        Object::with_shape(Shape::Sphere(sphere), material, albedo, emission)
+    }
+    pub fn make_sdf(sdf: Sdf, material: Material, albedo: RGB, emission: RGB) -> Object {
+        Object::with_shape(Shape::Sdf(sdf), material, albedo, emission)
+    }
+    pub fn make_heightfield(hf: Heightfield, material: Material, albedo: RGB, emission: RGB) -> Object {
+        Object::with_shape(Shape::Heightfield(hf), material, albedo, emission)
+    }
+    pub fn make_mesh(mesh: Mesh, material: Material, albedo: RGB, emission: RGB) -> Object {
+        Object::with_shape(Shape::Mesh(mesh), material, albedo, emission)
+    }
+    /// shape filled with `medium` with an invisible boundary.
+    pub fn make_volume(shape: Shape, medium: Medium) -> Object {
+        Object::with_shape(shape, Material::make_interface(), RGB::new(1.0, 1.0, 1.0),
+                           RGB::new(0.0, 0.0, 0.0)).with_medium(medium)
+    }
+
+    /// translucent object such as skin, wax or marble. The light refracts
+    /// at the dielectric boundary with `ior` and walks randomly inside.
+    /// `color` is the apparent albedo and `mfp` is the mean free path of
+    /// each channel.
+    pub fn make_subsurface(shape: Shape, color: RGB, mfp: RGB, ior: f32) -> Object {
+        Object::with_shape(shape, Material::make_dielectric(ior), RGB::new(1.0, 1.0, 1.0),
+                           RGB::new(0.0, 0.0, 0.0))
+            .with_medium(Medium::make_subsurface(color, mfp, 0.0))
+    }
+
+    /// replaces the emission, e.g. with a black body.
+    pub fn with_emission(mut self, emission: Emission) -> Object {
+        self.emission = emission;
+        self
+    }
+
+    /// perturbs the shading normal by a normal or bump map.
+    pub fn with_normal_map(mut self, map: NormalMap) -> Object {
+        self.normal_map = Some(map);
+        self
+    }
+
+    /// cuts out the surface where the opacity (red channel) is low, e.g.
+    /// for leaves on a quad.
+    pub fn with_opacity(mut self, opacity: Texture, cutout: Cutout) -> Object {
+        self.opacity = Some((opacity, cutout));
+        self
+    }
+
+    /// the nearest collision that is not cut out by the opacity.
+    pub fn collide_opaque<R: Rng>(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut R)
+        -> Option<Collision>
+    {
+        let (opacity, cutout) = match &self.opacity {
+            None    => {return self.collide_within(ray, t_min, t_max);}
+            Some(o) => {o}
+        };
+        let mut t_min = t_min;
+        loop {
+            let cr = self.collide_within(ray, t_min, t_max)?;
+            let alpha = opacity.scalar(cr.uv, ray.at(cr.t));
+            let opaque = match cutout {
+                Cutout::Threshold(threshold) => {alpha >= *threshold}
+                Cutout::Stochastic => {rng.gen_range(0.0f32, 1.0f32) < alpha}
+            };
+            if opaque {
+                return Some(cr);
+            }
+            t_min = cr.t + 1e-4;
+        }
+    }
+
+    /// fills the inside of the object with `medium`, e.g. tinted glass.
+    pub fn with_medium(mut self, medium: Medium) -> Object {
+        self.medium = Some(medium);
+        self
     }
 }
 
@@ -27,14 +138,136 @@ impl Collide for Object {
     fn collide_within(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Collision> {
         match &self.shape {
             Shape::Sphere(sphere) => {sphere.collide_within(ray, t_min, t_max)}
+            Shape::Sdf(sdf)       => {sdf.collide_within(ray, t_min, t_max)}
+            Shape::Heightfield(hf) => {hf.collide_within(ray, t_min, t_max)}
+            Shape::Mesh(mesh)     => {mesh.collide_within(ray, t_min, t_max)}
         }
     }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        self.shape.bounding_box(time0, time1)
+    }
 }
 
 impl Scatter for Object {
-    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> Ray {
-        self.material.scatter(ray, cr, rng)
+    fn scatter<R: Rng>(&self, ray: &Ray, cr: Collision, rng: &mut R) -> (Ray, RGB) {
+        let map = match &self.normal_map {
+            Some(map) => {map}
+            None      => {return self.material.scatter(ray, cr, rng);}
+        };
+        let cr = map.apply(cr, ray.at(cr.t)).facing(ray);
+        let (next, attenuation) = self.material.scatter(ray, cr, rng);
+        // the shading normal may say reflection where the surface says
+        // transmission or vice versa. such a path does not exist.
+        if cr.agrees(-ray.direction, next.direction) {
+            (next, attenuation)
+        } else {
+            (next, RGB::new(0.0, 0.0, 0.0))
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::object::*;
+    use crate::vector::Vector3;
+    use crate::background::SkyBg;
+    use crate::world::World;
+    use rand::{RngCore, SeedableRng};
+
+    fn quad(opacity: Texture, cutout: Cutout) -> Object {
+        let mesh = Mesh::grid(Vector3::new(-1.0, 0.0, 1.0), Vector3::new(2.0, 0.0, 0.0),
+                              Vector3::new(0.0, 0.0, -2.0), 1, 1);
+        Object::make_mesh(mesh, Material::make_diffuse(), RGB::new(0.5, 0.5, 0.5),
+                          RGB::new(0.0, 0.0, 0.0)).with_opacity(opacity, cutout)
+    }
+
+    #[test]
+    fn threshold_cuts_out() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let hole  = quad(Texture::from(0.2), Cutout::Threshold(0.5));
+        assert!(hole.collide_opaque(&ray, 1e-4, f32::INFINITY, &mut rng).is_none());
+        let solid = quad(Texture::from(0.8), Cutout::Threshold(0.5));
+        assert!(solid.collide_opaque(&ray, 1e-4, f32::INFINITY, &mut rng).is_some());
+    }
+
+    #[test]
+    fn stochastic_ratio() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(2);
+        let ray  = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let leaf = quad(Texture::from(0.3), Cutout::Stochastic);
+        let n    = 10000;
+        let hits = (0..n)
+            .filter(|_| leaf.collide_opaque(&ray, 1e-4, f32::INFINITY, &mut rng).is_some())
+            .count();
+        assert!((hits as f32 / n as f32 - 0.3).abs() < 0.02);
+    }
+
+    #[test]
+    fn subsurface_conserves_energy() {
+        use crate::background::UniBg;
+        use crate::color::Color;
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(4);
+        let ball  = Object::make_subsurface(Shape::Sphere(Sphere::new(Vector3::zero(), 1.0)),
+                                            RGB::new(1.0, 1.0, 1.0), RGB::new(0.5, 1.0, 2.0), 1.4);
+        let world = World::new(vec![ball], UniBg::new(RGB::new(1.0, 1.0, 1.0)));
+        let n = 20000;
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let y   = (i as f32 + 0.5) / n as f32 * 1.8 - 0.9;
+            let ray = Ray::new(Vector3::new(0.0, y, 5.0), Vector3::new(0.0, 0.0, -1.0));
+            sum += world.color(ray, &mut rng, 0).0;
+        }
+        let mean = sum / n as f32;
+        // a white furnace: nothing is absorbed
+        assert!((mean.r() - 1.0).abs() < 0.05 && (mean.b() - 1.0).abs() < 0.05, "{:?}", mean);
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(5);
+        let ball  = Object::make_subsurface(Shape::Sphere(Sphere::new(Vector3::zero(), 1.0)),
+                                            RGB::new(0.9, 0.5, 0.2), RGB::new(0.5, 0.5, 0.5), 1.4);
+        let world = World::new(vec![ball], UniBg::new(RGB::new(1.0, 1.0, 1.0)));
+        let ray   = Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            sum += world.color(ray.clone(), &mut rng, 0).0;
+        }
+        assert!(sum.r() > sum.g() && sum.g() > sum.b(), "{:?}", sum);
 
+        // hundreds of scattering events, more than the depth limit
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(6);
+        let ball  = Object::make_subsurface(Shape::Sphere(Sphere::new(Vector3::zero(), 1.0)),
+                                            RGB::new(1.0, 1.0, 1.0), RGB::new(0.05, 0.05, 0.05), 1.4);
+        let world = World::new(vec![ball], UniBg::new(RGB::new(1.0, 1.0, 1.0)));
+        let n = 2000;
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            sum += world.color(ray.clone(), &mut rng, 0).0;
+        }
+        let mean = sum / n as f32;
+        assert!((mean.g() - 1.0).abs() < 0.05, "{:?}", mean);
+    }
+
+    #[test]
+    fn masks_behind_t_max_draw_nothing() {
+        let mut rng  = rand_xorshift::XorShiftRng::seed_from_u64(4);
+        let mut copy = rng.clone();
+        let ray  = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let leaf = quad(Texture::from(0.5), Cutout::Stochastic);
+        assert!(leaf.collide_opaque(&ray, 1e-4, 0.5, &mut rng).is_none());
+        assert_eq!(rng.next_u64(), copy.next_u64());
+    }
+
+    #[test]
+    fn shadow_rays_pass_holes() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(3);
+        let from = Vector3::new(0.0, 1.0, 0.0);
+        let to   = Vector3::new(0.0, -1.0, 0.0);
+        let open = World::new(vec![quad(Texture::from(0.0), Cutout::Threshold(0.5))], SkyBg::new());
+        assert!(open.visible(from, to, 0.0, &mut rng));
+        let shut = World::new(vec![quad(Texture::from(1.0), Cutout::Threshold(0.5))], SkyBg::new());
+        assert!(!shut.visible(from, to, 0.0, &mut rng));
+        // the segment ends before the quad
+        assert!(shut.visible(from, Vector3::new(0.0, 0.5, 0.0), 0.0, &mut rng));
+    }
+}
@@ -0,0 +1,52 @@
+//! progress reports and cancellation of a render.
+
+use crate::film::Tile;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// reported each time a tile is done.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub tile:        Tile, // that is just done
+    pub tiles_done:  usize,
+    pub tiles_total: usize,
+    pub elapsed:     std::time::Duration,
+}
+
+impl Progress {
+    pub fn percentage(&self) -> f32 {
+        if self.tiles_total == 0 {
+            return 100.0;
+        }
+        100.0 * self.tiles_done as f32 / self.tiles_total as f32
+    }
+
+    /// estimated time to the end, assuming the rest of the tiles take as
+    /// long as the ones done so far.
+    pub fn eta(&self) -> Option<std::time::Duration> {
+        if self.tiles_done == 0 {
+            return None;
+        }
+        let left = (self.tiles_total - self.tiles_done) as f64 / self.tiles_done as f64;
+        Some(self.elapsed.mul_f64(left))
+    }
+}
+
+/// stops a render from another thread. The clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken{cancelled: Arc::new(AtomicBool::new(false))}
+    }
+    #[cfg(test)]
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,328 @@
+//! progressive rendering.
+//!
+//! The whole image is refined pass by pass, so that a snapshot of the
+//! image can be written while rendering. Each pass adds a few samples to
+//! every pixel that has not converged by the threshold of the camera. It
+//! stops when the time budget is used up, every pixel has the target
+//! number of samples, or all of them have converged, whichever comes first.
+//! Without a target, the `max_spp` of the camera is the target.
+//!
+//! A checkpoint may be written as well, so that a render that gets killed
+//! can be resumed from it. A render that is cancelled stops within the
+//! pass, and writes the snapshot and the checkpoint as when it stops.
+
+use crate::camera::Camera;
+use crate::world::World;
+use crate::film::Film;
+use crate::background::Background;
+use crate::checkpoint::Checkpoint;
+use crate::progress::{Progress, CancelToken};
+use crate::error::{Error, ErrorKind, Result};
+
+type Report = Box<dyn Fn(&Progress) + Send>;
+
+pub struct Progressive {
+    snapshot:         Option<std::path::PathBuf>,
+    snapshot_passes:  Option<usize>,
+    snapshot_seconds: Option<f32>,
+    time_budget:      Option<f32>,
+    target_spp:       Option<usize>,
+    pass_spp:         usize,
+    checkpoint:         Option<std::path::PathBuf>,
+    checkpoint_passes:  Option<usize>,
+    checkpoint_seconds: Option<f32>,
+    cancel:             CancelToken,
+    progress:           Option<Report>,
+}
+
+/// whether `passes` passes or `seconds` seconds since `last` have gone by.
+fn due(passes: Option<usize>, seconds: Option<f32>, pass: usize, last: std::time::Instant) -> bool {
+    passes.is_some_and(|n| pass % n == 0) ||
+        seconds.is_some_and(|t| last.elapsed().as_secs_f32() >= t)
+}
+
+impl Progressive {
+    /// runs until all the pixels converge.
+    pub fn new() -> Self {
+        Progressive{snapshot: None, snapshot_passes: None, snapshot_seconds: None,
+                    time_budget: None, target_spp: None, pass_spp: 1,
+                    checkpoint: None, checkpoint_passes: None, checkpoint_seconds: None,
+                    cancel: CancelToken::new(), progress: None}
+    }
+
+    /// writes the image into the PPM file every `passes` passes and every
+    /// `seconds`. Either of them may be `None`. The final image is always
+    /// written.
+    pub fn with_snapshot<P>(mut self, path: P, passes: Option<usize>, seconds: Option<f32>) -> Self
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        self.snapshot         = Some(path.as_ref().to_path_buf());
+        self.snapshot_passes  = passes.map(|n| n.max(1));
+        self.snapshot_seconds = seconds;
+        self
+    }
+
+    /// writes a checkpoint every `passes` passes and every `seconds`, and
+    /// when the render stops.
+    pub fn with_checkpoint<P>(mut self, path: P, passes: Option<usize>, seconds: Option<f32>) -> Self
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        self.checkpoint         = Some(path.as_ref().to_path_buf());
+        self.checkpoint_passes  = passes.map(|n| n.max(1));
+        self.checkpoint_seconds = seconds;
+        self
+    }
+
+    /// stops after the pass that exceeds the wall-clock time in seconds.
+    pub fn with_time_budget(mut self, seconds: f32) -> Self {
+        self.time_budget = Some(seconds);
+        self
+    }
+
+    /// stops when every pixel has at least `spp` samples. It may be more
+    /// than the `max_spp` of the camera.
+    pub fn with_target_spp(mut self, spp: usize) -> Self {
+        self.target_spp = Some(spp);
+        self
+    }
+
+    /// the number of samples added to a pixel in a pass. 1 by default.
+    pub fn with_pass_spp(mut self, spp: usize) -> Self {
+        self.pass_spp = spp.max(1);
+        self
+    }
+
+    /// stops the render when the token is cancelled.
+    #[cfg(test)]
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// calls `report` each time a tile of a pass is done. The percentage
+    /// and the time are of the pass.
+    pub fn with_progress<F>(mut self, report: F) -> Self
+    where
+        F: Fn(&Progress) + Send + 'static
+    {
+        self.progress = Some(Box::new(report));
+        self
+    }
+
+    pub fn render<Bg: Background>(&self, camera: &Camera, world: &World<Bg>) -> Result<Film> {
+        self.resume(camera, world, camera.film())
+    }
+
+    /// continues rendering into the film.
+    pub fn resume<Bg: Background>(&self, camera: &Camera, world: &World<Bg>, film: Film)
+        -> Result<Film>
+    {
+        self.run(camera, world, film, 0)
+    }
+
+    /// continues rendering from the checkpoint. The camera must have the
+    /// seed of the checkpoint, see `Checkpoint::read`.
+    pub fn resume_from<Bg: Background>(&self, camera: &Camera, world: &World<Bg>,
+                                       checkpoint: Checkpoint) -> Result<Film> {
+        let empty = camera.film();
+        let mismatch = |msg: &str| Err(Error::new(ErrorKind::Mismatch(msg.to_string())));
+        if checkpoint.seed != camera.seed() {
+            return mismatch("the checkpoint was rendered with another seed");
+        }
+        if checkpoint.film.bounds() != empty.bounds() {
+            return mismatch("the checkpoint has another image size");
+        }
+        if checkpoint.film.filter() != empty.filter() {
+            return mismatch("the checkpoint was rendered with another filter");
+        }
+        self.run(camera, world, checkpoint.film, checkpoint.passes)
+    }
+
+    fn run<Bg: Background>(&self, camera: &Camera, world: &World<Bg>, film: Film, passes: usize)
+        -> Result<Film>
+    {
+        let mut film = film;
+        let start = std::time::Instant::now();
+        let (mut last_snapshot, mut last_checkpoint) = (start, start);
+        let mut pass = passes;
+        let max_spp  = self.target_spp.unwrap_or(camera.adaptive().max_spp);
+        loop {
+            if self.target_spp.is_some_and(|spp| film.min_count() >= spp) {
+                break;
+            }
+            let report = |p: &Progress| {
+                if let Some(progress) = &self.progress {
+                    progress(p);
+                }
+            };
+            if !camera.render_pass(world, &mut film, self.pass_spp, max_spp, &self.cancel, report) {
+                break;
+            }
+            pass += 1;
+            if self.time_budget.is_some_and(|t| start.elapsed().as_secs_f32() >= t) {
+                break;
+            }
+            if due(self.snapshot_passes, self.snapshot_seconds, pass, last_snapshot) {
+                self.write_snapshot(&film)?;
+                last_snapshot = std::time::Instant::now();
+            }
+            if due(self.checkpoint_passes, self.checkpoint_seconds, pass, last_checkpoint) {
+                film = self.write_checkpoint(camera, film, pass)?;
+                last_checkpoint = std::time::Instant::now();
+            }
+        }
+        self.write_snapshot(&film)?;
+        self.write_checkpoint(camera, film, pass)
+    }
+
+    fn write_snapshot(&self, film: &Film) -> Result<()> {
+        match &self.snapshot {
+            Some(path) => {film.to_image().write_ppm(path)}
+            None       => {Ok(())}
+        }
+    }
+
+    /// gives the film back.
+    fn write_checkpoint(&self, camera: &Camera, film: Film, passes: usize) -> Result<Film> {
+        match &self.checkpoint {
+            Some(path) => {
+                let checkpoint = Checkpoint{seed: camera.seed(), passes, film};
+                checkpoint.write(path)?;
+                Ok(checkpoint.film)
+            }
+            None => {Ok(film)}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progressive::*;
+    use crate::camera::tests::small_scene;
+    use crate::filter::Filter;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn stops_at_target_spp() {
+        let (builder, world) = small_scene();
+        let camera = builder.samples_per_pixel(4).seed(1).build();
+        let path   = std::env::temp_dir().join(format!("progressive-{}.ppm", std::process::id()));
+        let film   = Progressive::new()
+            .with_snapshot(&path, Some(1), None)
+            .with_pass_spp(2)
+            .with_target_spp(6)
+            .render(&camera, &world).unwrap();
+        // beyond the samples of the camera
+        assert_eq!(film.min_count(), 6);
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"P6\n16 8\n255\n"));
+        assert_eq!(bytes.len(), 12 + 16 * 8 * 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refines_pass_by_pass() {
+        let (builder, world) = small_scene();
+        let camera = builder.samples_per_pixel(4).seed(1).build();
+        let path   = std::env::temp_dir().join(format!("progressive-{}-passes.ckpt", std::process::id()));
+        let film   = Progressive::new()
+            .with_checkpoint(&path, None, None)
+            .render(&camera, &world).unwrap();
+        assert_eq!(film.min_count(), 4);
+        let checkpoint = Checkpoint::read(&path).unwrap();
+        assert_eq!(checkpoint.passes, 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stops_at_time_budget() {
+        let (builder, world) = small_scene();
+        let camera = builder.seed(2).build();
+        let film   = Progressive::new().with_time_budget(0.0).render(&camera, &world).unwrap();
+        assert_eq!(film.min_count(), 1);
+    }
+
+    #[test]
+    fn resume_matches_uninterrupted_render() {
+        let (builder, world) = small_scene();
+        let camera = builder.adaptive(2, 16, 0.0).seed(3).build();
+        let whole  = Progressive::new().with_pass_spp(2).with_target_spp(8)
+            .render(&camera, &world).unwrap();
+
+        let path = std::env::temp_dir().join(format!("progressive-{}.ckpt", std::process::id()));
+        let part = Progressive::new()
+            .with_checkpoint(&path, Some(1), None)
+            .with_pass_spp(2)
+            .with_target_spp(4)
+            .render(&camera, &world).unwrap();
+        assert_eq!(part.min_count(), 4);
+        let checkpoint = Checkpoint::read(&path).unwrap();
+        assert_eq!((checkpoint.seed, checkpoint.passes), (3, 2));
+
+        let resumed = Progressive::new()
+            .with_pass_spp(2)
+            .with_target_spp(8)
+            .resume_from(&camera, &world, checkpoint).unwrap();
+        for (x, y) in whole.bounds().pixels() {
+            assert_eq!(resumed.count(x, y), whole.count(x, y));
+            assert_eq!(resumed.color(x, y), whole.color(x, y));
+        }
+
+        // another seed does not continue the same render
+        let (builder, world) = small_scene();
+        let other = builder.adaptive(2, 16, 0.0).seed(4).build();
+        let checkpoint = Checkpoint::read(&path).unwrap();
+        assert!(Progressive::new().resume_from(&other, &world, checkpoint).is_err());
+        // nor another filter
+        let (builder, world) = small_scene();
+        let other = builder.adaptive(2, 16, 0.0).seed(3).filter(Filter::make_tent(1.0)).build();
+        let checkpoint = Checkpoint::read(&path).unwrap();
+        assert!(Progressive::new().resume_from(&other, &world, checkpoint).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cancelled_render_resumes() {
+        let (builder, world) = small_scene();
+        let camera = builder.width(48).height(32).samples_per_pixel(4).seed(6).build();
+        let total  = camera.tiles().len();
+        let whole  = Progressive::new().render(&camera, &world).unwrap();
+
+        // cancelled after two tiles of the second pass
+        let path    = std::env::temp_dir().join(format!("progressive-{}-cancel.ckpt", std::process::id()));
+        let cancel  = CancelToken::new();
+        let token   = cancel.clone();
+        let reported = std::sync::atomic::AtomicUsize::new(0);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let part = Progressive::new()
+            .with_checkpoint(&path, None, None)
+            .with_cancel(cancel)
+            .with_progress(move |p| {
+                sender.send(*p).unwrap();
+                if reported.fetch_add(1, Ordering::Relaxed) + 1 == total + 2 {
+                    token.cancel();
+                }
+            })
+            .render(&camera, &world).unwrap();
+        let reports: std::vec::Vec<Progress> = receiver.try_iter().collect();
+        assert_eq!(reports.len(), total + 2);
+        assert_eq!(reports[total - 1].percentage(), 100.0);
+        assert_eq!(reports[total + 1].tiles_done, 2);
+        for (i, tile) in camera.tiles().into_iter().enumerate() {
+            for (x, y) in tile.pixels() {
+                assert_eq!(part.count(x, y), if i < 2 {2} else {1});
+            }
+        }
+        let checkpoint = Checkpoint::read(&path).unwrap();
+        assert_eq!(checkpoint.passes, 1);
+
+        let resumed = Progressive::new().resume_from(&camera, &world, checkpoint).unwrap();
+        for (x, y) in whole.bounds().pixels() {
+            assert_eq!(resumed.count(x, y), 4);
+            assert_eq!(resumed.color(x, y), whole.color(x, y));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,14 +1,39 @@
 use crate::vector::Vector3;
+use crate::color::RGB;
+use crate::spectrum::Wavelengths;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Ray {
     pub origin:    Vector3,
     pub direction: Vector3,
+    pub time:      f32,
+    pub wavelengths: Option<Wavelengths>, // only in the spectral mode
 }
 
 impl Ray {
     pub fn new(origin: Vector3, direction: Vector3) -> Ray {
-        Ray{origin, direction: Vector3::unit(direction)}
+        Ray{origin, direction: Vector3::unit(direction), time: 0.0, wavelengths: None}
+    }
+    pub fn with_time(origin: Vector3, direction: Vector3, time: f32) -> Ray {
+        Ray{origin, direction: Vector3::unit(direction), time,
+            wavelengths: None}
+    }
+    pub fn with_wavelengths(mut self, wavelengths: Wavelengths) -> Ray {
+        self.wavelengths = Some(wavelengths);
+        self
+    }
+    /// a ray that follows this one, at the same time and wavelengths.
+    pub fn spawn(&self, origin: Vector3, direction: Vector3) -> Ray {
+        Ray{origin, direction: Vector3::unit(direction), time: self.time,
+            wavelengths: self.wavelengths}
+    }
+    /// the color as it is seen by this ray. In the spectral mode, it is
+    /// the upsampled spectrum at the wavelengths of the ray.
+    pub fn tint(&self, color: RGB) -> RGB {
+        match &self.wavelengths {
+            None    => {color}
+            Some(w) => {w.upsample(color)}
+        }
     }
     pub fn at(&self, t: f32) -> Vector3 {
         self.origin + self.direction * t
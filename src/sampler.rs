@@ -0,0 +1,415 @@
+//! sample generators for Monte Carlo integration.
+//!
+//! A sampler gives the `index`-th sample vector of a pixel one dimension at
+//! a time. It implements `RngCore`, so every `rng.gen_range(0.0, 1.0)` in the
+//! camera, the materials and the media draws the next dimension; a path
+//! consumes the dimensions in the same order for all samples of a pixel.
+//! Dimensions that a pattern does not cover are filled with independent
+//! random numbers.
+
+use rand_core::{RngCore, SeedableRng};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// independent uniform random numbers
+    Independent,
+    /// jittered strata, shuffled per dimension, for the given samples per
+    /// pixel
+    Stratified(usize),
+    /// Halton sequence with the digits scrambled by random permutations
+    Halton,
+    /// Owen-scrambled Sobol sequence, padded from 2D
+    Sobol,
+    /// Owen-scrambled Sobol shifted by a blue-noise mask so that the error
+    /// is distributed as blue noise over the pixels
+    BlueNoise,
+}
+
+pub struct Sampler {
+    pattern: Pattern,
+    seed:    u32,
+    pixel:   u32, // hash of the pixel and the seed
+    coord:   (u32, u32),
+    index:   u32,
+    dim:     u32,
+    rng:     rand_xorshift::XorShiftRng,
+}
+
+impl Sampler {
+    pub fn new(pattern: Pattern, seed: u64) -> Self {
+        let seed = hash(seed as u32 ^ hash((seed >> 32) as u32));
+        Sampler{pattern, seed, pixel: seed, coord: (0, 0), index: 0, dim: 0,
+                rng: rand_xorshift::XorShiftRng::seed_from_u64(seed as u64)}
+    }
+
+    /// starts the `index`-th sample of the pixel from the first dimension.
+    pub fn start_pixel(&mut self, x: usize, y: usize, index: usize) {
+        self.coord = (x as u32, y as u32);
+        self.pixel = hash(hash(hash(x as u32) ^ y as u32) ^ self.seed);
+        self.index = index as u32;
+        self.dim   = 0;
+        let key = (self.pixel as u64) << 32 | index as u64;
+        self.rng = rand_xorshift::XorShiftRng::seed_from_u64(key);
+    }
+
+    /// the next dimension in [0, 1).
+    pub fn get_1d(&mut self) -> f32 {
+        let u = self.sample(self.dim);
+        self.dim += 1;
+        u
+    }
+
+    /// the next two dimensions in [0, 1)^2.
+    pub fn get_2d(&mut self) -> (f32, f32) {
+        let u = self.get_1d();
+        let v = self.get_1d();
+        (u, v)
+    }
+
+    fn sample(&mut self, dim: u32) -> f32 {
+        let key = hash(self.pixel ^ hash(dim));
+        let u = match self.pattern {
+            Pattern::Independent => {None}
+            Pattern::Stratified(spp) => {
+                let spp   = spp.max(1) as u32;
+                let round = self.index / spp;
+                let s     = permute(self.index % spp, spp, hash(key ^ round));
+                Some((s as f32 + self.jitter()) / spp as f32)
+            }
+            Pattern::Halton => {
+                PRIMES.get(dim as usize).map(|&base| scrambled_radical_inverse(self.index, base, key))
+            }
+            Pattern::Sobol => {Some(to_unit(sobol_owen(self.index, dim, self.pixel)))}
+            Pattern::BlueNoise => {
+                // the same scramble for all pixels, decorrelated by the mask
+                let (x, y) = self.coord;
+                let shift  = blue_noise(x.wrapping_add(dim.wrapping_mul(17)),
+                                        y.wrapping_add(dim.wrapping_mul(29)));
+                let u = to_unit(sobol_owen(self.index, dim, self.seed)) + shift;
+                Some(u - u.floor())
+            }
+        };
+        match u {
+            Some(u) => {u.min(ONE_MINUS_EPSILON)}
+            None    => {self.jitter()}
+        }
+    }
+
+    fn jitter(&mut self) -> f32 {
+        to_unit(self.rng.next_u32())
+    }
+}
+
+impl RngCore for Sampler {
+    fn next_u32(&mut self) -> u32 {
+        (self.get_1d() as f64 * 4294967296.0) as u32
+    }
+    fn next_u64(&mut self) -> u64 {
+        // the lower half only refines the value
+        (self.next_u32() as u64) << 32 | self.rng.next_u32() as u64
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+fn to_unit(x: u32) -> f32 {
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// integer hash with a low bias (Wellons)
+fn hash(x: u32) -> u32 {
+    let mut x = x;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+/// the `i`-th element of a random permutation of `0..n` (Kensler 2013)
+fn permute(i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            return i.wrapping_add(seed) % n;
+        }
+    }
+}
+
+const PRIMES: [u32; 64] = [
+      2,   3,   5,   7,  11,  13,  17,  19,  23,  29,  31,  37,  41,  43,  47,  53,
+     59,  61,  67,  71,  73,  79,  83,  89,  97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+/// radical inverse with each digit permuted, with a permutation per digit
+/// position. The trailing zeros are permuted as well.
+fn scrambled_radical_inverse(i: u32, base: u32, seed: u32) -> f32 {
+    let inv = 1.0 / base as f64;
+    let (mut i, mut r, mut f, mut level) = (i, 0.0f64, inv, 0u32);
+    while f > 1e-8 {
+        let digit = permute(i % base, base, hash(seed ^ level));
+        r += digit as f64 * f;
+        i /= base;
+        f *= inv;
+        level += 1;
+    }
+    r as f32
+}
+
+/// generator matrices of the first two Sobol dimensions, which form a
+/// (0, 2)-sequence. The second one is from the polynomial `x + 1`.
+fn sobol(i: u32, dim: u32) -> u32 {
+    let mut v = 0;
+    let mut m = 1u32;
+    for k in 0..32 {
+        if i >> k & 1 == 1 {
+            v ^= if dim == 0 {1 << (31 - k)} else {m << (31 - k)};
+        }
+        m ^= m << 1;
+    }
+    v
+}
+
+fn laine_karras(x: u32, seed: u32) -> u32 {
+    let mut x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// Owen-scrambled Sobol. Each pair of dimensions has its own shuffle of
+/// the indices (Burley 2020).
+fn sobol_owen(index: u32, dim: u32, seed: u32) -> u32 {
+    let pair  = dim / 2;
+    let index = nested_uniform_scramble(index, hash(seed ^ hash(pair)));
+    nested_uniform_scramble(sobol(index, dim % 2), hash(seed ^ hash(dim) ^ 0x5bd1_e995))
+}
+
+const MASK_SIZE: usize = 32;
+
+/// value of a tileable blue-noise mask in [0, 1).
+fn blue_noise(x: u32, y: u32) -> f32 {
+    static MASK: OnceLock<std::vec::Vec<f32>> = OnceLock::new();
+    let mask = MASK.get_or_init(|| void_and_cluster(MASK_SIZE, 0x2545_f491));
+    mask[(y as usize % MASK_SIZE) * MASK_SIZE + x as usize % MASK_SIZE]
+}
+
+/// ranks the pixels of a toroidal `size` x `size` grid by the
+/// void-and-cluster method (Ulichney 1993).
+fn void_and_cluster(size: usize, seed: u32) -> std::vec::Vec<f32> {
+    let n = size * size;
+    const SIGMA: f32 = 1.5;
+    let kernel: std::vec::Vec<f32> = (0..n).map(|i| {
+        let wrap = |d: usize| {let d = d as f32; d.min(size as f32 - d)};
+        let (dx, dy) = (wrap(i % size), wrap(i / size));
+        (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+    }).collect();
+    let splat = |energy: &mut [f32], p: usize, sign: f32| {
+        let (px, py) = (p % size, p / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    let tightest = |pattern: &[bool], energy: &[f32]| {
+        (0..n).filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap()).unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..n).filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap()).unwrap()
+    };
+
+    // initial pattern with a tenth of the pixels, relaxed until stable
+    let mut pattern = vec![false; n];
+    let mut energy  = vec![0.0; n];
+    let mut ones    = 0;
+    let mut state   = seed;
+    while ones < n / 10 {
+        state = hash(state);
+        let p = state as usize % n;
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+            ones += 1;
+        }
+    }
+    loop {
+        let c = tightest(&pattern, &energy);
+        pattern[c] = false;
+        splat(&mut energy, c, -1.0);
+        let v = largest_void(&pattern, &energy);
+        pattern[v] = true;
+        splat(&mut energy, v, 1.0);
+        if c == v {
+            break;
+        }
+    }
+
+    let mut rank = vec![0usize; n];
+    // remove the clusters of the initial pattern for the lower ranks
+    let (mut p, mut e) = (pattern.clone(), energy.clone());
+    for r in (0..ones).rev() {
+        let c = tightest(&p, &e);
+        p[c] = false;
+        splat(&mut e, c, -1.0);
+        rank[c] = r;
+    }
+    // fill the voids for the higher ranks
+    for r in ones..n {
+        let v = largest_void(&pattern, &energy);
+        pattern[v] = true;
+        splat(&mut energy, v, 1.0);
+        rank[v] = r;
+    }
+    rank.into_iter().map(|r| (r as f32 + 0.5) / n as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sampler::*;
+    use rand::Rng;
+
+    /// every interval [k/n, (k+1)/n) contains one of the n samples
+    fn is_stratified(samples: &[f32]) -> bool {
+        let n = samples.len();
+        let mut hit = vec![false; n];
+        for &u in samples {
+            hit[(u * n as f32) as usize] = true;
+        }
+        hit.iter().all(|&h| h)
+    }
+
+    #[test]
+    fn one_dimensional_stratification() {
+        for &pattern in &[Pattern::Stratified(64), Pattern::Sobol] {
+            let mut sampler = Sampler::new(pattern, 42);
+            for dim in 0..8 {
+                let samples: std::vec::Vec<f32> = (0..64).map(|i| {
+                    sampler.start_pixel(3, 5, i);
+                    (0..dim).for_each(|_| {sampler.get_1d();});
+                    sampler.get_1d()
+                }).collect();
+                assert!(is_stratified(&samples), "{:?} {}", pattern, dim);
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_pairs_are_nets() {
+        // each elementary interval of area 1/16 contains one of 16 points
+        let mut sampler = Sampler::new(Pattern::Sobol, 7);
+        for pair in 0..4 {
+            let points: std::vec::Vec<(f32, f32)> = (0..16).map(|i| {
+                sampler.start_pixel(0, 0, i);
+                (0..pair).for_each(|_| {sampler.get_2d();});
+                sampler.get_2d()
+            }).collect();
+            for &(nx, ny) in &[(1, 16), (2, 8), (4, 4), (8, 2), (16, 1)] {
+                let mut count = [0; 16];
+                for &(u, v) in points.iter() {
+                    count[(v * ny as f32) as usize * nx + (u * nx as f32) as usize] += 1;
+                }
+                assert!(count.iter().all(|&c| c == 1), "{} {}x{}", pair, nx, ny);
+            }
+        }
+    }
+
+    #[test]
+    fn scrambled_halton() {
+        // the digits of the index in reverse order, up to the permutation
+        let u = scrambled_radical_inverse(5, 3, 0);
+        let digits = [0, 1, 2].iter()
+            .map(|&level| permute([2, 1, 0][level], 3, hash(level as u32)))
+            .collect::<std::vec::Vec<_>>();
+        let head = digits[0] as f32 / 3.0 + digits[1] as f32 / 9.0 + digits[2] as f32 / 27.0;
+        assert!(head <= u && u < head + 1.0 / 27.0);
+        let mut sampler = Sampler::new(Pattern::Halton, 1);
+        let samples: std::vec::Vec<f32> = (0..27).map(|i| {
+            sampler.start_pixel(1, 1, i);
+            sampler.get_1d();
+            sampler.get_1d()
+        }).collect();
+        assert!(is_stratified(&samples));
+    }
+
+    #[test]
+    fn drives_rng_consumers() {
+        let mut sampler = Sampler::new(Pattern::Sobol, 3);
+        let n = 256;
+        let mean = (0..n).map(|i| {
+            sampler.start_pixel(0, 0, i);
+            sampler.gen_range(0.0f32, 1.0f32)
+        }).sum::<f32>() / n as f32;
+        assert!((mean - 0.5).abs() < 1e-2);
+        // deterministic for the same pixel and index
+        sampler.start_pixel(2, 2, 9);
+        let a = (sampler.gen_range(0.0f32, 1.0f32), sampler.gen_range(0usize, 10usize));
+        sampler.start_pixel(2, 2, 9);
+        let b = (sampler.gen_range(0.0f32, 1.0f32), sampler.gen_range(0usize, 10usize));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn blue_noise_mask() {
+        let n = MASK_SIZE * MASK_SIZE;
+        let mut values: std::vec::Vec<f32> = (0..n)
+            .map(|i| blue_noise((i % MASK_SIZE) as u32, (i / MASK_SIZE) as u32)).collect();
+        // a permutation of the ranks
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(values.iter().enumerate().all(|(i, &v)| (v - (i as f32 + 0.5) / n as f32).abs() < 1e-6));
+        // low-pass filtered mask is flatter than white noise would be
+        let mut var = 0.0;
+        for y in 0..MASK_SIZE as u32 {
+            for x in 0..MASK_SIZE as u32 {
+                let avg = (blue_noise(x, y) + blue_noise(x + 1, y) +
+                           blue_noise(x, y + 1) + blue_noise(x + 1, y + 1)) / 4.0 - 0.5;
+                var += avg * avg;
+            }
+        }
+        // white noise: 1/12/4
+        assert!((var / n as f32) < 0.5 / 48.0, "{}", var / n as f32);
+    }
+}
@@ -0,0 +1,952 @@
+//! plain-text scene files.
+//!
+//! One statement per line, and `#` starts a comment.
+//!
+//! ```text
+//! size       640 320          # width and height in pixels
+//! samples    100              # per pixel
+//! adaptive   16 1024 0.01     # or from min to max samples per pixel until
+//!                             # the relative error is below the threshold
+//! seed       1                # of the sampler, for the same image everywhere
+//! sampler    sobol            # independent, stratified n, halton, sobol
+//!                             # or blue-noise
+//! filter     gaussian 1.5 0.5 # box r, tent r, gaussian r sigma, mitchell r,
+//!                             # lanczos r or blackman-harris r
+//! camera     -2 0 1  2 0.2 -2  90 0.01 3.46   # position, direction,
+//!                                             # vertical fov, aperture, focus
+//! shutter    0 1              # open and close times, for motion blur
+//! spectral                    # traces wavelengths, e.g. for dispersion
+//! background 0.5 0.5 0.5
+//! fog        0 0.02  1 1 1  0  100   # absorption, scattering, color,
+//!                                     # phase asymmetry g, horizon
+//! sphere     0 0 -1  0.5  diffuse         0.8 0.3 0.3
+//! sphere     1 0 -1  0.5  metal 0.3       0.8 0.6 0.2
+//! sphere    -1 0 -1  0.5  dielectric 1.5  1 1 1
+//! sphere     0 1 -1  0.5  diffuse         1 1 1  emission 0.4 0.4 0
+//! sdf        torus 0 0 -2  0.5 0.1  smooth-union 0.2  sphere 0 0.3 -2  0.2  metal 0.1  1 1 1
+//! ```
+//!
+//! An object is the shape, the material and the albedo, optionally
+//! followed by the emission. The materials are
+//!
+//! ```text
+//! diffuse
+//! metal      fuzziness
+//! dielectric ior
+//! conductor  eta k alpha-x alpha-y     # GGX, complex ior in RGB
+//! gold | copper | aluminium | silver roughness
+//! rough-dielectric ior roughness absorption
+//! principled base-color [parameter value]...
+//! oren-nayar sigma                     # roughness in radians
+//! retro-reflective exponent diffuse
+//! layered    color ior thickness absorption base   # a coat over a material
+//! diffuse-transmission reflectance transmittance   # e.g. paper or leaves
+//! thin-dielectric ior                  # a sheet of glass without refraction
+//! dispersive bk7 | diamond | cauchy a b | sellmeier b1 b2 b3 c1 c2 c3   # in micrometers
+//! ```
+//!
+//! The options after the albedo are
+//!
+//! ```text
+//! emission   color
+//! blackbody  kelvin luminance
+//! spectral   spectrum luminance
+//! lumens     spectrum flux area        # a diffuse emitter of the area
+//! watts      spectrum power area
+//! ies        spectrum file.ies area   # measured luminaire, facing the normal
+//! profile    one-sided | cosine n     # of the emission before it
+//! medium     medium
+//! normal-map texture strength      # tangent-space normals
+//! bump       texture scale         # heights per unit of uv
+//! opacity    texture threshold t | stochastic     # cut-out, e.g. leaves
+//! ```
+//!
+//! where a spectrum is `blackbody kelvin`, one of the CIE illuminants
+//! `d65`, `a`, `f2`, `f7` and `f11`, or `tabulated start step n` followed
+//! by n values, e.g. measured every `step` nm from `start`.
+//!
+//! A dielectric and a metal can be coated with a thin film for iridescence,
+//! e.g. `dielectric 1.0 film 400 1.33` for a soap bubble. The film is the
+//! thickness in nm and the index of refraction.
+//!
+//! The parameters of the principled material are `metallic`, `roughness`,
+//! `specular`, `sheen`, `clearcoat`, `clearcoat-gloss`, `transmission`,
+//! `subsurface` and `ior`.
+//! A sphere is the center and the radius,
+//! optionally followed by its motion: `move t0 t1 offset` at a constant
+//! velocity, or `keyframes n` and then n times and offsets.
+//!
+//! In place of the material and the albedo, a medium such as
+//! `homogeneous absorption scattering color g` fills the shape with an
+//! invisible boundary. The option `medium` fills the inside of a surface,
+//! e.g. `dielectric 1.5  1 1 1  medium homogeneous 0.5 0  0.2 0.6 0.9  0`.
+//! A translucent object such as skin or wax is `subsurface ior mfp color`
+//! with the mean free path of each channel and the apparent color.
+//! A voxel grid fills the bounding box of the shape, e.g.
+//! `voxels smoke.nrrd 4  0.9 0.9 0.9  0.3` (density scale, albedo and g),
+//! or `voxels smoke.raw 64 64 64 4 ...` for the sizes of headerless floats.
+//!
+//! A signed distance field is a primitive, optionally combined with more
+//! primitives from left to right by an operator, and then the options of
+//! the sphere tracing (`lipschitz l`, `epsilon e` and `max-steps n`).
+//!
+//! ```text
+//! sphere     center radius
+//! cuboid     center half-extents
+//! torus      center major minor
+//! capsule    a b radius
+//! plane      normal h              # below dot(normal, p) = h, no bound
+//! mandelbulb center power iterations
+//!
+//! union, intersection, subtraction,
+//! smooth-union k, smooth-intersection k, smooth-subtraction k, blend t
+//! ```
+//!
+//! A heightfield is a PGM or PFM file, the corner and the extent, e.g.
+//! `heightfield hills.pgm  -5 0 -5  10 1 10  diffuse 0.4 0.6 0.3`.
+//! Files are relative to the working directory, also on the workers of a
+//! distributed render.
+//!
+//! A grid is a triangle mesh of nu x nv quads spanned by two edges from the
+//! corner. It can be split `subdivide n` times and then displaced along the
+//! normals by a texture, e.g.
+//! `grid -1 0 1  2 0 0  0 0 -2  8 8  subdivide 2  displace image h.ppm 0.1`.
+//!
+//! ```text
+//! constant  color
+//! checker   even odd scale         # 3D cubes of the edge 1 / scale
+//! image     file.ppm               # linearized with gamma 2
+//! raw-image file.ppm               # as it is, e.g. for normal maps
+//! ```
+
+use crate::vector::Vector3;
+use crate::color::RGB;
+use crate::camera::{Camera, CameraBuilder};
+use crate::sampler::Pattern;
+use crate::filter::Filter;
+use crate::world::World;
+use crate::object::{Object, Shape, Cutout};
+use crate::sphere::Sphere;
+use crate::motion::Motion;
+use crate::sdf::{self, Sdf};
+use crate::heightfield::{HeightMap, Heightfield};
+use crate::mesh::Mesh;
+use crate::texture::{Texture, ImageTexture};
+use crate::bump::NormalMap;
+use crate::material::{Material, Principled};
+use crate::metal::Metal;
+use crate::thinfilm::ThinFilm;
+use crate::spectrum::Dispersion;
+use crate::medium::Medium;
+use crate::voxel::VoxelGrid;
+use crate::emission::{Emission, Spectrum, Profile};
+use crate::ies::IesProfile;
+use crate::background::UniBg;
+use crate::error::{Error, Result};
+
+struct Tokens<'a> {
+    line:   usize,
+    tokens: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Tokens<'a> {
+    fn error(&self, msg: &str) -> Error {
+        Error::parse(format!("line {}: {}", self.line, msg))
+    }
+    fn word(&mut self) -> Result<&'a str> {
+        let line = self.line;
+        self.tokens.next().ok_or_else(|| Error::parse(format!("line {}: too few values", line)))
+    }
+    fn float(&mut self) -> Result<f32> {
+        let word = self.word()?;
+        word.parse().map_err(|_| self.error(&format!("not a number: {}", word)))
+    }
+    fn int(&mut self) -> Result<usize> {
+        let word = self.word()?;
+        word.parse().map_err(|_| self.error(&format!("not an integer: {}", word)))
+    }
+    fn vector(&mut self) -> Result<Vector3> {
+        Ok(Vector3::new(self.float()?, self.float()?, self.float()?))
+    }
+    fn color(&mut self) -> Result<RGB> {
+        Ok(RGB::new(self.float()?, self.float()?, self.float()?))
+    }
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.clone().next()
+    }
+    fn end(&mut self) -> Result<()> {
+        match self.tokens.next() {
+            None       => {Ok(())}
+            Some(word) => {Err(self.error(&format!("unexpected value: {}", word)))}
+        }
+    }
+}
+
+fn sphere(t: &mut Tokens) -> Result<Sphere> {
+    let sphere = Sphere::new(t.vector()?, t.float()?);
+    let motion = match t.peek() {
+        Some("move") => {
+            t.word()?;
+            Motion::linear(t.float()?, t.float()?, t.vector()?)
+        }
+        Some("keyframes") => {
+            t.word()?;
+            let n = t.int()?;
+            let keys = (0..n).map(|_| Ok((t.float()?, t.vector()?)))
+                .collect::<Result<std::vec::Vec<_>>>()?;
+            Motion::keyframes(keys)?
+        }
+        _ => {return Ok(sphere);}
+    };
+    Ok(sphere.with_motion(motion))
+}
+
+fn principled(t: &mut Tokens) -> Result<Principled> {
+    let mut principled = Principled::new(t.color()?);
+    loop {
+        principled = match t.peek() {
+            Some("metallic")        => {t.word()?; principled.metallic(t.float()?)}
+            Some("roughness")       => {t.word()?; principled.roughness(t.float()?)}
+            Some("specular")        => {t.word()?; principled.specular(t.float()?)}
+            Some("sheen")           => {t.word()?; principled.sheen(t.float()?)}
+            Some("clearcoat")       => {t.word()?; principled.clearcoat(t.float()?)}
+            Some("clearcoat-gloss") => {t.word()?; principled.clearcoat_gloss(t.float()?)}
+            Some("transmission")    => {t.word()?; principled.transmission(t.float()?)}
+            Some("subsurface")      => {t.word()?; principled.subsurface(t.float()?)}
+            Some("ior")             => {t.word()?; principled.ior(t.float()?)}
+            _ => {return Ok(principled);}
+        };
+    }
+}
+
+fn film(t: &mut Tokens) -> Result<Option<ThinFilm>> {
+    if t.peek() != Some("film") {
+        return Ok(None);
+    }
+    t.word()?;
+    Ok(Some(ThinFilm::new(t.float()?, t.float()?)))
+}
+
+fn dispersion(t: &mut Tokens) -> Result<Dispersion> {
+    Ok(match t.word()? {
+        "bk7"       => {Dispersion::bk7()}
+        "diamond"   => {Dispersion::diamond()}
+        "cauchy"    => {Dispersion::make_cauchy(t.float()?, t.float()?)}
+        "sellmeier" => {
+            let b = [t.float()?, t.float()?, t.float()?];
+            Dispersion::make_sellmeier(b, [t.float()?, t.float()?, t.float()?])
+        }
+        other => {return Err(t.error(&format!("unknown dispersion: {}", other)));}
+    })
+}
+
+fn material(t: &mut Tokens, word: &str) -> Result<Material> {
+    Ok(match word {
+        "diffuse"    => {Material::make_diffuse()}
+        "metal"      => {Material::make_metalic(t.float()?)}
+        "dielectric" => {
+            let n = t.float()?;
+            match film(t)? {
+                Some(film) => {Material::make_film_dielectric(n, film)}
+                None       => {Material::make_dielectric(n)}
+            }
+        }
+        "conductor"  => {Material::make_conductor(t.color()?, t.color()?, t.float()?, t.float()?)}
+        "gold" | "copper" | "aluminium" | "silver" => {
+            let metal = match word {
+                "gold"   => {Metal::Gold}
+                "copper" => {Metal::Copper}
+                "silver" => {Metal::Silver}
+                _        => {Metal::Aluminium}
+            };
+            let roughness = t.float()?;
+            match film(t)? {
+                Some(film) => {Material::make_film_metal(metal, roughness, film)}
+                None       => {Material::make_metal(metal, roughness)}
+            }
+        }
+        "rough-dielectric" => {Material::make_rough_dielectric(t.float()?, t.float()?, t.color()?)}
+        "principled" => {Material::make_principled(principled(t)?)}
+        "dispersive" => {Material::make_dispersive(dispersion(t)?)}
+        "diffuse-transmission" => {Material::make_diffuse_transmission(t.color()?, t.color()?)}
+        "thin-dielectric"      => {Material::make_thin_dielectric(t.float()?)}
+        "oren-nayar" => {Material::make_oren_nayar(t.float()?)}
+        "retro-reflective" => {Material::make_retro_reflective(t.float()?, t.float()?)}
+        "layered" => {
+            let (color, n, thickness, absorption) = (t.color()?, t.float()?, t.float()?, t.color()?);
+            let word = t.word()?;
+            Material::make_layered(material(t, word)?, color, n, thickness, absorption)
+        }
+        other        => {return Err(t.error(&format!("unknown material: {}", other)));}
+    })
+}
+
+/// a medium inside of `shape`.
+fn medium(t: &mut Tokens, word: &str, shape: &Shape) -> Result<Medium> {
+    Ok(match word {
+        "homogeneous" => {Medium::make_homogeneous(t.float()?, t.float()?, t.color()?, t.float()?)}
+        "voxels" => {
+            let bounds = shape.bounding_box(0.0, 0.0).ok_or_else(|| t.error("unbounded shape"))?;
+            let path   = t.word()?;
+            let grid   = if path.ends_with(".raw") {
+                VoxelGrid::read_raw(path, [t.int()?, t.int()?, t.int()?], bounds)?
+            } else {
+                VoxelGrid::read_nrrd(path, bounds)?
+            };
+            Medium::make_heterogeneous(grid, t.float()?, t.color()?, t.float()?)
+        }
+        other         => {return Err(t.error(&format!("unknown medium: {}", other)));}
+    })
+}
+
+fn spectrum(t: &mut Tokens) -> Result<Spectrum> {
+    Ok(match t.word()? {
+        "blackbody" => {Spectrum::make_blackbody(t.float()?)}
+        "d65"       => {Spectrum::d65()}
+        "a"         => {Spectrum::a()}
+        "f2"        => {Spectrum::f2()}
+        "f7"        => {Spectrum::f7()}
+        "f11"       => {Spectrum::f11()}
+        "tabulated" => {
+            let (start, step, n) = (t.float()?, t.float()?, t.int()?);
+            let values = (0..n).map(|_| t.float()).collect::<Result<std::vec::Vec<_>>>()?;
+            Spectrum::make_tabulated(start, step, values)
+        }
+        other => {return Err(t.error(&format!("unknown spectrum: {}", other)));}
+    })
+}
+
+/// the material, the albedo and the options of the object after its shape.
+fn object(t: &mut Tokens, shape: Shape) -> Result<Object> {
+    let word = t.word()?;
+    let mut object = match word {
+        "homogeneous" | "voxels" => {
+            let medium = medium(t, word, &shape)?;
+            Object::make_volume(shape, medium)
+        }
+        "subsurface" => {
+            let (n, mfp) = (t.float()?, t.color()?);
+            Object::make_subsurface(shape, t.color()?, mfp, n)
+        }
+        _ => {
+            let material = material(t, word)?;
+            let albedo   = t.color()?;
+            let black    = RGB::new(0.0, 0.0, 0.0);
+            match shape {
+                Shape::Sphere(sphere)  => {Object::make_sphere(sphere, material, albedo, black)}
+                Shape::Sdf(sdf)        => {Object::make_sdf(sdf, material, albedo, black)}
+                Shape::Heightfield(hf) => {Object::make_heightfield(hf, material, albedo, black)}
+                Shape::Mesh(mesh)      => {Object::make_mesh(mesh, material, albedo, black)}
+            }
+        }
+    };
+    while let Some(word) = t.tokens.next() {
+        object = match word {
+            "emission"   => {object.with_emission(Emission::from(t.color()?))}
+            "blackbody"  => {object.with_emission(Emission::blackbody(t.float()?, t.float()?))}
+            "spectral"   => {object.with_emission(Emission::spectral(spectrum(t)?, t.float()?))}
+            "lumens"     => {
+                let spectrum = spectrum(t)?;
+                object.with_emission(Emission::from_lumens(spectrum, t.float()?, t.float()?))
+            }
+            "watts"      => {
+                let spectrum = spectrum(t)?;
+                object.with_emission(Emission::from_watts(spectrum, t.float()?, t.float()?))
+            }
+            "ies"        => {
+                let (spectrum, ies) = (spectrum(t)?, IesProfile::read(t.word()?)?);
+                object.with_emission(Emission::from_ies(spectrum, ies, t.float()?))
+            }
+            "profile"    => {
+                let profile = match t.word()? {
+                    "one-sided" => {Profile::OneSided}
+                    "cosine"    => {Profile::CosinePower(t.float()?)}
+                    other       => {return Err(t.error(&format!("unknown profile: {}", other)));}
+                };
+                let emission = object.emission.clone().with_profile(profile);
+                object.with_emission(emission)
+            }
+            "normal-map" => {object.with_normal_map(NormalMap::make_normal(texture(t)?, t.float()?))}
+            "bump"       => {object.with_normal_map(NormalMap::make_bump(texture(t)?, t.float()?))}
+            "opacity"    => {
+                let opacity = texture(t)?;
+                let cutout  = match t.word()? {
+                    "threshold"  => {Cutout::Threshold(t.float()?)}
+                    "stochastic" => {Cutout::Stochastic}
+                    other        => {return Err(t.error(&format!("unknown cutout: {}", other)));}
+                };
+                object.with_opacity(opacity, cutout)
+            }
+            "medium"     => {
+                let word   = t.word()?;
+                let medium = medium(t, word, &object.shape)?;
+                object.with_medium(medium)
+            }
+            other => {return Err(t.error(&format!("unexpected value: {}", other)));}
+        };
+    }
+    Ok(object)
+}
+
+type Field = Box<dyn Fn(Vector3) -> f32 + Send + Sync>;
+
+/// a distance function and its bounding sphere.
+fn primitive(t: &mut Tokens) -> Result<(Field, Vector3, f32)> {
+    Ok(match t.word()? {
+        "sphere" => {
+            let (c, r) = (t.vector()?, t.float()?);
+            (Box::new(move |p| sdf::sphere(p - c, r)), c, r)
+        }
+        "cuboid" => {
+            let (c, b) = (t.vector()?, t.vector()?);
+            (Box::new(move |p| sdf::cuboid(p - c, b)), c, b.len())
+        }
+        "torus" => {
+            let (c, major, minor) = (t.vector()?, t.float()?, t.float()?);
+            (Box::new(move |p| sdf::torus(p - c, major, minor)), c, major + minor)
+        }
+        "capsule" => {
+            let (a, b, r) = (t.vector()?, t.vector()?, t.float()?);
+            (Box::new(move |p| sdf::capsule(p, a, b, r)), (a + b) * 0.5, (b - a).len() * 0.5 + r)
+        }
+        "plane" => {
+            let (n, h) = (t.vector()?.unit(), t.float()?);
+            (Box::new(move |p| sdf::plane(p, n, h)), Vector3::zero(), f32::INFINITY)
+        }
+        "mandelbulb" => {
+            let (c, power, iterations) = (t.vector()?, t.float()?, t.int()?);
+            // the iteration escapes beyond the radius 2
+            (Box::new(move |p| sdf::mandelbulb(p - c, power, iterations)), c, 2.0)
+        }
+        other => {return Err(t.error(&format!("unknown distance field: {}", other)));}
+    })
+}
+
+/// the smallest sphere around both spheres.
+fn enclose((c1, r1): (Vector3, f32), (c2, r2): (Vector3, f32)) -> (Vector3, f32) {
+    let d = (c2 - c1).len();
+    if d + r2 <= r1 {
+        return (c1, r1);
+    }
+    if d + r1 <= r2 {
+        return (c2, r2);
+    }
+    let r = (d + r1 + r2) * 0.5;
+    (c1 + (c2 - c1) * ((r - r1) / d), r)
+}
+
+fn distance_field(t: &mut Tokens) -> Result<Sdf> {
+    let (mut field, mut center, mut radius) = primitive(t)?;
+    while let Some(op) = t.peek() {
+        let k = match op {
+            "union" | "intersection" | "subtraction" => {t.word()?; 0.0}
+            "smooth-union" | "smooth-intersection" | "smooth-subtraction" | "blend" => {
+                t.word()?;
+                t.float()?
+            }
+            _ => {break;}
+        };
+        let (a, (b, c2, r2)) = (field, primitive(t)?);
+        field = match op {
+            "union"        => {Box::new(move |p| sdf::union(a(p), b(p)))}
+            "intersection" => {Box::new(move |p| sdf::intersection(a(p), b(p)))}
+            "subtraction"  => {Box::new(move |p| sdf::subtraction(a(p), b(p)))}
+            "smooth-union" => {Box::new(move |p| sdf::smooth_min(a(p), b(p), k))}
+            "smooth-intersection" => {Box::new(move |p| sdf::smooth_max(a(p), b(p), k))}
+            "smooth-subtraction"  => {Box::new(move |p| sdf::smooth_subtraction(a(p), b(p), k))}
+            _              => {Box::new(move |p| sdf::blend(a(p), b(p), k))}
+        };
+        // an intersection is inside of both, a subtraction inside of the
+        // first one, and the smooth union grows by less than k.
+        (center, radius) = match op {
+            "union" | "blend" => {enclose((center, radius), (c2, r2))}
+            "smooth-union"    => {enclose((center, radius + k), (c2, r2 + k))}
+            "intersection" | "smooth-intersection" if r2 < radius => {(c2, r2)}
+            _ => {(center, radius)}
+        };
+    }
+    if !radius.is_finite() {
+        return Err(t.error("the distance field is unbounded"));
+    }
+    let mut sdf = Sdf::new(field, center, radius);
+    loop {
+        sdf = match t.peek() {
+            Some("lipschitz") => {t.word()?; sdf.lipschitz(t.float()?)}
+            Some("epsilon")   => {t.word()?; sdf.epsilon(t.float()?)}
+            Some("max-steps") => {t.word()?; sdf.max_steps(t.int()?)}
+            _ => {return Ok(sdf);}
+        };
+    }
+}
+
+fn texture(t: &mut Tokens) -> Result<Texture> {
+    Ok(match t.word()? {
+        "constant"  => {Texture::make_constant(t.color()?)}
+        "checker"   => {Texture::make_checker(t.color()?, t.color()?, t.float()?)}
+        "image"     => {Texture::make_image(ImageTexture::read_ppm(t.word()?)?)}
+        "raw-image" => {Texture::make_image(ImageTexture::read_ppm_raw(t.word()?)?)}
+        other       => {return Err(t.error(&format!("unknown texture: {}", other)));}
+    })
+}
+
+fn grid(t: &mut Tokens) -> Result<Mesh> {
+    let mut mesh = Mesh::grid(t.vector()?, t.vector()?, t.vector()?, t.int()?, t.int()?);
+    if t.peek() == Some("subdivide") {
+        t.word()?;
+        for _ in 0..t.int()? {
+            mesh = mesh.subdivide();
+        }
+    }
+    if t.peek() == Some("displace") {
+        t.word()?;
+        let height = texture(t)?;
+        mesh = mesh.displace(&height, t.float()?);
+    }
+    Ok(mesh)
+}
+
+fn heightfield(t: &mut Tokens) -> Result<Heightfield> {
+    let path = t.word()?;
+    let map = if path.ends_with(".pfm") {
+        HeightMap::read_pfm(path)?
+    } else {
+        HeightMap::read_pgm(path)?
+    };
+    Heightfield::new(&map, t.vector()?, t.vector()?)
+}
+
+pub fn read<P>(path: P) -> Result<(Camera, World<UniBg>)>
+where
+    P: std::convert::AsRef<std::path::Path>
+{
+    parse(&std::fs::read(path)?)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<(Camera, World<UniBg>)> {
+    let text = std::str::from_utf8(bytes).map_err(|_| Error::parse("scene file is not UTF-8"))?;
+    let mut builder    = CameraBuilder::new().view_up(Vector3::new(0.0, 1.0, 0.0));
+    let mut has_size   = false;
+    let mut has_camera = false;
+    let mut background = RGB::new(0.5, 0.5, 0.5);
+    let mut objects    = std::vec::Vec::new();
+    let mut fog        = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut t = Tokens{line: i + 1, tokens: line.split_whitespace()};
+        let keyword = match t.tokens.next() {
+            Some(k) => {k}
+            None    => {continue;}
+        };
+        match keyword {
+            "size" => {
+                let (w, h) = (t.int()?, t.int()?);
+                if w == 0 || h == 0 {
+                    return Err(t.error("empty image"));
+                }
+                builder  = builder.width(w).height(h);
+                has_size = true;
+            }
+            "samples" => {
+                builder = builder.samples_per_pixel(t.int()?.max(1));
+            }
+            "adaptive" => {
+                builder = builder.adaptive(t.int()?.max(1), t.int()?, t.float()?);
+            }
+            "seed" => {
+                let word = t.word()?;
+                let seed = word.parse().map_err(|_| t.error(&format!("not a seed: {}", word)))?;
+                builder = builder.seed(seed);
+            }
+            "sampler" => {
+                let pattern = match t.word()? {
+                    "independent" => {Pattern::Independent}
+                    "stratified"  => {Pattern::Stratified(t.int()?)}
+                    "halton"      => {Pattern::Halton}
+                    "sobol"       => {Pattern::Sobol}
+                    "blue-noise"  => {Pattern::BlueNoise}
+                    other         => {return Err(t.error(&format!("unknown sampler: {}", other)));}
+                };
+                builder = builder.sampler(pattern);
+            }
+            "filter" => {
+                let filter = match t.word()? {
+                    "box"      => {Filter::make_box(t.float()?)}
+                    "tent"     => {Filter::make_tent(t.float()?)}
+                    "gaussian" => {Filter::make_gaussian(t.float()?, t.float()?)}
+                    "mitchell" => {Filter::make_mitchell(t.float()?)}
+                    "lanczos"  => {Filter::make_lanczos(t.float()?)}
+                    "blackman-harris" => {Filter::make_blackman_harris(t.float()?)}
+                    other      => {return Err(t.error(&format!("unknown filter: {}", other)));}
+                };
+                builder = builder.filter(filter);
+            }
+            "camera" => {
+                builder = builder.position(t.vector()?)
+                    .direction(t.vector()?)
+                    .vertical_angle_of_view(t.float()?)
+                    .diameter_of_apature(t.float()?)
+                    .focus_distance(t.float()?);
+                has_camera = true;
+            }
+            "shutter" => {
+                builder = builder.shutter_open(t.float()?).shutter_close(t.float()?);
+            }
+            "spectral" => {
+                builder = builder.spectral(true);
+            }
+            "background" => {
+                background = t.color()?;
+            }
+            "fog" => {
+                let medium = Medium::make_homogeneous(t.float()?, t.float()?, t.color()?, t.float()?);
+                fog = Some((medium, t.float()?));
+            }
+            "sphere" => {
+                let sphere = sphere(&mut t)?;
+                objects.push(object(&mut t, Shape::Sphere(sphere))?);
+            }
+            "sdf" => {
+                let sdf = distance_field(&mut t)?;
+                objects.push(object(&mut t, Shape::Sdf(sdf))?);
+            }
+            "grid" => {
+                let mesh = grid(&mut t)?;
+                objects.push(object(&mut t, Shape::Mesh(mesh))?);
+            }
+            "heightfield" => {
+                let hf = heightfield(&mut t)?;
+                objects.push(object(&mut t, Shape::Heightfield(hf))?);
+            }
+            other => {
+                return Err(t.error(&format!("unknown statement: {}", other)));
+            }
+        }
+        t.end()?;
+    }
+    if !has_size || !has_camera {
+        return Err(Error::parse("scene file needs the size and the camera"));
+    }
+    let world = World::new(objects, UniBg::new(background));
+    let world = match fog {
+        Some((medium, horizon)) => {world.with_fog(medium, horizon)}
+        None                    => {world}
+    };
+    Ok((builder.build(), world))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scene::*;
+
+    const SCENE: &str = "
+# two spheres
+size 8 4
+samples 2
+seed 3
+camera 0 0 3  0 0 -1  40 0 3
+sphere 0 0 0  0.5  metal 0.1  0.8 0.6 0.2
+sphere 0 -100.5 0  100  diffuse  0.5 0.5 0.5  emission 0.1 0 0
+";
+
+    #[test]
+    fn parse_scene() {
+        let (camera, world) = parse(SCENE.as_bytes()).unwrap();
+        assert_eq!(camera.seed(), 3);
+        assert_eq!(camera.tiles().len(), 1);
+        assert_eq!(world.objects.len(), 2);
+        assert_eq!(camera.render_film(&world).min_count(), 2);
+    }
+
+    #[test]
+    fn adaptive_sampling() {
+        let scene = format!("{}adaptive 4 8 0\n", SCENE);
+        let (camera, world) = parse(scene.as_bytes()).unwrap();
+        assert_eq!(camera.render_film(&world).min_count(), 4);
+        // converged after the minimum
+        let scene = format!("{}adaptive 2 8 1000\n", SCENE);
+        let (camera, world) = parse(scene.as_bytes()).unwrap();
+        let film = camera.render_film(&world);
+        assert!((0..4).all(|y| (0..8).all(|x| film.count(x, y) == 2)));
+        assert!(parse(format!("{}adaptive 2 8\n", SCENE).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn samplers() {
+        let mut images = std::vec::Vec::new();
+        for sampler in ["independent", "stratified 2", "halton", "sobol", "blue-noise"].iter() {
+            let scene = format!("sampler {}\n{}", sampler, SCENE);
+            let (camera, world) = parse(scene.as_bytes()).unwrap();
+            images.push(camera.render_film(&world).to_image());
+        }
+        // the default is sobol
+        let (camera, world) = parse(SCENE.as_bytes()).unwrap();
+        assert!(camera.render_film(&world).to_image() == images[3]);
+        assert!(images[0] != images[2]);
+        assert!(parse(format!("sampler sobol 2\n{}", SCENE).as_bytes()).is_err());
+        assert!(parse(format!("sampler stratified\n{}", SCENE).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn filters() {
+        let filters = ["box 0.5", "tent 1", "gaussian 1.5 0.5", "mitchell 2", "lanczos 2",
+                       "blackman-harris 2"];
+        for filter in filters.iter() {
+            let scene = format!("filter {}\n{}", filter, SCENE);
+            let (camera, world) = parse(scene.as_bytes()).unwrap();
+            let radius: f32 = filter.split(' ').nth(1).unwrap().parse().unwrap();
+            assert_eq!(camera.film().filter().radius(), radius);
+            assert!(camera.render_film(&world).min_count() > 0);
+        }
+        assert!(parse(format!("filter gaussian 1.5\n{}", SCENE).as_bytes()).is_err());
+        assert!(parse(format!("filter sinc 1\n{}", SCENE).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn malformed_scenes() {
+        assert!(parse(b"size 8 4\n").is_err());
+        assert!(parse(SCENE.replace("metal 0.1", "wood").as_bytes()).is_err());
+        assert!(parse(SCENE.replace("samples 2", "samples 2 3").as_bytes()).is_err());
+        assert!(parse(SCENE.replace("size 8 4", "size 8").as_bytes()).is_err());
+        assert!(parse(SCENE.replace("emission", "glow").as_bytes()).is_err());
+    }
+
+    const MATERIALS: &[&str] = &[
+        "conductor 0.2 0.9 1.1  3.9 2.4 2.2  0.1 0.3",
+        "gold 0.2", "copper 0", "aluminium 0.5", "silver 1",
+        "rough-dielectric 1.5 0.3  0.1 0 0",
+        "principled 0.8 0.2 0.2  metallic 0.5 roughness 0.3 specular 0.4 sheen 0.1 clearcoat 1 \
+         clearcoat-gloss 0.8 transmission 0.2 subsurface 0.3 ior 1.4",
+        "oren-nayar 0.3", "retro-reflective 20 0.2",
+        "layered 1 1 1  1.5 0.1  0.5 0 0  gold 0.3",
+        "diffuse-transmission 0.3 0.3 0.3  0.6 0.6 0.6", "thin-dielectric 1.5",
+        "dielectric 1.0 film 400 1.33", "copper 0.2 film 300 2.0",
+        "dispersive cauchy 1.5 0.0042", "dispersive sellmeier 1.03 0.23 1.01  0.006 0.02 103.6",
+    ];
+
+    #[test]
+    fn materials() {
+        for material in MATERIALS.iter() {
+            let scene = format!("{}sphere 0 0 0  1  {}  0.8 0.8 0.8\n", SCENE, material);
+            let (camera, world) = parse(scene.as_bytes()).unwrap();
+            assert_eq!(camera.render_film(&world).min_count(), 2);
+            // without the last parameter
+            let cut = &material[..material.rfind(' ').unwrap()];
+            let scene = format!("{}sphere 0 0 0  1  {}  0.8 0.8 0.8\n", SCENE, cut);
+            assert!(parse(scene.as_bytes()).is_err(), "{}", cut);
+        }
+    }
+
+    #[test]
+    fn spectral_mode() {
+        let scene = format!("spectral\n{}sphere 0 0 0  1  dispersive bk7  1 1 1\n\
+                             sphere 0 0 0  1  dispersive diamond  1 1 1\n", SCENE);
+        let (camera, world) = parse(scene.as_bytes()).unwrap();
+        assert_eq!(camera.render_film(&world).min_count(), 2);
+        assert!(parse(scene.replace("diamond", "quartz").as_bytes()).is_err());
+        assert!(parse(scene.replace("spectral", "spectral 1").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn emissions() {
+        use crate::color::Color;
+        let lights = [
+            "emission 1 1 1", "blackbody 6500 1", "spectral d65 1", "spectral a 1",
+            "spectral f2 1", "spectral f7 1", "spectral f11 1", "spectral blackbody 3000 1",
+            "spectral tabulated 400 100 4  0 1 1 0  1", "lumens d65 800 0.1", "watts f11 10 0.1",
+        ];
+        let ray = crate::ray::Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let cr  = crate::collide::Collision::new(4.0, Vector3::new(0.0, 0.0, 1.0), (0.0, 0.0));
+        for light in lights.iter() {
+            let scene = format!("{}sphere 0 0 0  1  diffuse  1 1 1  {}\n", SCENE, light);
+            let (_, world) = parse(scene.as_bytes()).unwrap();
+            let radiance = world.objects[2].emission.radiance(&ray, &cr);
+            assert!(radiance.g() > 0.0, "{}", light);
+            let cut = &light[..light.rfind(' ').unwrap()];
+            let scene = format!("{}sphere 0 0 0  1  diffuse  1 1 1  {}\n", SCENE, cut);
+            assert!(parse(scene.as_bytes()).is_err(), "{}", cut);
+        }
+        let scene = format!("{}sphere 0 0 0  1  diffuse  1 1 1  spectral d50 1\n", SCENE);
+        assert!(parse(scene.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn emission_profiles() {
+        use crate::color::Color;
+        let path = std::env::temp_dir().join(format!("scene-{}.ies", std::process::id()));
+        std::fs::write(&path, "IESNA:LM-63-2002\nTILT=NONE\n1 1000 1.0 2 1 1 2 0.1 0.1 0.0\n\
+                               1.0 1.0 20\n0 90\n0\n1000 0\n").unwrap();
+        let scene = format!("{}{}", SCENE, "
+sphere 0 0 0  1  diffuse  1 1 1  emission 1 1 1  profile one-sided
+sphere 0 0 0  1  diffuse  1 1 1  emission 1 1 1  profile cosine 2
+sphere 0 0 0  1  diffuse  1 1 1  ies d65 PATH 0.01
+").replace("PATH", &path.display().to_string());
+        let (_, world) = parse(scene.as_bytes()).unwrap();
+        let cr = crate::collide::Collision::new(4.0, Vector3::new(0.0, 0.0, 1.0), (0.0, 0.0));
+        let facing = crate::ray::Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let behind = crate::ray::Ray::new(Vector3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        for object in world.objects[2..].iter() {
+            assert!(object.emission.radiance(&facing, &cr).g() > 0.0);
+            assert_eq!(object.emission.radiance(&behind, &cr).g(), 0.0);
+        }
+        assert!(parse(scene.replace("cosine 2", "spot 2").as_bytes()).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(parse(scene.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn distance_fields() {
+        use crate::collide::Collide;
+        use crate::ray::Ray;
+        let scene = format!("{}{}", SCENE, "
+sdf cuboid 0 0 0  1 1 1  subtraction sphere 0 0 1  0.5  diffuse  1 1 1
+sdf torus 3 0 0  1 0.2  smooth-union 0.3  capsule 3 -1 0  3 1 0  0.1  epsilon 1e-3 max-steps 100  diffuse  1 1 1
+");
+        let (_, world) = parse(scene.as_bytes()).unwrap();
+        assert_eq!(world.objects.len(), 4);
+        // the hole of the box
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let cr  = world.objects[2].collide_within(&ray, 1e-4, f32::INFINITY).unwrap();
+        assert!((cr.t - 4.5).abs() < 1e-2);
+        let ray = Ray::new(Vector3::new(0.9, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let cr  = world.objects[2].collide_within(&ray, 1e-4, f32::INFINITY).unwrap();
+        assert!((cr.t - 4.0).abs() < 1e-2);
+        // the capsule through the torus, from above
+        let ray = Ray::new(Vector3::new(3.0, 5.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        assert!(world.objects[3].collide_within(&ray, 1e-4, f32::INFINITY).is_some());
+
+        let unbounded = format!("{}{}", SCENE, "sdf plane 0 1 0  0  diffuse  1 1 1\n");
+        assert!(parse(unbounded.as_bytes()).is_err());
+        let bounded = format!("{}{}", SCENE, "sdf sphere 0 0 0  1  intersection plane 0 1 0  0  diffuse  1 1 1\n");
+        assert!(parse(bounded.as_bytes()).is_ok());
+        let unknown = format!("{}{}", SCENE, "sdf cone 0 0 0  1  diffuse  1 1 1\n");
+        assert!(parse(unknown.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn moving_spheres() {
+        use crate::collide::Collide;
+        use crate::ray::Ray;
+        let scene = format!("{}{}", SCENE, "
+shutter 0 1
+sphere 0 0 0  0.5  move 0 1  2 0 0  diffuse  1 1 1
+sphere 0 0 0  0.5  keyframes 2  1 0 2 0  0 0 0 0  diffuse  1 1 1
+");
+        let (_, world) = parse(scene.as_bytes()).unwrap();
+        let dir = Vector3::new(0.0, 0.0, -1.0);
+        for (i, &center) in [Vector3::new(2.0, 0.0, 0.0), Vector3::new(0.0, 2.0, 0.0)].iter().enumerate() {
+            let object = &world.objects[2 + i];
+            let ray = Ray::with_time(Vector3::new(0.0, 0.0, 5.0), dir, 0.0);
+            assert!(object.collide_within(&ray, 1e-4, f32::INFINITY).is_some());
+            let ray = Ray::with_time(center + Vector3::new(0.0, 0.0, 5.0), dir, 1.0);
+            assert!(object.collide_within(&ray, 1e-4, f32::INFINITY).is_some());
+        }
+        assert!(parse(scene.replace("keyframes 2", "keyframes 3").as_bytes()).is_err());
+        assert!(parse(scene.replace("keyframes 2", "keyframes 0").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn participating_media() {
+        use crate::color::Color;
+        use rand::SeedableRng;
+        let scene = format!("{}{}", SCENE, "
+fog 0 0  1 1 1  0  10
+sphere 0 0 0  1  homogeneous 0 0  1 1 1  0
+sphere 0 0 0  1  dielectric 1.5  1 1 1  medium homogeneous 1 0  1 1 1  0
+");
+        let (_, world) = parse(scene.as_bytes()).unwrap();
+        assert!(world.objects[2].medium.is_some());
+        assert!(world.objects[3].medium.is_some());
+
+        // everything absorbed by the fog before the horizon
+        let dark = scene.replace("fog 0 0", "fog 100 0");
+        let (_, world) = parse(dark.as_bytes()).unwrap();
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let ray = crate::ray::Ray::new(Vector3::new(0.0, 5.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(world.color(ray, &mut rng, 0).0.g(), 0.0);
+
+        assert!(parse(scene.replace("homogeneous 0 0", "smoke 0 0").as_bytes()).is_err());
+
+        let skin = format!("{}sphere 0 0 0  1  subsurface 1.4  0.5 0.25 0.1  0.9 0.6 0.5\n", SCENE);
+        let (camera, world) = parse(skin.as_bytes()).unwrap();
+        assert!(world.objects[2].medium.is_some());
+        assert_eq!(camera.render_film(&world).min_count(), 2);
+        assert!(parse(skin.replace("0.6 0.5", "0.6").as_bytes()).is_err());
+        assert!(parse(scene.replace("  0  10", "  0").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn voxel_grids() {
+        use crate::color::Color;
+        use rand::SeedableRng;
+        let path = std::env::temp_dir().join(format!("scene-{}.nrrd", std::process::id()));
+        let mut bytes = b"NRRD0004\ntype: uchar\ndimension: 3\nsizes: 2 2 2\nencoding: raw\n\n".to_vec();
+        bytes.extend_from_slice(&[255; 8]);
+        std::fs::write(&path, &bytes).unwrap();
+        let scene = format!("{}sphere 0 0 0  1  voxels {} 100  0.5 0.5 0.5  0\n", SCENE, path.display());
+        let (_, world) = parse(scene.as_bytes()).unwrap();
+        // the grid fills the box around the sphere, the ray passes its corner
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let ray = crate::ray::Ray::new(Vector3::new(0.9, 0.9, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(world.color(ray.clone(), &mut rng, 0).0.g(), 0.5);
+        let scene = scene.replace("sphere 0 0 0  1 ", "sdf cuboid 0 0 0  1 1 1 ");
+        let (_, world) = parse(scene.as_bytes()).unwrap();
+        assert!(world.color(ray, &mut rng, 0).0.g() < 0.5);
+        std::fs::remove_file(&path).unwrap();
+        assert!(parse(scene.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn meshes_and_maps() {
+        use crate::collide::Collide;
+        use crate::ray::Ray;
+        use rand::SeedableRng;
+        let path = std::env::temp_dir().join(format!("scene-{}.ppm", std::process::id()));
+        std::fs::write(&path, b"P6 1 1 255\n\x80\x80\xff").unwrap();
+        let scene = format!("{}{}", SCENE, "
+grid -1 0 1  2 0 0  0 0 -2  1 1  subdivide 2  displace constant 1 1 1  0.5  diffuse  1 1 1
+grid -1 0 1  2 0 0  0 0 -2  1 1  diffuse  1 1 1  bump checker 0 0 0  1 1 1  4  0.1
+grid -1 0 1  2 0 0  0 0 -2  1 1  diffuse  1 1 1  normal-map raw-image PATH 1
+").replace("PATH", &path.display().to_string());
+        let (camera, world) = parse(scene.as_bytes()).unwrap();
+        let ray = Ray::new(Vector3::new(0.3, 5.0, 0.2), Vector3::new(0.0, -1.0, 0.0));
+        let cr  = world.objects[2].collide_within(&ray, 1e-4, f32::INFINITY).unwrap();
+        assert!((cr.t - 4.5).abs() < 1e-4);
+        assert!(world.objects[3].normal_map.is_some());
+        assert!(world.objects[4].normal_map.is_some());
+        assert_eq!(camera.render_film(&world).min_count(), 2);
+
+        assert!(parse(scene.replace("raw-image", "tiles").as_bytes()).is_err());
+
+        let cutouts = format!("{}{}", SCENE, "
+grid -1 0 1  2 0 0  0 0 -2  1 1  diffuse  1 1 1  opacity constant 0.2 0.2 0.2  threshold 0.5
+grid -1 0 1  2 0 0  0 0 -2  1 1  diffuse  1 1 1  opacity constant 0.8 0.8 0.8  stochastic
+");
+        let (_, world) = parse(cutouts.as_bytes()).unwrap();
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        assert!(world.objects[2].collide_opaque(&ray, 1e-4, f32::INFINITY, &mut rng).is_none());
+        assert!(world.objects[3].opacity.is_some());
+        assert!(parse(cutouts.replace("stochastic", "dither").as_bytes()).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(parse(scene.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn heightfields() {
+        use crate::collide::Collide;
+        use crate::ray::Ray;
+        let path = std::env::temp_dir().join(format!("scene-{}.pgm", std::process::id()));
+        std::fs::write(&path, b"P2 2 2 255 255 255 255 255").unwrap();
+        let scene = format!("{}heightfield {}  -1 0 -1  2 1 2  diffuse  1 1 1\n",
+                            SCENE, path.display());
+        let (_, world) = parse(scene.as_bytes()).unwrap();
+        let ray = Ray::new(Vector3::new(0.0, 5.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let cr  = world.objects[2].collide_within(&ray, 1e-4, f32::INFINITY).unwrap();
+        assert!((cr.t - 4.0).abs() < 1e-3);
+
+        let flat = scene.replace("2 1 2", "0 1 2");
+        assert!(parse(flat.as_bytes()).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(parse(scene.as_bytes()).is_err());
+    }
+}
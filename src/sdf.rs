@@ -0,0 +1,275 @@
+//! signed distance field shapes.
+//!
+//! The surface is the zero level set of a distance function and is found by
+//! sphere tracing. The function does not need to be an exact distance; any
+//! function with a Lipschitz bound `L` can be traced by dividing the step by
+//! `L`. The built-in primitives and operators below can be combined in a
+//! closure to make a shape.
+
+use crate::vector::Vector3;
+use crate::ray::Ray;
+use crate::aabb::AABB;
+use crate::collide::{Collision, Collide};
+use crate::util::clamp;
+use crate::sphere::sphere_uv;
+
+pub struct Sdf {
+    func:      Box<dyn Fn(Vector3) -> f32 + Send + Sync>,
+    center:    Vector3, // bounding sphere
+    radius:    f32,
+    rlipschitz: f32,
+    epsilon:   f32,
+    max_steps: usize,
+}
+
+impl Sdf {
+    /// the shape must be contained in the sphere at `center` with `radius`.
+    pub fn new<F>(func: F, center: Vector3, radius: f32) -> Sdf
+    where
+        F: Fn(Vector3) -> f32 + Send + Sync + 'static
+    {
+        Sdf{func: Box::new(func), center, radius,
+            rlipschitz: 1.0, epsilon: 1.0e-4, max_steps: 256}
+    }
+
+    /// sets the Lipschitz bound of the function. it must be >= 1 for
+    /// inexact distance estimators, e.g. fractals or smoothly blended shapes.
+    pub fn lipschitz(mut self, l: f32) -> Self {
+        self.rlipschitz = 1.0 / l;
+        self
+    }
+    pub fn epsilon(mut self, eps: f32) -> Self {
+        self.epsilon = eps;
+        self
+    }
+    pub fn max_steps(mut self, n: usize) -> Self {
+        self.max_steps = n;
+        self
+    }
+
+    pub fn distance(&self, p: Vector3) -> f32 {
+        (self.func)(p)
+    }
+
+    /// gradient by central differences
+    pub fn normal_at(&self, p: Vector3) -> Vector3 {
+        let h  = self.epsilon;
+        let dx = Vector3::new(h, 0.0, 0.0);
+        let dy = Vector3::new(0.0, h, 0.0);
+        let dz = Vector3::new(0.0, 0.0, h);
+        Vector3::unit(Vector3::new(
+            self.distance(p + dx) - self.distance(p - dx),
+            self.distance(p + dy) - self.distance(p - dy),
+            self.distance(p + dz) - self.distance(p - dz)))
+    }
+
+    /// returns the range of t where the ray is inside of the bounding sphere.
+    fn bounds(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let oc = ray.origin - self.center;
+        let b  = Vector3::dot(oc, ray.direction);
+        let c  = oc.len_sq() - self.radius * self.radius;
+        let d  = b * b - c;
+        if d < 0.0 {return None;}
+        let sqrt_d = d.sqrt();
+        Some((-b - sqrt_d, -b + sqrt_d))
+    }
+}
+
+impl Collide for Sdf {
+    fn collide_within(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Collision> {
+        let (t_enter, t_exit) = self.bounds(ray)?;
+        let t_end = t_max.min(t_exit);
+        let mut t = t_min.max(t_enter);
+
+        // if the ray starts on the surface (e.g. after scattering), first
+        // move away from it to know which side the ray is marching in.
+        let mut d = self.distance(ray.at(t));
+        let mut steps = 0;
+        while d.abs() < self.epsilon && steps < self.max_steps {
+            t += self.epsilon;
+            d  = self.distance(ray.at(t));
+            steps += 1;
+        }
+        let side = if d < 0.0 {-1.0} else {1.0};
+
+        while t <= t_end && steps < self.max_steps {
+            let dist = side * d * self.rlipschitz;
+            if dist < self.epsilon {
+                let normal = self.normal_at(ray.at(t));
+                let d      = ray.at(t) - self.center;
+                let uv     = sphere_uv(d.unit());
+                return Some(Collision::new(t, normal, uv)
+                            .with_tangent(Vector3::new(d[2], 0.0, -d[0])));
+            }
+            t += dist;
+            d  = self.distance(ray.at(t));
+            steps += 1;
+        }
+        None
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        Some(AABB::new(self.center - r, self.center + r))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// primitives. all of them are centered at the origin; translate `p` to move.
+// ---------------------------------------------------------------------------
+
+pub fn sphere(p: Vector3, radius: f32) -> f32 {
+    p.len() - radius
+}
+
+/// box with half extents `b`
+pub fn cuboid(p: Vector3, b: Vector3) -> f32 {
+    let q = Vector3::new(p[0].abs() - b[0], p[1].abs() - b[1], p[2].abs() - b[2]);
+    let outside = Vector3::new(q[0].max(0.0), q[1].max(0.0), q[2].max(0.0)).len();
+    let inside  = q[0].max(q[1]).max(q[2]).min(0.0);
+    outside + inside
+}
+
+/// torus lying on the xz plane
+pub fn torus(p: Vector3, major: f32, minor: f32) -> f32 {
+    let qx = (p[0] * p[0] + p[2] * p[2]).sqrt() - major;
+    (qx * qx + p[1] * p[1]).sqrt() - minor
+}
+
+/// line segment from `a` to `b` with thickness `radius`
+pub fn capsule(p: Vector3, a: Vector3, b: Vector3, radius: f32) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h  = clamp(Vector3::dot(pa, ba) / ba.len_sq(), 0.0, 1.0);
+    (pa - ba * h).len() - radius
+}
+
+/// half space below the plane `dot(n, p) = h`. `n` must be normalized.
+pub fn plane(p: Vector3, n: Vector3, h: f32) -> f32 {
+    Vector3::dot(p, n) - h
+}
+
+/// distance estimator of the Mandelbulb fractal. It is not an exact
+/// distance, so a Lipschitz bound larger than 1 is recommended.
+pub fn mandelbulb(p: Vector3, power: f32, iterations: usize) -> f32 {
+    let mut z  = p;
+    let mut dr = 1.0;
+    let mut r  = 0.0;
+    for _ in 0..iterations {
+        r = z.len();
+        if r > 2.0 {break;}
+        if r == 0.0 {
+            // only at the origin, which is in the set. the angles are 0/0.
+            return 0.0;
+        }
+
+        let theta = (z[2] / r).acos() * power;
+        let phi   = z[1].atan2(z[0])  * power;
+        let zr    = r.powf(power);
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+
+        z = zr * Vector3::new(theta.sin() * phi.cos(),
+                              theta.sin() * phi.sin(),
+                              theta.cos()) + p;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+// ---------------------------------------------------------------------------
+// operators
+// ---------------------------------------------------------------------------
+
+pub fn union(d1: f32, d2: f32) -> f32 {
+    d1.min(d2)
+}
+pub fn intersection(d1: f32, d2: f32) -> f32 {
+    d1.max(d2)
+}
+/// removes the second shape from the first one
+pub fn subtraction(d1: f32, d2: f32) -> f32 {
+    d1.max(-d2)
+}
+
+/// polynomial smooth minimum. `k` is the width of the blended region.
+pub fn smooth_min(d1: f32, d2: f32, k: f32) -> f32 {
+    let h = clamp(0.5 + 0.5 * (d2 - d1) / k, 0.0, 1.0);
+    d2 * (1.0 - h) + d1 * h - k * h * (1.0 - h)
+}
+pub fn smooth_max(d1: f32, d2: f32, k: f32) -> f32 {
+    -smooth_min(-d1, -d2, k)
+}
+pub fn smooth_subtraction(d1: f32, d2: f32, k: f32) -> f32 {
+    smooth_max(d1, -d2, k)
+}
+
+/// linear interpolation between two shapes. `t = 0` gives the first one.
+pub fn blend(d1: f32, d2: f32, t: f32) -> f32 {
+    d1 * (1.0 - t) + d2 * t
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sdf::*;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn sphere_tracing_matches_analytic_sphere() {
+        let c   = Vector3::new(0.0, 0.0, -2.0);
+        let sdf = Sdf::new(move |p| sphere(p - c, 0.5), c, 0.6);
+        let sph = Sphere::new(c, 0.5);
+        let ray = Ray::new(Vector3::zero(), Vector3::new(0.1, 0.2, -1.0));
+
+        let a = sdf.collide_within(&ray, 0.0001, f32::INFINITY).unwrap();
+        let b = sph.collide_within(&ray, 0.0001, f32::INFINITY).unwrap();
+        assert!((a.t - b.t).abs() < 1e-3);
+        assert!((a.normal - b.normal).len() < 1e-2);
+    }
+
+    #[test]
+    fn marching_from_inside() {
+        let sdf = Sdf::new(|p| sphere(p, 1.0), Vector3::zero(), 1.1);
+        let ray = Ray::new(Vector3::zero(), Vector3::new(1.0, 0.0, 0.0));
+        let cr  = sdf.collide_within(&ray, 0.0001, f32::INFINITY).unwrap();
+        assert!((cr.t - 1.0).abs() < 1e-3);
+        assert!(cr.normal[0] > 0.99);
+    }
+
+    #[test]
+    fn leaving_the_surface() {
+        let sdf = Sdf::new(|p| sphere(p, 1.0), Vector3::zero(), 1.1);
+        let ray = Ray::new(Vector3::new(1.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 0.0));
+        assert!(sdf.collide_within(&ray, 0.0001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn missing_the_bound() {
+        let sdf = Sdf::new(|p| sphere(p, 1.0), Vector3::zero(), 1.1);
+        let ray = Ray::new(Vector3::new(0.0, 2.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(sdf.collide_within(&ray, 0.0001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn cuboid_distance() {
+        let b = Vector3::new(1.0, 2.0, 3.0);
+        assert!((cuboid(Vector3::new(2.0, 0.0, 0.0), b) - 1.0).abs() < 1e-6);
+        assert!((cuboid(Vector3::zero(), b) + 1.0).abs() < 1e-6);
+        assert!((cuboid(Vector3::new(2.0, 3.0, 0.0), b) - 2.0f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn smooth_min_is_below_min() {
+        for &(a, b) in &[(0.0, 0.0), (0.1, 0.3), (-0.5, 0.2), (1.0, 3.0)] {
+            let s = smooth_min(a, b, 0.5);
+            assert!(s <= union(a, b) + 1e-6);
+        }
+        // far from the blended region, it is the exact minimum.
+        assert_eq!(smooth_min(0.0, 3.0, 0.5), 0.0);
+    }
+
+    #[test]
+    fn mandelbulb_at_the_origin() {
+        assert_eq!(mandelbulb(Vector3::zero(), 8.0, 10), 0.0);
+        assert!(mandelbulb(Vector3::new(0.0, 0.0, 1e-3), 8.0, 10).is_finite());
+        assert!(mandelbulb(Vector3::new(0.0, 0.0, 3.0), 8.0, 10) > 0.0);
+    }
+}
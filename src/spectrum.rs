@@ -0,0 +1,278 @@
+//! spectral rendering.
+//!
+//! In the spectral mode, each camera ray samples a hero wavelength and two
+//! more wavelengths rotated over the visible range (Wilkie et al. 2014).
+//! The three channels of `RGB` then carry the radiance at those wavelengths
+//! instead of red, green and blue. RGB colors of the scene are upsampled to
+//! spectra by the method of Smits (1999) and the result is converted back
+//! to RGB through CIE XYZ.
+
+use crate::color::{Color, RGB};
+
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 720.0;
+
+/// wavelengths (nm) carried by a ray. The first one is the hero wavelength.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Wavelengths {
+    pub lambda: [f32; 3],
+    /// true if the secondary wavelengths are already dropped, e.g. after
+    /// a refraction by a dispersive material.
+    pub terminated: bool,
+}
+
+impl Wavelengths {
+    /// `u` is uniform in [0, 1).
+    pub fn sample(u: f32) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; 3];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let v = u + i as f32 / 3.0;
+            *l = LAMBDA_MIN + (v - v.floor()) * range;
+        }
+        Wavelengths{lambda, terminated: false}
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// drops the secondary wavelengths. Returns the weight that keeps the
+    /// estimate unbiased and the wavelengths after that.
+    pub fn terminate_secondary(&self) -> (RGB, Wavelengths) {
+        if self.terminated {
+            (RGB::new(1.0, 1.0, 1.0), *self)
+        } else {
+            (RGB::new(3.0, 0.0, 0.0), Wavelengths{lambda: self.lambda, terminated: true})
+        }
+    }
+
+    /// values of the upsampled spectrum of `color` at the wavelengths
+    pub fn upsample(&self, color: RGB) -> RGB {
+        RGB::new(smits(color, self.lambda[0]),
+                 smits(color, self.lambda[1]),
+                 smits(color, self.lambda[2]))
+    }
+
+    /// converts the radiance at the wavelengths into linear sRGB. A
+    /// constant spectrum with value 1 becomes white (1, 1, 1) on average.
+    pub fn to_rgb(self, values: RGB) -> RGB {
+        let mut xyz = [0.0; 3];
+        for (i, v) in [values.r(), values.g(), values.b()].iter().enumerate() {
+            let cmf = cie_xyz(self.lambda[i]);
+            for c in 0..3 {
+                xyz[c] += v * cmf[c];
+            }
+        }
+        let rgb   = xyz_to_rgb(xyz);
+        let white = white_rgb();
+        let scale = (LAMBDA_MAX - LAMBDA_MIN) / 3.0;
+        RGB::new(rgb[0] * scale / white[0],
+                 rgb[1] * scale / white[1],
+                 rgb[2] * scale / white[2])
+    }
+}
+
+fn lobe(x: f32, mu: f32, sigma1: f32, sigma2: f32) -> f32 {
+    let t = (x - mu) / if x < mu {sigma1} else {sigma2};
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 2-degree color matching functions by the multi-lobe Gaussian
+/// fit of Wyman et al. (2013).
+pub fn cie_xyz(lambda: f32) -> [f32; 3] {
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+          - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+    [x, y, z]
+}
+
+/// CIE XYZ to linear sRGB (D65)
+pub fn xyz_to_rgb(xyz: [f32; 3]) -> [f32; 3] {
+    let [x, y, z] = xyz;
+    [ 3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+     -0.969_266 * x + 1.8760108 * y + 0.0415560 * z,
+      0.0556434 * x - 0.2040259 * y + 1.0572252 * z]
+}
+
+/// linear sRGB of the constant spectrum over the sampled range, without
+/// normalization.
+fn white_rgb() -> [f32; 3] {
+    static WHITE: std::sync::OnceLock<[f32; 3]> = std::sync::OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let mut xyz = [0.0; 3];
+        let mut lambda = LAMBDA_MIN + 0.5;
+        while lambda < LAMBDA_MAX {
+            let cmf = cie_xyz(lambda);
+            for c in 0..3 {
+                xyz[c] += cmf[c];
+            }
+            lambda += 1.0;
+        }
+        xyz_to_rgb(xyz)
+    })
+}
+
+/// linear sRGB of the spectrum `f`, normalized in the same way as
+/// `Wavelengths::to_rgb`.
+pub fn spectrum_to_rgb<F: Fn(f32) -> f32>(f: F) -> RGB {
+    let mut xyz = [0.0; 3];
+    let mut lambda = LAMBDA_MIN + 0.5;
+    while lambda < LAMBDA_MAX {
+        let cmf = cie_xyz(lambda);
+        let v   = f(lambda);
+        for c in 0..3 {
+            xyz[c] += v * cmf[c];
+        }
+        lambda += 1.0;
+    }
+    let rgb   = xyz_to_rgb(xyz);
+    let white = white_rgb();
+    RGB::new(rgb[0] / white[0], rgb[1] / white[1], rgb[2] / white[2])
+}
+
+/// integral of `f` times the luminous efficiency over the visible range.
+/// The constant spectrum 1 has the luminance 1 if `photometric` is false.
+/// Otherwise it returns the photometric quantity (683 lm/W at 555 nm).
+pub fn luminance<F: Fn(f32) -> f32>(f: F, photometric: bool) -> f32 {
+    let (mut sum, mut norm) = (0.0, 0.0);
+    let mut lambda = LAMBDA_MIN + 0.5;
+    while lambda < LAMBDA_MAX {
+        let y = cie_xyz(lambda)[1];
+        sum  += f(lambda) * y;
+        norm += y;
+        lambda += 1.0;
+    }
+    if photometric {683.0 * sum} else {sum / norm}
+}
+
+// spectra of Smits (1999) in 10 bins from 380 to 720 nm
+const SMITS_WHITE:   [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN:    [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW:  [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED:     [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN:   [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE:    [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+fn smits_bin(table: &[f32; 10], lambda: f32) -> f32 {
+    let x = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0;
+    let i = (x.max(0.0) as usize).min(9);
+    table[i]
+}
+
+/// value of the spectrum made from `color` at `lambda`.
+pub fn smits(color: RGB, lambda: f32) -> f32 {
+    let (r, g, b) = (color.r(), color.g(), color.b());
+    let s = |table: &[f32; 10]| smits_bin(table, lambda);
+    if r <= g && r <= b {
+        r * s(&SMITS_WHITE) + if g <= b {
+            (g - r) * s(&SMITS_CYAN) + (b - g) * s(&SMITS_BLUE)
+        } else {
+            (b - r) * s(&SMITS_CYAN) + (g - b) * s(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        g * s(&SMITS_WHITE) + if r <= b {
+            (r - g) * s(&SMITS_MAGENTA) + (b - r) * s(&SMITS_BLUE)
+        } else {
+            (b - g) * s(&SMITS_MAGENTA) + (r - b) * s(&SMITS_RED)
+        }
+    } else {
+        b * s(&SMITS_WHITE) + if r <= g {
+            (r - b) * s(&SMITS_YELLOW) + (g - r) * s(&SMITS_GREEN)
+        } else {
+            (g - b) * s(&SMITS_YELLOW) + (r - g) * s(&SMITS_RED)
+        }
+    }
+}
+
+/// wavelength-dependent index of refraction. Wavelengths are in nm.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dispersion {
+    /// n = a + b / lambda^2, lambda in micrometers
+    Cauchy{a: f32, b: f32},
+    /// n^2 = 1 + sum B lambda^2 / (lambda^2 - C), lambda in micrometers
+    Sellmeier{b: [f32; 3], c: [f32; 3]},
+}
+
+impl Dispersion {
+    pub fn make_cauchy(a: f32, b: f32) -> Self {
+        Dispersion::Cauchy{a, b}
+    }
+    pub fn make_sellmeier(b: [f32; 3], c: [f32; 3]) -> Self {
+        Dispersion::Sellmeier{b, c}
+    }
+    /// Schott N-BK7 crown glass
+    pub fn bk7() -> Self {
+        Dispersion::make_sellmeier([1.039_612, 0.231_792_34, 1.010_469_5],
+                                   [0.006_000_699, 0.020_017_914, 103.560_65])
+    }
+    pub fn diamond() -> Self {
+        Dispersion::make_sellmeier([4.3356, 0.3306, 0.0], [0.1060 * 0.1060, 0.1750 * 0.1750, 0.0])
+    }
+
+    pub fn ior(&self, lambda: f32) -> f32 {
+        let l2 = (lambda * 1e-3) * (lambda * 1e-3);
+        match self {
+            Dispersion::Cauchy{a, b} => {a + b / l2}
+            Dispersion::Sellmeier{b, c} => {
+                (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>()).sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spectrum::*;
+
+    #[test]
+    fn white_stays_white() {
+        let n = 1000;
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let w = Wavelengths::sample((i as f32 + 0.5) / n as f32);
+            sum += w.to_rgb(w.upsample(RGB::new(1.0, 1.0, 1.0)));
+        }
+        let mean = sum / n as f32;
+        for c in [mean.r(), mean.g(), mean.b()].iter() {
+            assert!((c - 1.0).abs() < 0.01, "{:?}", mean);
+        }
+    }
+
+    #[test]
+    fn upsampling_roundtrip() {
+        let color = RGB::new(0.8, 0.3, 0.2);
+        let n = 1000;
+        let mut sum = RGB::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let w = Wavelengths::sample((i as f32 + 0.5) / n as f32);
+            sum += w.to_rgb(w.upsample(color));
+        }
+        let mean = sum / n as f32;
+        assert!((mean.r() - 0.8).abs() < 0.1, "{:?}", mean);
+        assert!((mean.g() - 0.3).abs() < 0.1, "{:?}", mean);
+        assert!((mean.b() - 0.2).abs() < 0.1, "{:?}", mean);
+    }
+
+    #[test]
+    fn rotated_wavelengths() {
+        let w = Wavelengths::sample(0.9);
+        for l in w.lambda.iter() {
+            assert!(LAMBDA_MIN <= *l && *l < LAMBDA_MAX);
+        }
+        let (weight, t) = w.terminate_secondary();
+        assert_eq!(weight, RGB::new(3.0, 0.0, 0.0));
+        assert_eq!(t.terminate_secondary().0, RGB::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn glass_dispersion() {
+        let bk7 = Dispersion::bk7();
+        assert!((bk7.ior(587.6) - 1.5168).abs() < 1e-3);
+        assert!(bk7.ior(450.0) > bk7.ior(650.0));
+        assert!((Dispersion::diamond().ior(589.0) - 2.417).abs() < 0.01);
+        assert!((Dispersion::make_cauchy(1.5, 0.004).ior(500.0) - 1.516).abs() < 1e-4);
+    }
+}
@@ -1,22 +1,58 @@
 use crate::vector::Vector3;
 use crate::ray::Ray;
+use crate::aabb::AABB;
+use crate::motion::Motion;
 use crate::collide::{Collision, Collide};
+use crate::util::clamp;
 
 pub struct Sphere {
     center: Vector3,
     radius: f32,
     rradius: f32,
+    motion: Option<Motion>,
 }
 
 impl Sphere {
     pub fn new(center: Vector3, radius: f32) -> Sphere {
-        Sphere{center, radius, rradius: 1.0 / radius}
+        Sphere{center, radius, rradius: 1.0 / radius, motion: None}
+    }
+
+    /// makes the center move by `motion`.
+    pub fn with_motion(mut self, motion: Motion) -> Sphere {
+        self.motion = Some(motion);
+        self
+    }
+
+    pub fn center_at(&self, time: f32) -> Vector3 {
+        match &self.motion {
+            None         => {self.center}
+            Some(motion) => {self.center + motion.offset_at(time)}
+        }
+    }
+}
+
+/// texture coordinate of a point on the unit sphere. u goes around the y
+/// axis and v goes from the bottom to the top.
+pub fn sphere_uv(p: Vector3) -> (f32, f32) {
+    let phi   = (-p[2]).atan2(p[0]) + std::f32::consts::PI;
+    let theta = clamp(-p[1], -1.0, 1.0).acos();
+    (phi / (2.0 * std::f32::consts::PI), theta / std::f32::consts::PI)
+}
+
+impl Sphere {
+    fn collision(&self, ray: &Ray, center: Vector3, t: f32) -> Collision {
+        let d      = ray.at(t) - center;
+        let normal = d * self.rradius;
+        let uv     = sphere_uv(d / self.radius.abs());
+        // d/du of the point, see `sphere_uv`
+        Collision::new(t, normal, uv).with_tangent(Vector3::new(d[2], 0.0, -d[0]))
     }
 }
 
 impl Collide for Sphere {
     fn collide_within(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Collision> {
-        let oc = ray.origin - self.center;
+        let center = self.center_at(ray.time);
+        let oc = ray.origin - center;
         let b  = Vector3::dot(oc, ray.direction);
         let c  = oc.len_sq() - self.radius * self.radius;
         if 0.0 < c && 0.0 < b {return None;}
@@ -28,15 +64,63 @@ impl Collide for Sphere {
 
         let t = (-b - sqrt_d);
         if t_min <= t && t <= t_max {
-            let normal = (ray.at(t) - self.center) * self.rradius;
-            return Some(Collision{t, normal})
+            return Some(self.collision(ray, center, t))
         }
 
         let t = (-b + sqrt_d);
         if t_min <= t && t <= t_max {
-            let normal = (ray.at(t) - self.center) * self.rradius;
-            return Some(Collision{t, normal})
+            return Some(self.collision(ray, center, t))
         }
         None
     }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        let r = self.radius.abs();
+        let r = Vector3::new(r, r, r);
+        let offsets = match &self.motion {
+            None         => {vec![Vector3::zero()]}
+            Some(motion) => {motion.offsets_within(time0, time1)}
+        };
+        offsets.into_iter()
+            .map(|o| AABB::new(self.center + o - r, self.center + o + r))
+            .reduce(|l, r| l.union(&r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sphere::*;
+
+    #[test]
+    fn moving_sphere() {
+        let sphere = Sphere::new(Vector3::new(0.0, 0.0, -2.0), 0.5)
+            .with_motion(Motion::linear(0.0, 1.0, Vector3::new(2.0, 0.0, 0.0)));
+
+        let dir = Vector3::new(0.0, 0.0, -1.0);
+        let ray = Ray::with_time(Vector3::zero(), dir, 0.0);
+        assert!(sphere.collide_within(&ray, 0.0001, f32::INFINITY).is_some());
+        let ray = Ray::with_time(Vector3::zero(), dir, 1.0);
+        assert!(sphere.collide_within(&ray, 0.0001, f32::INFINITY).is_none());
+        let ray = Ray::with_time(Vector3::new(2.0, 0.0, 0.0), dir, 1.0);
+        assert!(sphere.collide_within(&ray, 0.0001, f32::INFINITY).is_some());
+    }
+
+    #[test]
+    fn bounding_box_covers_motion() {
+        let sphere = Sphere::new(Vector3::zero(), 1.0)
+            .with_motion(Motion::keyframes(vec![
+                (0.0, Vector3::zero()),
+                (0.5, Vector3::new(0.0, 3.0, 0.0)),
+                (1.0, Vector3::new(2.0, 0.0, 0.0)),
+            ]).unwrap());
+        let bb = sphere.bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(bb.min, Vector3::new(-1.0, -1.0, -1.0));
+        assert_eq!(bb.max, Vector3::new( 3.0,  4.0,  1.0));
+
+        for i in 0..=10 {
+            let c = sphere.center_at(i as f32 * 0.1);
+            assert!(bb.contains(c + Vector3::new(1.0, 1.0, 1.0)));
+            assert!(bb.contains(c - Vector3::new(1.0, 1.0, 1.0)));
+        }
+    }
 }
@@ -0,0 +1,152 @@
+//! textures that give a color or a scalar at a point on a surface.
+
+use crate::vector::Vector3;
+use crate::color::{Color, RGB};
+use crate::image::header_tokens;
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageTexture {
+    width:  usize,
+    height: usize,
+    texels: std::vec::Vec<RGB>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, texels: std::vec::Vec<RGB>) -> ImageTexture {
+        assert_eq!(width * height, texels.len());
+        ImageTexture{width, height, texels}
+    }
+
+    /// reads binary PPM (P6). The colors are linearized with gamma 2, the
+    /// inverse of what `Camera::render` applies.
+    pub fn read_ppm<P>(path: P) -> Result<ImageTexture>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        ImageTexture::parse_ppm(&std::fs::read(path)?)
+    }
+
+    /// reads binary PPM (P6) without linearization, e.g. for normal maps.
+    pub fn read_ppm_raw<P>(path: P) -> Result<ImageTexture>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        ImageTexture::decode_ppm(&std::fs::read(path)?, false)
+    }
+
+    pub fn parse_ppm(bytes: &[u8]) -> Result<ImageTexture> {
+        ImageTexture::decode_ppm(bytes, true)
+    }
+
+    fn decode_ppm(bytes: &[u8], linearize: bool) -> Result<ImageTexture> {
+        let (tokens, offset) = header_tokens(bytes, 4)?;
+        if tokens[0] != "P6" {
+            return Err(Error::parse(format!("not a binary PPM: {}", tokens[0])));
+        }
+        let width:  usize = tokens[1].parse()?;
+        let height: usize = tokens[2].parse()?;
+        let maxval: u32   = tokens[3].parse()?;
+        if maxval == 0 || maxval > 255 {
+            return Err(Error::parse(format!("unsupported PPM maxval: {}", maxval)));
+        }
+        let body = bytes.get(offset..).unwrap_or(&[]);
+        if body.len() < width * height * 3 {
+            return Err(Error::parse("PPM is too short"));
+        }
+        let scale  = 1.0 / maxval as f32;
+        let texels = body[..width * height * 3].chunks_exact(3).map(|c| {
+            let (r, g, b) = (c[0] as f32 * scale, c[1] as f32 * scale, c[2] as f32 * scale);
+            if linearize {RGB::new(r * r, g * g, b * b)} else {RGB::new(r, g, b)}
+        }).collect();
+        Ok(ImageTexture::new(width, height, texels))
+    }
+
+    /// bilinear lookup. The texture repeats and v = 0 is the bottom row.
+    pub fn at(&self, (u, v): (f32, f32)) -> RGB {
+        let x = (u - u.floor()) * self.width  as f32 - 0.5;
+        let y = (1.0 - (v - v.floor())) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let wrap = |i: f32, n: usize| (i as isize).rem_euclid(n as isize) as usize;
+        let (x0, x1) = (wrap(x0, self.width),  wrap(x0 + 1.0, self.width));
+        let (y0, y1) = (wrap(y0, self.height), wrap(y0 + 1.0, self.height));
+        let texel = |x: usize, y: usize| self.texels[y * self.width + x];
+        (texel(x0, y0) * (1.0 - fx) + texel(x1, y0) * fx) * (1.0 - fy) +
+        (texel(x0, y1) * (1.0 - fx) + texel(x1, y1) * fx) * fy
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Texture {
+    Constant(RGB),
+    /// 3D checker pattern with cubes of edge `1 / scale`
+    Checker{even: RGB, odd: RGB, scale: f32},
+    Image(ImageTexture),
+}
+
+impl Texture {
+    pub fn make_constant(color: RGB) -> Self {
+        Texture::Constant(color)
+    }
+    pub fn make_checker(even: RGB, odd: RGB, scale: f32) -> Self {
+        Texture::Checker{even, odd, scale}
+    }
+    pub fn make_image(image: ImageTexture) -> Self {
+        Texture::Image(image)
+    }
+
+    /// color at the point `p` with the texture coordinate `uv`.
+    pub fn value(&self, uv: (f32, f32), p: Vector3) -> RGB {
+        match self {
+            Texture::Constant(c) => {*c}
+            Texture::Checker{even, odd, scale} => {
+                let s = (p[0] * scale).floor() + (p[1] * scale).floor() + (p[2] * scale).floor();
+                if (s as i64).rem_euclid(2) == 0 {*even} else {*odd}
+            }
+            Texture::Image(img) => {img.at(uv)}
+        }
+    }
+
+    /// scalar value, taken from the red channel.
+    pub fn scalar(&self, uv: (f32, f32), p: Vector3) -> f32 {
+        self.value(uv, p).r()
+    }
+}
+
+impl std::convert::From<RGB> for Texture {
+    fn from(color: RGB) -> Texture {
+        Texture::Constant(color)
+    }
+}
+
+impl std::convert::From<f32> for Texture {
+    fn from(v: f32) -> Texture {
+        Texture::Constant(RGB::new(v, v, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::texture::*;
+
+    #[test]
+    fn checker() {
+        let tex = Texture::make_checker(RGB::new(1.0, 1.0, 1.0), RGB::new(0.0, 0.0, 0.0), 1.0);
+        assert_eq!(tex.scalar((0.0, 0.0), Vector3::new( 0.5, 0.5, 0.5)), 1.0);
+        assert_eq!(tex.scalar((0.0, 0.0), Vector3::new( 1.5, 0.5, 0.5)), 0.0);
+        assert_eq!(tex.scalar((0.0, 0.0), Vector3::new(-0.5, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn ppm_texture() {
+        let mut bytes = b"P6\n2 1\n255\n".to_vec();
+        bytes.extend_from_slice(&[255, 0, 0, 0, 255, 0]);
+        let img = ImageTexture::parse_ppm(&bytes).unwrap();
+        // at the centers of the texels
+        assert_eq!(img.at((0.25, 0.5)), RGB::new(1.0, 0.0, 0.0));
+        assert_eq!(img.at((0.75, 0.5)), RGB::new(0.0, 1.0, 0.0));
+        let mid = img.at((0.5, 0.5));
+        assert!((mid.r() - 0.5).abs() < 1e-5 && (mid.g() - 0.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,155 @@
+//! thin-film interference, e.g. soap bubbles and anti-reflection coatings.
+//!
+//! The reflectance of a film between two media is computed by summing the
+//! multiple reflections inside the film (Airy summation) for s and p
+//! polarizations separately.
+
+use crate::color::{Color, RGB};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Complex {
+        Complex{re, im}
+    }
+    fn real(re: f32) -> Complex {
+        Complex{re, im: 0.0}
+    }
+    fn norm_sq(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+    fn sqrt(self) -> Complex {
+        let r = self.norm_sq().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.0).sqrt();
+        // principal branch, with the imaginary part having the sign of the input
+        Complex::new(re, if self.im < 0.0 {-im} else {im})
+    }
+    /// exp(i z)
+    fn expi(self) -> Complex {
+        let m = (-self.im).exp();
+        Complex::new(m * self.re.cos(), m * self.re.sin())
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+}
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+}
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(self.re * o.re - self.im * o.im, self.re * o.im + self.im * o.re)
+    }
+}
+impl std::ops::Div for Complex {
+    type Output = Complex;
+    fn div(self, o: Complex) -> Complex {
+        let d = o.norm_sq();
+        Complex::new((self.re * o.re + self.im * o.im) / d, (self.im * o.re - self.re * o.im) / d)
+    }
+}
+
+/// Fresnel amplitude coefficients (s, p) from the medium `n1` to `n2`.
+fn fresnel_amplitudes(n1: Complex, cos1: Complex, n2: Complex, cos2: Complex)
+    -> (Complex, Complex) {
+    let rs = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let rp = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    (rs, rp)
+}
+
+/// film with the thickness in nanometers and the index of refraction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ThinFilm {
+    pub thickness: f32,
+    pub ior:       f32,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f32, ior: f32) -> ThinFilm {
+        ThinFilm{thickness: thickness.max(0.0), ior}
+    }
+
+    /// reflectance at the wavelength `lambda` (nm) for the light coming from
+    /// the medium `n1` onto the film on a substrate with `eta + i k`.
+    pub fn reflectance(&self, cos_i: f32, n1: f32, eta: f32, k: f32, lambda: f32) -> f32 {
+        let cos1 = cos_i.abs().min(1.0);
+        let sin2 = n1 * n1 * (1.0 - cos1 * cos1);
+
+        let cos_in = |n: Complex| (Complex::real(1.0) - Complex::real(sin2) / (n * n)).sqrt();
+        let n1c = Complex::real(n1);
+        let n2  = Complex::real(self.ior);
+        let n3  = Complex::new(eta, k);
+        let c1  = Complex::real(cos1);
+        let c2  = cos_in(n2);
+        let c3  = cos_in(n3);
+
+        let (r12s, r12p) = fresnel_amplitudes(n1c, c1, n2, c2);
+        let (r23s, r23p) = fresnel_amplitudes(n2, c2, n3, c3);
+
+        // phase difference of one round trip in the film
+        let delta = Complex::real(4.0 * std::f32::consts::PI * self.thickness / lambda) * n2 * c2;
+        let phase = delta.expi();
+        let airy  = |r12: Complex, r23: Complex| {
+            let r = (r12 + r23 * phase) / (Complex::real(1.0) + r12 * r23 * phase);
+            r.norm_sq()
+        };
+        (0.5 * (airy(r12s, r23s) + airy(r12p, r23p))).min(1.0)
+    }
+
+    /// reflectance at three wavelengths, with the substrate index at each.
+    pub fn reflectance_at(&self, cos_i: f32, n1: f32, eta: RGB, k: RGB, lambda: [f32; 3]) -> RGB {
+        RGB::new(self.reflectance(cos_i, n1, eta.r(), k.r(), lambda[0]),
+                 self.reflectance(cos_i, n1, eta.g(), k.g(), lambda[1]),
+                 self.reflectance(cos_i, n1, eta.b(), k.b(), lambda[2]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::thinfilm::*;
+    use crate::microfacet::{fresnel_dielectric, fresnel_conductor};
+    use crate::metal::RGB_WAVELENGTHS;
+
+    #[test]
+    fn vanishing_film() {
+        let film = ThinFilm::new(0.0, 1.33);
+        for &c in [1.0f32, 0.7, 0.3, 0.05].iter() {
+            let r = film.reflectance(c, 1.0, 1.5, 0.0, 550.0);
+            assert!((r - fresnel_dielectric(c, 1.5)).abs() < 1e-4, "{} {}", c, r);
+            let r = film.reflectance(c, 1.0, 0.2, 3.9, 550.0);
+            assert!((r - fresnel_conductor(c, 0.2, 3.9)).abs() < 1e-4, "{} {}", c, r);
+        }
+    }
+
+    #[test]
+    fn quarter_wave_coating() {
+        // a quarter-wave film with n = sqrt(1.5) cancels the reflection
+        let n    = 1.5f32.sqrt();
+        let film = ThinFilm::new(550.0 / (4.0 * n), n);
+        assert!(film.reflectance(1.0, 1.0, 1.5, 0.0, 550.0) < 1e-5);
+        assert!(film.reflectance(1.0, 1.0, 1.5, 0.0, 400.0) > 1e-3);
+    }
+
+    #[test]
+    fn soap_bubble_is_iridescent() {
+        let film = ThinFilm::new(300.0, 1.33);
+        let (eta, k) = (RGB::new(1.0, 1.0, 1.0), RGB::new(0.0, 0.0, 0.0));
+        let r = film.reflectance_at(1.0, 1.0, eta, k, RGB_WAVELENGTHS);
+        let (min, max) = (r.r().min(r.g()).min(r.b()), r.r().max(r.g()).max(r.b()));
+        assert!(max - min > 0.02, "{:?}", r);
+        assert!(max <= 1.0);
+    }
+}
@@ -31,6 +31,47 @@ pub fn pick_in_circle<R: Rng>(rng: &mut R) -> (f32, f32) {
 pub fn reflect(v: Vector3, n: Vector3) -> Vector3 {
     v - 2.0 * Vector3::dot(v, n) * n
 }
+/// returns two unit vectors perpendicular to `n` and to each other.
+/// `n` must be normalized. (Duff et al. 2017)
+pub fn orthonormal_basis(n: Vector3) -> (Vector3, Vector3) {
+    let sign = 1.0f32.copysign(n[2]);
+    let a = -1.0 / (sign + n[2]);
+    let b = n[0] * n[1] * a;
+    (Vector3::new(1.0 + sign * n[0] * n[0] * a, sign * b, -sign * n[0]),
+     Vector3::new(b, sign + n[1] * n[1] * a, -n[1]))
+}
+
+/// orthonormal frame with the normal as the z axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frame {
+    pub s: Vector3,
+    pub t: Vector3,
+    pub n: Vector3,
+}
+
+impl Frame {
+    /// `n` must be normalized.
+    pub fn new(n: Vector3) -> Frame {
+        let (s, t) = orthonormal_basis(n);
+        Frame{s, t, n}
+    }
+    /// with `s` along the tangent projected onto the plane of `n`.
+    pub fn with_tangent(n: Vector3, tangent: Vector3) -> Frame {
+        let s = tangent - n * Vector3::dot(tangent, n);
+        if s.len_sq() < 1e-12 {
+            return Frame::new(n);
+        }
+        let s = s.unit();
+        Frame{s, t: Vector3::cross(n, s), n}
+    }
+    pub fn to_local(self, v: Vector3) -> Vector3 {
+        Vector3::new(Vector3::dot(v, self.s), Vector3::dot(v, self.t), Vector3::dot(v, self.n))
+    }
+    pub fn to_world(self, v: Vector3) -> Vector3 {
+        v[0] * self.s + v[1] * self.t + v[2] * self.n
+    }
+}
+
 /// Snell's law
 pub fn refract(v: Vector3, n: Vector3, ni_over_nt: f32) -> std::option::Option<Vector3> {
     let uv = v.unit();
@@ -54,6 +95,35 @@ mod tests {
         assert!((w.len() - 1.0).abs() < 3.0 / 4096.0);
     }
 
+    #[test]
+    fn orthonormal() {
+        for n in &[Vector3::new(1.0, 2.0, 3.0), Vector3::new(0.0, 0.0, -1.0),
+                   Vector3::new(-1.0, 0.5, -0.1)] {
+            let n = n.unit();
+            let (s, t) = orthonormal_basis(n);
+            assert!((s.len() - 1.0).abs() < 1e-5);
+            assert!((t.len() - 1.0).abs() < 1e-5);
+            assert!(Vector3::dot(s, n).abs() < 1e-5);
+            assert!(Vector3::dot(t, n).abs() < 1e-5);
+            assert!(Vector3::dot(s, t).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn frame_roundtrip() {
+        let f = Frame::new(Vector3::new(1.0, -2.0, 0.5).unit());
+        let v = Vector3::new(0.3, 0.2, -0.7);
+        assert!((f.to_world(f.to_local(v)) - v).len() < 1e-5);
+        assert!((f.to_local(f.n) - Vector3::new(0.0, 0.0, 1.0)).len() < 1e-5);
+
+        // the tangent stays the x axis on both sides
+        for &n in [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0)].iter() {
+            let f = Frame::with_tangent(n, Vector3::new(1.0, 0.0, 0.3));
+            assert!((f.s - Vector3::new(1.0, 0.0, 0.0)).len() < 1e-5);
+            assert!((f.to_world(f.to_local(v)) - v).len() < 1e-5);
+        }
+    }
+
     #[test]
     fn index_3() {
         let v = Vector3::new(1.0, 2.0, 3.0);
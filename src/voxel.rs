@@ -0,0 +1,303 @@
+//! dense voxel grid of densities.
+//!
+//! The grid is divided into bricks of `BRICK`^3 voxels and the maximum
+//! density in each brick is kept as the majorant of the brick. Tracking
+//! methods walk over the bricks along a ray and skip the empty ones.
+
+use crate::vector::Vector3;
+use crate::ray::Ray;
+use crate::aabb::AABB;
+use crate::error::{Error, Result};
+
+const BRICK: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelGrid {
+    size:     [usize; 3],
+    values:   std::vec::Vec<f32>, // values[(z * ny + y) * nx + x]
+    bounds:   AABB,
+    bricks:   [usize; 3],
+    majorant: std::vec::Vec<f32>,
+}
+
+impl VoxelGrid {
+    /// the grid fills `bounds`. voxel values are at the centers of the cells.
+    /// fails if a size is zero or the values do not fill the grid.
+    pub fn new(size: [usize; 3], values: std::vec::Vec<f32>, bounds: AABB) -> Result<VoxelGrid> {
+        if voxel_count(size)? != values.len() {
+            return Err(Error::parse(format!("{} values for the grid of {:?}", values.len(), size)));
+        }
+        let bricks = [(size[0] + BRICK - 1) / BRICK, (size[1] + BRICK - 1) / BRICK,
+                      (size[2] + BRICK - 1) / BRICK];
+        let mut grid = VoxelGrid{size, values, bounds, bricks, majorant: vec![]};
+
+        let mut majorant = std::vec::Vec::with_capacity(bricks[0] * bricks[1] * bricks[2]);
+        for bz in 0..bricks[2] {
+            for by in 0..bricks[1] {
+                for bx in 0..bricks[0] {
+                    // trilinear interpolation reads the neighboring voxels
+                    let range = |b: usize, n: usize| {
+                        (b * BRICK).saturating_sub(1)..((b + 1) * BRICK + 1).min(n)
+                    };
+                    let mut m = 0.0f32;
+                    for z in range(bz, size[2]) {
+                        for y in range(by, size[1]) {
+                            for x in range(bx, size[0]) {
+                                m = m.max(grid.voxel(x, y, z));
+                            }
+                        }
+                    }
+                    majorant.push(m);
+                }
+            }
+        }
+        grid.majorant = majorant;
+        Ok(grid)
+    }
+
+    /// reads NRRD with raw encoding. `type` can be `float`, `uchar` or
+    /// `ushort`. integers are normalized into [0, 1].
+    pub fn read_nrrd<P>(path: P, bounds: AABB) -> Result<VoxelGrid>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        VoxelGrid::parse_nrrd(&std::fs::read(path)?, bounds)
+    }
+
+    /// reads headerless little endian `f32`s.
+    pub fn read_raw<P>(path: P, size: [usize; 3], bounds: AABB) -> Result<VoxelGrid>
+    where
+        P: std::convert::AsRef<std::path::Path>
+    {
+        let bytes = std::fs::read(path)?;
+        let values = decode(&bytes, "float", true, voxel_count(size)?)?;
+        VoxelGrid::new(size, values, bounds)
+    }
+
+    pub fn parse_nrrd(bytes: &[u8], bounds: AABB) -> Result<VoxelGrid> {
+        if !bytes.starts_with(b"NRRD") {
+            return Err(Error::parse("not a NRRD"));
+        }
+        let mut ty     = std::string::String::new();
+        let mut sizes  = std::vec::Vec::new();
+        let mut little = true;
+        let mut offset = 0;
+        // the header ends with an empty line
+        for line in bytes.split(|&b| b == b'\n') {
+            offset += line.len() + 1;
+            let line = std::string::String::from_utf8_lossy(line);
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with('#') || line.starts_with("NRRD") {
+                continue;
+            }
+            let (key, value) = match line.find(':') {
+                Some(i) => (line[..i].trim(), line[i+1..].trim()),
+                None    => {return Err(Error::parse(format!("invalid line: {}", line)));}
+            };
+            match key {
+                "type" => {ty = value.to_string();}
+                "dimension" if value != "3" => {
+                    return Err(Error::parse(format!("dimension must be 3: {}", value)));
+                }
+                "sizes" => {
+                    sizes = value.split_whitespace().map(|v| v.parse::<usize>())
+                        .collect::<std::result::Result<std::vec::Vec<usize>, _>>()?;
+                }
+                "encoding" if value != "raw" => {
+                    return Err(Error::parse(format!("unsupported encoding: {}", value)));
+                }
+                "endian" => {little = value == "little";}
+                _ => {}
+            }
+        }
+        if sizes.len() != 3 {
+            return Err(Error::parse("sizes must have 3 values"));
+        }
+        let size   = [sizes[0], sizes[1], sizes[2]];
+        let n      = voxel_count(size)?;
+        let body   = bytes.get(offset..).unwrap_or(&[]);
+        let values = decode(body, &ty, little, n)?;
+        VoxelGrid::new(size, values, bounds)
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[(z * self.size[1] + y) * self.size[0] + x]
+    }
+
+    /// trilinear interpolation. it is zero outside of the bounds.
+    pub fn density(&self, p: Vector3) -> f32 {
+        if !self.bounds.contains(p) {
+            return 0.0;
+        }
+        let mut i0 = [0usize; 3];
+        let mut i1 = [0usize; 3];
+        let mut f  = [0.0f32; 3];
+        for k in 0..3 {
+            let n = self.size[k];
+            let extent = self.bounds.max[k] - self.bounds.min[k];
+            let x = (p[k] - self.bounds.min[k]) / extent * n as f32 - 0.5;
+            let x = x.max(0.0).min((n - 1) as f32);
+            i0[k] = x.floor() as usize;
+            i1[k] = (i0[k] + 1).min(n - 1);
+            f[k]  = x - i0[k] as f32;
+        }
+        let lerp = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
+        let c00 = lerp(self.voxel(i0[0], i0[1], i0[2]), self.voxel(i1[0], i0[1], i0[2]), f[0]);
+        let c10 = lerp(self.voxel(i0[0], i1[1], i0[2]), self.voxel(i1[0], i1[1], i0[2]), f[0]);
+        let c01 = lerp(self.voxel(i0[0], i0[1], i1[2]), self.voxel(i1[0], i0[1], i1[2]), f[0]);
+        let c11 = lerp(self.voxel(i0[0], i1[1], i1[2]), self.voxel(i1[0], i1[1], i1[2]), f[0]);
+        lerp(lerp(c00, c10, f[1]), lerp(c01, c11, f[1]), f[2])
+    }
+
+    /// splits the ray in `[t_min, t_max]` into the segments in each brick.
+    /// returns `(t_enter, t_exit, majorant)` in the order along the ray.
+    pub fn segments(&self, ray: &Ray, t_min: f32, t_max: f32) -> std::vec::Vec<(f32, f32, f32)> {
+        let mut segments = std::vec::Vec::new();
+        let (t0, t1) = match self.bounds.hit(ray, t_min, t_max) {
+            Some(range) => range,
+            None        => {return segments;}
+        };
+
+        let mut cell    = [0isize; 3];
+        let mut step    = [0isize; 3];
+        let mut t_next  = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        let start = ray.at(t0);
+        for k in 0..3 {
+            let extent = self.bounds.max[k] - self.bounds.min[k];
+            let width  = extent * BRICK as f32 / self.size[k] as f32;
+            let n      = self.bricks[k] as isize;
+            let c = ((start[k] - self.bounds.min[k]) / width).floor() as isize;
+            cell[k] = c.max(0).min(n - 1);
+
+            let org = ray.origin[k] - self.bounds.min[k];
+            let dir = ray.direction[k];
+            if dir > 0.0 {
+                step[k]    = 1;
+                t_next[k]  = ((cell[k] + 1) as f32 * width - org) / dir;
+                t_delta[k] = width / dir;
+            } else if dir < 0.0 {
+                step[k]    = -1;
+                t_next[k]  = (cell[k] as f32 * width - org) / dir;
+                t_delta[k] = -width / dir;
+            }
+        }
+
+        let mut t_enter = t0;
+        while t_enter < t1 {
+            let axis = if t_next[0] < t_next[1] {
+                if t_next[0] < t_next[2] {0} else {2}
+            } else if t_next[1] < t_next[2] {1} else {2};
+            let t_exit = t_next[axis].min(t1);
+
+            let idx = (cell[2] as usize * self.bricks[1] + cell[1] as usize)
+                    * self.bricks[0] + cell[0] as usize;
+            segments.push((t_enter, t_exit, self.majorant[idx]));
+
+            cell[axis]   += step[axis];
+            t_enter       = t_next[axis];
+            t_next[axis] += t_delta[axis];
+            if step[axis] == 0 || cell[axis] < 0 ||
+               self.bricks[axis] as isize <= cell[axis] {
+                break;
+            }
+        }
+        segments
+    }
+}
+
+fn voxel_count(size: [usize; 3]) -> Result<usize> {
+    if size.contains(&0) {
+        return Err(Error::parse(format!("empty voxel grid: {:?}", size)));
+    }
+    size[0].checked_mul(size[1]).and_then(|n| n.checked_mul(size[2]))
+        .ok_or_else(|| Error::parse(format!("voxel grid is too large: {:?}", size)))
+}
+
+fn decode(body: &[u8], ty: &str, little: bool, n: usize) -> Result<std::vec::Vec<f32>> {
+    let width = match ty {
+        "float" => 4,
+        "uchar" | "unsigned char" | "uint8" => 1,
+        "ushort" | "unsigned short" | "uint16" => 2,
+        _ => {return Err(Error::parse(format!("unsupported type: {}", ty)));}
+    };
+    if body.len() / width < n {
+        return Err(Error::parse("voxel data is too short"));
+    }
+    let values = body[..n * width].chunks_exact(width).map(|b| match width {
+        1 => b[0] as f32 / 255.0,
+        2 => {
+            let v = if little {u16::from_le_bytes([b[0], b[1]])}
+                    else      {u16::from_be_bytes([b[0], b[1]])};
+            v as f32 / 65535.0
+        }
+        _ => {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little {f32::from_le_bytes(b)} else {f32::from_be_bytes(b)}
+        }
+    }).collect();
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::*;
+
+    fn unit_box() -> AABB {
+        AABB::new(Vector3::zero(), Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn trilinear() {
+        let grid = VoxelGrid::new([2, 1, 1], vec![0.0, 1.0], unit_box()).unwrap();
+        assert_eq!(grid.density(Vector3::new(0.25, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.density(Vector3::new(0.50, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.density(Vector3::new(0.75, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.density(Vector3::new(1.50, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn majorants_bound_density() {
+        let n = 20;
+        let values = (0..n*n*n).map(|i| {
+            let (x, y, z) = (i % n, (i / n) % n, i / (n * n));
+            if x < 5 && y > 12 && z > 7 {(x + y + z) as f32} else {0.0}
+        }).collect();
+        let grid = VoxelGrid::new([n, n, n], values, unit_box()).unwrap();
+        let ray  = Ray::new(Vector3::new(-0.5, 0.3, 0.2), Vector3::new(1.0, 0.4, 0.5));
+        let segs = grid.segments(&ray, 0.0, f32::INFINITY);
+        assert!(!segs.is_empty());
+        assert!(segs.iter().any(|s| s.2 == 0.0));
+        for w in segs.windows(2) {
+            assert!((w[0].1 - w[1].0).abs() < 1e-5);
+        }
+        for &(t0, t1, m) in segs.iter() {
+            for i in 0..=20 {
+                let t = t0 + (t1 - t0) * i as f32 / 20.0;
+                assert!(grid.density(ray.at(t)) <= m);
+            }
+        }
+    }
+
+    #[test]
+    fn nrrd() {
+        let mut bytes = b"NRRD0004\n# comment\ntype: float\ndimension: 3\n\
+                          sizes: 2 1 1\nendian: little\nencoding: raw\n\n".to_vec();
+        bytes.extend_from_slice(&0.25f32.to_le_bytes());
+        bytes.extend_from_slice(&0.75f32.to_le_bytes());
+        let grid = VoxelGrid::parse_nrrd(&bytes, unit_box()).unwrap();
+        assert_eq!(grid.density(Vector3::new(0.25, 0.5, 0.5)), 0.25);
+        assert_eq!(grid.density(Vector3::new(0.75, 0.5, 0.5)), 0.75);
+
+        assert!(VoxelGrid::parse_nrrd(b"NRRD0004\ntype: float\ndimension: 2\n\n",
+                                      unit_box()).is_err());
+        assert!(VoxelGrid::parse_nrrd(b"NRRD0004\ntype: uchar\nsizes: 0 1 1\n\n",
+                                      unit_box()).is_err());
+        let huge = format!("NRRD0004\ntype: float\nsizes: {} 2 2\n\n", usize::MAX);
+        assert!(VoxelGrid::parse_nrrd(huge.as_bytes(), unit_box()).is_err());
+        assert!(VoxelGrid::new([2, 2, 1], vec![0.0; 3], unit_box()).is_err());
+    }
+}
@@ -1,7 +1,8 @@
+use crate::vector::Vector3;
 use crate::color::RGB;
 use crate::ray::Ray;
-use crate::collide::{Collision, Collide};
 use crate::material::{Scatter, Material};
+use crate::medium::{Medium, Event};
 use crate::object::Object;
 use crate::background::Background;
 use rand::Rng;
@@ -9,15 +10,49 @@ use rand::Rng;
 pub struct World<Bg> {
     pub objects: std::vec::Vec<Object>,
         bg:      Bg,
+        fog:     Option<(Medium, f32)>,
 }
 
 impl<Bg: Background> World<Bg> {
     pub fn new(objects: std::vec::Vec<Object>, bg: Bg) -> World<Bg> {
-        World{objects, bg}
+        World{objects, bg, fog: None}
+    }
+
+    /// fills the space outside of the objects with `medium`. The background
+    /// is regarded to be at the distance `horizon` from the ray origin.
+    pub fn with_fog(mut self, medium: Medium, horizon: f32) -> World<Bg> {
+        self.fog = Some((medium, horizon));
+        self
     }
 
     /// returns color & depth of the recursion.
     pub fn color<R>(&self, ray: Ray, rng: &mut R, depth: usize) -> (RGB, usize)
+    where
+        R: Rng
+    {
+        let mut media = std::vec::Vec::new();
+        self.color_in(ray, rng, depth, &mut media)
+    }
+
+    /// true if nothing blocks the segment from `from` to `to`. Cut-out parts
+    /// and invisible boundaries do not block it. The path tracer itself does
+    /// not cast shadow rays.
+    #[cfg(test)]
+    pub fn visible<R>(&self, from: Vector3, to: Vector3, time: f32, rng: &mut R) -> bool
+    where
+        R: Rng
+    {
+        let ray  = Ray::with_time(from, to - from, time);
+        let dist = (to - from).len() - 0.0001;
+        self.objects.iter()
+            .filter(|obj| !matches!(obj.material, Material::Interface(_)))
+            .all(|obj| obj.collide_opaque(&ray, 0.0001, dist, rng).is_none())
+    }
+
+    /// `media` is the stack of the media that contain the origin of the ray.
+    /// if it is empty, the ray is in the fog.
+    fn color_in<'a, R>(&'a self, ray: Ray, rng: &mut R, depth: usize,
+                       media: &mut std::vec::Vec<&'a Medium>) -> (RGB, usize)
     where
         R: Rng
     {
@@ -26,25 +61,72 @@ impl<Bg: Background> World<Bg> {
             return (RGB::new(0.0, 0.0, 0.0), DEPTH_LIMIT);
         }
 
-        let mut nearest = None;
-        let mut min_t   = std::f32::INFINITY;
-        for obj in self.objects.iter() {
-            if let Some(collide) =
-                obj.collide_within(&ray, 0.0001, std::f32::INFINITY) {
+        // scattering in a medium is not a bounce on a surface. a random walk
+        // in a dense medium takes hundreds of steps, so it is followed here
+        // with its own budget instead of counting toward the depth limit.
+        const MEDIUM_EVENT_LIMIT: usize = 100000;
+        let mut ray    = ray;
+        let mut weight = RGB::new(1.0, 1.0, 1.0);
+        let mut events = 0;
+        let nearest = loop {
+            let mut nearest = None;
+            let mut min_t   = std::f32::INFINITY;
+            for obj in self.objects.iter() {
+                // up to min_t, so that the opacity of the objects behind the
+                // nearest one so far is not looked up and draws no random numbers
+                if let Some(collide) = obj.collide_opaque(&ray, 0.0001, min_t, rng) {
+                    if collide.t < min_t {
+                        min_t = collide.t;
+                        nearest = Some((obj, collide))
+                    }
+                }
+            }
 
-                if collide.t < min_t {
-                    min_t = collide.t;
-                    nearest = Some((obj, collide))
+            let medium = match media.last() {
+                Some(m) => Some((*m, min_t)),
+                None    => self.fog.as_ref().map(|(m, horizon)| (m, min_t.min(*horizon))),
+            };
+            let (medium, t_max) = match medium {
+                Some(m) => {m}
+                None    => {break nearest;}
+            };
+            match medium.sample(&ray, t_max, rng) {
+                Event::Scatter{t, weight: w} => {
+                    events += 1;
+                    if events >= MEDIUM_EVENT_LIMIT {
+                        return (RGB::new(0.0, 0.0, 0.0), depth);
+                    }
+                    weight *= w;
+                    let dir = medium.phase().sample(ray.direction, rng);
+                    ray     = ray.spawn(ray.at(t), dir);
+                }
+                Event::Pass{weight: w} => {
+                    weight *= w;
+                    break nearest;
                 }
             }
-        }
+        };
 
         if let Some((nearest, collide)) = nearest {
-            let next_ray = nearest.scatter(&ray, collide, rng);
-            let (c, d)   = self.color(next_ray, rng, depth+1);
-            (nearest.emission + nearest.albedo * c, d)
+            let normal   = collide.geometric;
+            let (next_ray, attenuation) = nearest.scatter(&ray, collide, rng);
+
+            if let Some(m) = &nearest.medium {
+                let entering = Vector3::dot(ray.direction, normal) < 0.0;
+                let crossed  = (Vector3::dot(next_ray.direction, normal) < 0.0) == entering;
+                if crossed && entering {
+                    media.push(m);
+                } else if crossed {
+                    if let Some(i) = media.iter().rposition(|x| std::ptr::eq(*x, m)) {
+                        media.remove(i);
+                    }
+                }
+            }
+
+            let (c, d)   = self.color_in(next_ray, rng, depth+1, media);
+            (weight * (nearest.emission.radiance(&ray, &collide) + ray.tint(nearest.albedo) * attenuation * c), d)
         } else {
-            (self.bg.color_at(ray.direction), depth)
+            (weight * ray.tint(self.bg.color_at(ray.direction)), depth)
         }
     }
 }
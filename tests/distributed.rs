@@ -0,0 +1,80 @@
+//! renders a scene with worker processes on loopback.
+
+use std::io::{BufRead, Read};
+use std::process::{Child, Command, Stdio};
+
+const SCENE: &str = "
+size 40 20
+samples 4
+seed 11
+camera 0 0 3  0 0 -1  40 0 3
+background 0.6 0.7 0.9
+sphere 0 0 0  0.5  diffuse  0.8 0.3 0.3
+sphere 0.8 0 0  0.3  metal 0.2  0.8 0.6 0.2
+sphere 0 -100.5 0  100  diffuse  0.5 0.5 0.5
+";
+
+fn rustracer() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_rustracer"))
+}
+
+/// starts a worker process and returns it with its address.
+fn spawn_worker() -> (Child, std::string::String) {
+    let mut child = rustracer().args(["worker", "127.0.0.1:0"])
+        .stdout(Stdio::piped()).spawn().unwrap();
+    let mut line = std::string::String::new();
+    std::io::BufReader::new(child.stdout.as_mut().unwrap()).read_line(&mut line).unwrap();
+    let address = line.trim().strip_prefix("listening on ").unwrap().to_string();
+    (child, address)
+}
+
+/// takes the scene, the seed and the first tile, and then goes away.
+fn spawn_failing_worker() -> std::string::String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address  = listener.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 8];
+        stream.read_exact(&mut buf).unwrap();
+        let mut rest = vec![0u8; u64::from_le_bytes(buf) as usize + 8 + 33];
+        stream.read_exact(&mut rest).unwrap();
+    });
+    address
+}
+
+#[test]
+fn workers_on_loopback_render_the_same_image() {
+    let dir   = std::env::temp_dir().join(format!("rustracer-distributed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let scene = dir.join("scene.txt");
+    std::fs::write(&scene, SCENE).unwrap();
+    let (local, remote) = (dir.join("local.ppm"), dir.join("remote.ppm"));
+
+    let status = rustracer().arg("render").arg(&scene).arg(&local).status().unwrap();
+    assert!(status.success());
+
+    let (mut first, first_address)   = spawn_worker();
+    let (mut second, second_address) = spawn_worker();
+    let refused = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let status = rustracer().arg("coordinate").arg(&scene).arg(&remote).args(["--timeout", "30"])
+        .args([spawn_failing_worker(), first_address, refused.to_string(), second_address])
+        .status().unwrap();
+    first.kill().unwrap();
+    second.kill().unwrap();
+    first.wait().unwrap();
+    second.wait().unwrap();
+    assert!(status.success());
+
+    let local = std::fs::read(&local).unwrap();
+    assert!(local.starts_with(b"P6\n40 20\n255\n"));
+    assert_eq!(local, std::fs::read(&remote).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn usage_errors() {
+    let output = rustracer().args(["coordinate", "scene.txt", "out.ppm"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let output = rustracer().args(["render", "no-such-scene.txt", "out.ppm"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,88 @@
+//! renders a scene progressively from the command line.
+
+use std::process::Command;
+
+const SCENE: &str = "
+size 40 20
+samples 3
+seed 5
+camera 0 0 3  0 0 -1  40 0 3
+sphere 0 0 0  0.5  diffuse  0.8 0.3 0.3
+sphere 0 -100.5 0  100  diffuse  0.5 0.5 0.5
+";
+
+fn rustracer() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_rustracer"))
+}
+
+fn scratch(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustracer-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("scene.txt"), SCENE).unwrap();
+    dir
+}
+
+#[test]
+fn progressive_ends_with_the_rendered_image() {
+    let dir = scratch("progressive");
+    let status = rustracer().arg("render").arg(dir.join("scene.txt")).arg(dir.join("render.ppm"))
+        .status().unwrap();
+    assert!(status.success());
+
+    // pass by pass up to the samples of the scene
+    let status = rustracer().arg("progressive").arg(dir.join("scene.txt")).arg(dir.join("out.ppm"))
+        .args(["--snapshot-passes", "1", "--budget", "600"])
+        .status().unwrap();
+    assert!(status.success());
+    let expected = std::fs::read(dir.join("render.ppm")).unwrap();
+    assert_eq!(std::fs::read(dir.join("out.ppm")).unwrap(), expected);
+
+    // a single pass of all the samples
+    let output = rustracer().arg("progressive").arg(dir.join("scene.txt")).arg(dir.join("out.ppm"))
+        .args(["--pass-spp", "3", "--target-spp", "3", "--progress", "--heatmap"]).arg(dir.join("spp.ppm"))
+        .output().unwrap();
+    assert!(output.status.success());
+    assert!(std::string::String::from_utf8_lossy(&output.stderr).contains("100.0%"));
+    assert_eq!(std::fs::read(dir.join("out.ppm")).unwrap(), expected);
+    assert!(std::fs::read(dir.join("spp.ppm")).unwrap().starts_with(b"P6\n40 20\n255\n"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn progressive_options() {
+    let dir = scratch("options");
+    let output = rustracer().arg("progressive").arg(dir.join("scene.txt")).arg(dir.join("out.ppm"))
+        .args(["--budget"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let output = rustracer().arg("progressive").arg(dir.join("scene.txt")).arg(dir.join("out.ppm"))
+        .args(["--sharpen", "1"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn progressive_resumes_from_a_checkpoint() {
+    let dir = scratch("checkpoint");
+    let status = rustracer().arg("render").arg(dir.join("scene.txt")).arg(dir.join("render.ppm"))
+        .status().unwrap();
+    assert!(status.success());
+
+    // stops after the first pass and leaves a checkpoint behind
+    let status = rustracer().arg("progressive").arg(dir.join("scene.txt")).arg(dir.join("out.ppm"))
+        .args(["--target-spp", "1", "--checkpoint"]).arg(dir.join("render.ckpt"))
+        .status().unwrap();
+    assert!(status.success());
+    assert!(dir.join("render.ckpt").exists());
+
+    let status = rustracer().arg("progressive").arg(dir.join("scene.txt")).arg(dir.join("out.ppm"))
+        .args(["--resume", "--checkpoint"]).arg(dir.join("render.ckpt"))
+        .status().unwrap();
+    assert!(status.success());
+    assert_eq!(std::fs::read(dir.join("out.ppm")).unwrap(),
+               std::fs::read(dir.join("render.ppm")).unwrap());
+
+    let output = rustracer().arg("progressive").arg(dir.join("scene.txt")).arg(dir.join("out.ppm"))
+        .args(["--resume"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    std::fs::remove_dir_all(&dir).unwrap();
+}